@@ -0,0 +1,86 @@
+//! Compares the original line-at-a-time `search` with `search_lines`, which
+//! finds literals across the whole text before locating their lines, for
+//! one pattern and for several.
+//!
+//! Run with `cargo bench --bench literal`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use minigrep::matcher::Matcher;
+use minigrep::{search, search_lines, Config};
+
+const LINES: usize = 500_000;
+const RUNS: usize = 5;
+
+fn build_text() -> String {
+    let mut text = String::new();
+    for line in 0..LINES {
+        if line.is_multiple_of(9_973) {
+            text.push_str("a needle hidden in the haystack\n");
+        } else if line.is_multiple_of(7_919) {
+            text.push_str("some thread got lost in here too\n");
+        } else {
+            text.push_str("the quick brown fox jumps over the lazy dog 0123456789\n");
+        }
+    }
+    text
+}
+
+fn time<F: FnMut() -> usize>(mut f: F) -> (Duration, usize) {
+    let mut found = 0;
+    let elapsed = (0..RUNS)
+        .map(|_| {
+            let started = Instant::now();
+            found = black_box(f());
+            started.elapsed()
+        })
+        .min()
+        .unwrap();
+    (elapsed, found)
+}
+
+fn matcher(patterns: &[&str], regex: bool) -> Matcher {
+    Matcher::new(&Config {
+        patterns: patterns.iter().map(|p| p.to_string()).collect(),
+        regex,
+        ..Config::default()
+    })
+    .unwrap()
+}
+
+fn main() {
+    let text = build_text();
+    println!("text: {} lines, {} bytes\n", LINES, text.len());
+
+    let literal = matcher(&["needle"], false);
+    let several = matcher(&["needle", "thread", "lost", "haystack"], false);
+    let alternation = matcher(&["needle", "thread", "lost", "haystack"], true);
+
+    let results = [
+        ("search", time(|| search("needle", &text).len())),
+        (
+            "search_lines, literal",
+            time(|| search_lines(&literal, &text).len()),
+        ),
+        (
+            "search_lines, 4 literals",
+            time(|| search_lines(&several, &text).len()),
+        ),
+        (
+            "search_lines, 4-way regex",
+            time(|| search_lines(&alternation, &text).len()),
+        ),
+    ];
+
+    let baseline = results[0].1 .0;
+    for (name, (elapsed, found)) in results {
+        println!(
+            "{:26} {:>10.2?}  ({:.2}x, {} lines)",
+            name,
+            elapsed,
+            baseline.as_secs_f64() / elapsed.as_secs_f64(),
+            found
+        );
+    }
+}
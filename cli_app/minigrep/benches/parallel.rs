@@ -0,0 +1,87 @@
+//! Times the minigrep binary over a generated corpus of several thousand
+//! files with different numbers of worker threads.
+//!
+//! Run with `cargo bench --bench parallel`.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{self, Command, Stdio};
+use std::time::{Duration, Instant};
+
+const DIRS: usize = 40;
+const FILES_PER_DIR: usize = 100;
+const LINES_PER_FILE: usize = 200;
+const RUNS: usize = 3;
+
+fn build_corpus(root: &Path) {
+    for d in 0..DIRS {
+        let dir = root.join(format!("dir{:02}", d));
+        fs::create_dir_all(&dir).unwrap();
+
+        for f in 0..FILES_PER_DIR {
+            let mut text = String::new();
+            for l in 0..LINES_PER_FILE {
+                if (d * FILES_PER_DIR + f + l).is_multiple_of(997) {
+                    text.push_str("a needle hidden in the haystack\n");
+                } else {
+                    text.push_str("the quick brown fox jumps over the lazy dog 0123456789\n");
+                }
+            }
+            fs::write(dir.join(format!("file{:03}.txt", f)), text).unwrap();
+        }
+    }
+}
+
+fn time(args: &[&str], root: &Path, threads: usize) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let started = Instant::now();
+            let status = Command::new(env!("CARGO_BIN_EXE_minigrep"))
+                .arg("-j")
+                .arg(threads.to_string())
+                .args(args)
+                .arg(root)
+                .stdout(Stdio::null())
+                .status()
+                .unwrap();
+            assert!(status.success());
+            started.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let root = env::temp_dir().join(format!("minigrep-bench-{}", process::id()));
+    build_corpus(&root);
+    println!(
+        "corpus: {} files of {} lines\n",
+        DIRS * FILES_PER_DIR,
+        LINES_PER_FILE
+    );
+
+    for (name, args) in [
+        ("literal", &["needle"][..]),
+        ("regex", &["-E", r"n[aeiou]+dle\s+\w+"][..]),
+    ] {
+        let baseline = time(args, &root, 1);
+        for threads in [1, 2, 4, 8] {
+            let elapsed = if threads == 1 {
+                baseline
+            } else {
+                time(args, &root, threads)
+            };
+            println!(
+                "{:8} -j {}: {:>8.1?}  ({:.2}x)",
+                name,
+                threads,
+                elapsed,
+                baseline.as_secs_f64() / elapsed.as_secs_f64()
+            );
+        }
+        println!();
+    }
+
+    fs::remove_dir_all(&root).unwrap();
+}
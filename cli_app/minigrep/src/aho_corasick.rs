@@ -0,0 +1,239 @@
+//! Search for many literals at once with an Aho–Corasick automaton.
+//!
+//! The patterns are put in a trie whose states get failure links to the
+//! longest proper suffix that is also in the trie, so the haystack is
+//! scanned once no matter how many patterns there are.
+
+use std::collections::VecDeque;
+use std::ops::Range;
+
+const ROOT: usize = 0;
+
+struct State {
+    /// Outgoing edges of the trie, sorted by byte.
+    next: Vec<(u8, usize)>,
+    fail: usize,
+    depth: usize,
+    /// Lengths of the patterns that end here, including through failure
+    /// links.
+    matches: Vec<usize>,
+}
+
+impl State {
+    fn new(depth: usize) -> State {
+        State {
+            next: Vec::new(),
+            fail: ROOT,
+            depth,
+            matches: Vec::new(),
+        }
+    }
+
+    fn edge(&self, byte: u8) -> Option<usize> {
+        self.next
+            .binary_search_by_key(&byte, |&(b, _)| b)
+            .ok()
+            .map(|i| self.next[i].1)
+    }
+}
+
+pub struct AhoCorasick {
+    states: Vec<State>,
+    /// Transitions out of the root for every byte, which is where the
+    /// automaton spends most of its time.
+    root: [usize; 256],
+    ascii_case_insensitive: bool,
+    has_empty: bool,
+}
+
+impl AhoCorasick {
+    /// Build an automaton for `patterns`. With `ascii_case_insensitive`,
+    /// ASCII letters match either case.
+    pub fn new<P: AsRef<[u8]>>(patterns: &[P], ascii_case_insensitive: bool) -> AhoCorasick {
+        let mut states = vec![State::new(0)];
+        let mut has_empty = false;
+
+        for pattern in patterns {
+            let pattern = pattern.as_ref();
+            has_empty |= pattern.is_empty();
+
+            let mut state = ROOT;
+            for &byte in pattern {
+                let byte = if ascii_case_insensitive {
+                    byte.to_ascii_lowercase()
+                } else {
+                    byte
+                };
+                state = match states[state].edge(byte) {
+                    Some(next) => next,
+                    None => {
+                        let next = states.len();
+                        states.push(State::new(states[state].depth + 1));
+                        let edges = &mut states[state].next;
+                        let at = edges.partition_point(|&(b, _)| b < byte);
+                        edges.insert(at, (byte, next));
+                        next
+                    }
+                };
+            }
+            if !pattern.is_empty() {
+                states[state].matches.push(pattern.len());
+            }
+        }
+
+        // Breadth first, so that failure links always point to states whose
+        // own links are already known.
+        let mut queue: VecDeque<usize> = states[ROOT].next.iter().map(|&(_, s)| s).collect();
+        while let Some(state) = queue.pop_front() {
+            for (byte, child) in states[state].next.clone() {
+                let mut fail = states[state].fail;
+                let target = loop {
+                    if let Some(target) = states[fail].edge(byte) {
+                        break target;
+                    }
+                    if fail == ROOT {
+                        break ROOT;
+                    }
+                    fail = states[fail].fail;
+                };
+
+                states[child].fail = target;
+                let inherited = states[target].matches.clone();
+                states[child].matches.extend(inherited);
+                queue.push_back(child);
+            }
+        }
+
+        let mut root = [ROOT; 256];
+        for &(byte, state) in &states[ROOT].next {
+            root[byte as usize] = state;
+            if ascii_case_insensitive {
+                root[byte.to_ascii_uppercase() as usize] = state;
+            }
+        }
+
+        AhoCorasick {
+            states,
+            root,
+            ascii_case_insensitive,
+            has_empty,
+        }
+    }
+
+    fn step(&self, mut state: usize, byte: u8) -> usize {
+        let byte = if self.ascii_case_insensitive {
+            byte.to_ascii_lowercase()
+        } else {
+            byte
+        };
+
+        loop {
+            if state == ROOT {
+                return self.root[byte as usize];
+            }
+            if let Some(next) = self.states[state].edge(byte) {
+                return next;
+            }
+            state = self.states[state].fail;
+        }
+    }
+
+    pub fn is_match(&self, haystack: &[u8]) -> bool {
+        if self.has_empty {
+            return true;
+        }
+
+        let mut state = ROOT;
+        haystack.iter().any(|&byte| {
+            state = self.step(state, byte);
+            !self.states[state].matches.is_empty()
+        })
+    }
+
+    /// Find the leftmost match at or after `start`, preferring the longest
+    /// pattern among those starting at the same place.
+    pub fn find_at(&self, haystack: &[u8], start: usize) -> Option<Range<usize>> {
+        if self.has_empty {
+            return Some(start..start);
+        }
+
+        let mut state = ROOT;
+        let mut best: Option<Range<usize>> = None;
+
+        for (pos, &byte) in haystack.iter().enumerate().skip(start) {
+            state = self.step(state, byte);
+            let end = pos + 1;
+
+            for &len in &self.states[state].matches {
+                let candidate = end - len..end;
+                let better = match &best {
+                    None => true,
+                    Some(best) => {
+                        candidate.start < best.start
+                            || (candidate.start == best.start && candidate.end > best.end)
+                    }
+                };
+                if better {
+                    best = Some(candidate);
+                }
+            }
+
+            // Stop once no partial match still in progress could start at
+            // or before the best match found so far.
+            if let Some(best) = &best {
+                if end - self.states[state].depth > best.start {
+                    break;
+                }
+            }
+        }
+
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find<'h>(patterns: &[&str], haystack: &'h str) -> Option<&'h str> {
+        AhoCorasick::new(patterns, false)
+            .find_at(haystack.as_bytes(), 0)
+            .map(|range| &haystack[range])
+    }
+
+    #[test]
+    fn finds_leftmost_longest() {
+        assert_eq!(Some("he"), find(&["he", "she", "his", "hers"], "ahe shers"));
+        assert_eq!(Some("she"), find(&["he", "she", "hers"], "ushers"));
+        assert_eq!(Some("abcd"), find(&["bc", "abcd"], "xabcd"));
+        assert_eq!(Some("abc"), find(&["ab", "abc"], "abcd"));
+        assert_eq!(None, find(&["foo", "bar"], "fobaz"));
+        assert_eq!(None, find(&[], "anything"));
+    }
+
+    #[test]
+    fn matches_ascii_case_insensitively() {
+        let ac = AhoCorasick::new(&["rust", "Tape"], true);
+        assert!(ac.is_match(b"DUCT TAPE"));
+        assert_eq!(Some(1..5), ac.find_at(b"TRUST", 0));
+        assert!(!AhoCorasick::new(&["rust"], false).is_match(b"RUST"));
+    }
+
+    #[test]
+    fn agrees_with_naive_search() {
+        let patterns = ["ab", "ba", "aab", "bbb", "abab"];
+        let haystack = b"aababbbabaabbbab";
+        let ac = AhoCorasick::new(&patterns, false);
+
+        for start in 0..haystack.len() {
+            let naive = (start..haystack.len()).find_map(|at| {
+                patterns
+                    .iter()
+                    .filter(|p| haystack[at..].starts_with(p.as_bytes()))
+                    .map(|p| at..at + p.len())
+                    .max_by_key(|range| range.end)
+            });
+            assert_eq!(naive, ac.find_at(haystack, start), "start {}", start);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Compressed files and archives, recognized by their first bytes rather
+//! than their names.
+//!
+//! gzip, and the DEFLATE compression inside zip archives, are decoded here.
+//! bzip2, xz and zstd files are piped through the `bzip2`, `xz` and `zstd`
+//! programs, which have to be installed to search them.
+
+mod inflate;
+mod tar;
+mod zip;
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+pub use inflate::{GzDecoder, Inflate};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+    Tar,
+    Zip,
+}
+
+impl Format {
+    /// The format of input starting with `head`, if it is one of these.
+    pub fn detect(head: &[u8]) -> Option<Format> {
+        if head.starts_with(&[0x1f, 0x8b]) {
+            Some(Format::Gzip)
+        } else if head.starts_with(b"BZh") {
+            Some(Format::Bzip2)
+        } else if head.starts_with(b"\xfd7zXZ\0") {
+            Some(Format::Xz)
+        } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Format::Zstd)
+        } else if head.starts_with(b"PK\x03\x04") {
+            Some(Format::Zip)
+        } else if head.get(257..262) == Some(b"ustar") {
+            Some(Format::Tar)
+        } else {
+            None
+        }
+    }
+
+    /// The program that decompresses this format, if it needs one.
+    fn program(self) -> Option<&'static str> {
+        match self {
+            Format::Bzip2 => Some("bzip2"),
+            Format::Xz => Some("xz"),
+            Format::Zstd => Some("zstd"),
+            _ => None,
+        }
+    }
+}
+
+/// Call `f` with the decompressed contents of `reader`, which is in a
+/// format that `Format::program` decompresses.
+///
+/// `reader` is copied into the program on another thread while `f` reads
+/// what it writes. If `f` stops before the end, the program is killed.
+pub fn decompress<T, F>(format: Format, reader: &mut (dyn BufRead + Send), f: F) -> io::Result<T>
+where
+    F: FnOnce(&mut (dyn BufRead + Send)) -> io::Result<T>,
+{
+    let program = format.program().expect("format needs no program");
+    let mut child = Command::new(program)
+        .arg("-dc")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("cannot run {} to decompress: {}", program, e),
+            )
+        })?;
+    let mut stdin = child.stdin.take().unwrap();
+    let mut output = BufReader::new(child.stdout.take().unwrap());
+
+    thread::scope(|scope| {
+        let copier = scope.spawn(move || {
+            io::copy(reader, &mut stdin)?;
+            stdin.flush()
+        });
+
+        let result = f(&mut output);
+        let finished = result.is_ok() && output.fill_buf().is_ok_and(|rest| rest.is_empty());
+        if !finished {
+            // It may have exited already.
+            let _ = child.kill();
+        }
+        drop(output);
+        let status = child.wait()?;
+        let copied = copier.join().unwrap();
+        if !finished {
+            return result;
+        }
+
+        if !status.success() {
+            let mut message = String::new();
+            child.stderr.take().unwrap().read_to_string(&mut message)?;
+            let message = message.trim();
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                if message.is_empty() {
+                    format!("{} failed: {}", program, status)
+                } else {
+                    message.to_string()
+                },
+            ));
+        }
+        copied?;
+        result
+    })
+}
+
+/// Call `f` with the path and contents of each regular file in a tar or zip
+/// archive, in the order they are stored.
+pub fn entries<F>(format: Format, reader: &mut (dyn BufRead + Send), f: F) -> io::Result<()>
+where
+    F: FnMut(&str, &mut (dyn BufRead + Send)) -> io::Result<()>,
+{
+    match format {
+        Format::Tar => tar::entries(reader, f),
+        Format::Zip => zip::entries(reader, f),
+        _ => panic!("{:?} is not an archive", format),
+    }
+}
+
+/// Read and throw away the rest of `reader`.
+fn skip<R: Read>(mut reader: R) -> io::Result<()> {
+    io::copy(&mut reader, &mut io::sink())?;
+    Ok(())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_formats_by_magic_bytes() {
+        assert_eq!(Some(Format::Gzip), Format::detect(b"\x1f\x8b\x08\0"));
+        assert_eq!(Some(Format::Bzip2), Format::detect(b"BZh91AY&SY"));
+        assert_eq!(Some(Format::Xz), Format::detect(b"\xfd7zXZ\0\0\x04"));
+        assert_eq!(Some(Format::Zstd), Format::detect(b"\x28\xb5\x2f\xfd\x04"));
+        assert_eq!(Some(Format::Zip), Format::detect(b"PK\x03\x04\x14\0"));
+
+        let mut tar = vec![0; 512];
+        tar[257..263].copy_from_slice(b"ustar\0");
+        assert_eq!(Some(Format::Tar), Format::detect(&tar));
+
+        assert_eq!(None, Format::detect(b"plain text"));
+        assert_eq!(None, Format::detect(b""));
+    }
+}
@@ -0,0 +1,524 @@
+//! DEFLATE decompression (RFC 1951), the format inside gzip files and most
+//! zip archives, and the gzip wrapper around it (RFC 1952).
+//!
+//! Output is produced as it is read, keeping only the last 32 KiB that
+//! back-references may point into.
+
+use std::io::{self, BufRead, Read};
+
+use super::invalid;
+
+/// How far back a back-reference may reach.
+const WINDOW: usize = 32 * 1024;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order in which the lengths of the code length code are stored.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Continue a CRC-32 over `bytes`; start from `!0` and invert the result.
+pub fn crc32(crc: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(crc, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Reads the input least significant bit first, never taking a byte from
+/// the underlying reader before one of its bits is needed, so whatever
+/// follows the compressed data is left unread.
+struct Bits<R> {
+    inner: R,
+    buffer: u32,
+    count: u32,
+}
+
+impl<R: BufRead> Bits<R> {
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        while self.count < n {
+            let byte = match self.inner.fill_buf()?.first() {
+                Some(&byte) => byte,
+                None => return Err(io::ErrorKind::UnexpectedEof.into()),
+            };
+            self.inner.consume(1);
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+
+        let value = self.buffer & ((1 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.bits(8)? as u8)
+    }
+
+    /// Skip to the next byte boundary.
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+/// A canonical Huffman code, decoded a bit at a time.
+struct Huffman {
+    /// How many codes there are of each length.
+    counts: [u16; 16],
+    /// The symbols ordered by code.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        Huffman { counts, symbols }
+    }
+
+    fn decode<R: BufRead>(&self, bits: &mut Bits<R>) -> io::Result<u16> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+
+        for &count in &self.counts[1..] {
+            code |= bits.bits(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(invalid("invalid Huffman code"))
+    }
+}
+
+enum State {
+    /// At the start of a block, or past the last one.
+    Header,
+    Stored(usize),
+    Compressed {
+        literals: Huffman,
+        distances: Huffman,
+    },
+    Done,
+}
+
+/// Decompresses a raw DEFLATE stream.
+pub struct Inflate<R> {
+    bits: Bits<R>,
+    state: State,
+    last_block: bool,
+    /// Recent output: everything from `pos` on is yet to be read, and at
+    /// least a window's worth before it is kept for back-references.
+    out: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: BufRead> Inflate<R> {
+    pub fn new(inner: R) -> Inflate<R> {
+        Inflate {
+            bits: Bits {
+                inner,
+                buffer: 0,
+                count: 0,
+            },
+            state: State::Header,
+            last_block: false,
+            out: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// Get ready for another stream right after the one that ended.
+    fn reset(&mut self) {
+        self.state = State::Header;
+        self.last_block = false;
+        self.out.clear();
+        self.pos = 0;
+    }
+
+    /// Decode until there is some new output or the stream ends.
+    fn decode(&mut self) -> io::Result<()> {
+        let start = self.out.len();
+
+        while self.out.len() == start {
+            match &self.state {
+                State::Header if self.last_block => {
+                    self.bits.align();
+                    self.state = State::Done;
+                }
+                State::Header => self.header()?,
+                &State::Stored(remaining) => {
+                    let n = remaining.min(WINDOW);
+                    for _ in 0..n {
+                        let byte = self.bits.byte()?;
+                        self.out.push(byte);
+                    }
+                    self.state = if n == remaining {
+                        State::Header
+                    } else {
+                        State::Stored(remaining - n)
+                    };
+                }
+                State::Compressed {
+                    literals,
+                    distances,
+                } => {
+                    if codes(&mut self.bits, &mut self.out, literals, distances)? {
+                        self.state = State::Header;
+                    }
+                }
+                State::Done => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn header(&mut self) -> io::Result<()> {
+        self.last_block = self.bits.bits(1)? == 1;
+        self.state = match self.bits.bits(2)? {
+            0 => {
+                self.bits.align();
+                let len = self.bits.bits(16)?;
+                let complement = self.bits.bits(16)?;
+                if len != !complement & 0xffff {
+                    return Err(invalid("corrupt stored block"));
+                }
+                State::Stored(len as usize)
+            }
+            1 => {
+                let mut lengths = [8u8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                State::Compressed {
+                    literals: Huffman::new(&lengths),
+                    distances: Huffman::new(&[5; 30]),
+                }
+            }
+            2 => self.dynamic_tables()?,
+            _ => return Err(invalid("invalid block type")),
+        };
+        Ok(())
+    }
+
+    fn dynamic_tables(&mut self) -> io::Result<State> {
+        let literals = self.bits.bits(5)? as usize + 257;
+        let distances = self.bits.bits(5)? as usize + 1;
+        let code_lengths = self.bits.bits(4)? as usize + 4;
+
+        let mut lengths = [0u8; 19];
+        for &i in &CODE_LENGTH_ORDER[..code_lengths] {
+            lengths[i] = self.bits.bits(3)? as u8;
+        }
+        let code = Huffman::new(&lengths);
+
+        let mut lengths = Vec::with_capacity(literals + distances);
+        while lengths.len() < literals + distances {
+            let (length, repeat) = match code.decode(&mut self.bits)? {
+                symbol @ 0..=15 => (symbol as u8, 1),
+                16 => {
+                    let previous = *lengths
+                        .last()
+                        .ok_or_else(|| invalid("repeat with no previous length"))?;
+                    (previous, 3 + self.bits.bits(2)?)
+                }
+                17 => (0, 3 + self.bits.bits(3)?),
+                _ => (0, 11 + self.bits.bits(7)?),
+            };
+            lengths.extend((0..repeat).map(|_| length));
+        }
+        if lengths.len() > literals + distances {
+            return Err(invalid("too many code lengths"));
+        }
+
+        Ok(State::Compressed {
+            literals: Huffman::new(&lengths[..literals]),
+            distances: Huffman::new(&lengths[literals..]),
+        })
+    }
+}
+
+/// Decode up to a window's worth of output from a compressed block,
+/// returning whether the block ended.
+fn codes<R: BufRead>(
+    bits: &mut Bits<R>,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> io::Result<bool> {
+    let limit = out.len() + WINDOW;
+
+    while out.len() < limit {
+        let symbol = literals.decode(bits)? as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(true);
+        }
+
+        let symbol = symbol - 257;
+        if symbol >= LENGTH_BASE.len() {
+            return Err(invalid("invalid length code"));
+        }
+        let length =
+            LENGTH_BASE[symbol] as usize + bits.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+        let symbol = distances.decode(bits)? as usize;
+        if symbol >= DISTANCE_BASE.len() {
+            return Err(invalid("invalid distance code"));
+        }
+        let distance =
+            DISTANCE_BASE[symbol] as usize + bits.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+        if distance > out.len() {
+            return Err(invalid("distance too far back"));
+        }
+
+        // The copy may overlap what it produces, so go byte by byte.
+        let from = out.len() - distance;
+        for i in 0..length {
+            out.push(out[from + i]);
+        }
+    }
+
+    Ok(false)
+}
+
+impl<R: BufRead> Read for Inflate<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.out.len() {
+            // Forget what is no longer needed for back-references.
+            if self.out.len() > 2 * WINDOW {
+                let forget = self.out.len() - WINDOW;
+                self.out.drain(..forget);
+                self.pos -= forget;
+            }
+            self.decode()?;
+        }
+
+        let n = buf.len().min(self.out.len() - self.pos);
+        buf[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Decompresses a gzip file, which may hold several members one after the
+/// other as `cat a.gz b.gz` makes.
+pub struct GzDecoder<R> {
+    inflate: Inflate<R>,
+    /// Whether the header of the current member has been read.
+    started: bool,
+    done: bool,
+    crc: u32,
+    size: u32,
+}
+
+impl<R: BufRead> GzDecoder<R> {
+    pub fn new(inner: R) -> GzDecoder<R> {
+        GzDecoder {
+            inflate: Inflate::new(inner),
+            started: false,
+            done: false,
+            crc: !0,
+            size: 0,
+        }
+    }
+
+    fn header(&mut self) -> io::Result<()> {
+        let bits = &mut self.inflate.bits;
+        let mut fixed = [0; 10];
+        for byte in &mut fixed {
+            *byte = bits.byte()?;
+        }
+        if fixed[..3] != [0x1f, 0x8b, 8] {
+            return Err(invalid("not a gzip file"));
+        }
+
+        let flags = fixed[3];
+        if flags & 4 != 0 {
+            let len = bits.bits(16)?;
+            for _ in 0..len {
+                bits.byte()?;
+            }
+        }
+        // The original file name and a comment, both zero-terminated.
+        for flag in [8, 16] {
+            if flags & flag != 0 {
+                while bits.byte()? != 0 {}
+            }
+        }
+        if flags & 2 != 0 {
+            bits.bits(16)?;
+        }
+        Ok(())
+    }
+
+    /// Check the trailer of a member and return whether another follows.
+    fn trailer(&mut self) -> io::Result<bool> {
+        let bits = &mut self.inflate.bits;
+        let crc = bits.bits(16)? | bits.bits(16)? << 16;
+        let size = bits.bits(16)? | bits.bits(16)? << 16;
+        if crc != !self.crc || size != self.size {
+            return Err(invalid("gzip checksum mismatch"));
+        }
+
+        Ok(bits.inner.fill_buf()?.starts_with(&[0x1f, 0x8b]))
+    }
+}
+
+impl<R: BufRead> Read for GzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while !self.done {
+            if !self.started {
+                self.header()?;
+                self.started = true;
+            }
+
+            let n = self.inflate.read(buf)?;
+            if n > 0 {
+                self.crc = crc32(self.crc, &buf[..n]);
+                self.size = self.size.wrapping_add(n as u32);
+                return Ok(n);
+            }
+
+            if !self.trailer()? {
+                self.done = true;
+                break;
+            }
+            self.inflate.reset();
+            self.started = false;
+            self.crc = !0;
+            self.size = 0;
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inflate(compressed: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        Inflate::new(compressed).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn inflates_each_kind_of_block() {
+        let stored = [1, 5, 0, 0xfa, 0xff, b'h', b'e', b'l', b'l', b'o'];
+        assert_eq!(b"hello", &inflate(&stored).unwrap()[..]);
+
+        // Fixed codes, with a back-reference to "rust".
+        let fixed = [
+            0x0b, 0x29, 0x2a, 0x2d, 0x2e, 0x51, 0x00, 0x11, 0x3a, 0x60, 0x52, 0xa1, 0x04, 0x44,
+            0x16, 0x2b, 0x54, 0xe6, 0x97, 0xea, 0x71, 0x01, 0x00,
+        ];
+        assert_eq!(
+            b"Trust rust, rust trusts you.\n",
+            &inflate(&fixed).unwrap()[..]
+        );
+
+        // Dynamic codes.
+        let poem = [
+            0x2d, 0x8e, 0xb1, 0x0e, 0x83, 0x30, 0x0c, 0x44, 0xf7, 0x7c, 0xc5, 0x31, 0xb1, 0xd0,
+            0xfe, 0x02, 0xea, 0xd6, 0xee, 0x48, 0x9d, 0x9d, 0xc6, 0x40, 0x44, 0x88, 0x2b, 0x13,
+            0x8a, 0xf2, 0xf7, 0x4d, 0x68, 0x37, 0xeb, 0xee, 0xe9, 0xf9, 0x1e, 0xed, 0x8a, 0x28,
+            0x56, 0x5c, 0x6e, 0xf0, 0x9c, 0x05, 0xa4, 0x8c, 0x2c, 0x7b, 0x6f, 0x6e, 0xbf, 0xe3,
+            0x5f, 0x76, 0x48, 0x22, 0xbd, 0x19, 0x66, 0x8e, 0x48, 0x33, 0x2b, 0xb7, 0x1b, 0x08,
+            0x6f, 0xf2, 0x0a, 0x19, 0xb1, 0x6f, 0xb8, 0xc0, 0x49, 0x6c, 0x13, 0x12, 0x87, 0xd0,
+            0x54, 0x2e, 0xb7, 0x0e, 0x96, 0xa2, 0xdf, 0xe6, 0x52, 0x77, 0xa7, 0x6a, 0x89, 0x72,
+            0x5c, 0x8d, 0xb9, 0xcb, 0x01, 0xa7, 0x4c, 0x9a, 0x8b, 0x14, 0x96, 0xb1, 0xc9, 0xca,
+            0xe7, 0x82, 0xb3, 0x7a, 0xef, 0x36, 0xf8, 0x57, 0x87, 0xe0, 0x17, 0x2e, 0x3f, 0x46,
+            0x95, 0xc9, 0x0c, 0x72, 0x8a, 0xab, 0x45, 0x11, 0x69, 0xe5, 0x3a, 0xa2, 0x10, 0x1f,
+            0x0e, 0x12, 0x27, 0x38, 0xca, 0x15, 0xa1, 0x08, 0x72, 0xab, 0x57, 0x5f, 0x22, 0x2b,
+            0x53, 0xf3, 0x05,
+        ];
+        assert_eq!(
+            include_bytes!("../../poem.txt"),
+            &inflate(&poem).unwrap()[..]
+        );
+
+        assert!(inflate(&fixed[..10]).is_err());
+        assert!(inflate(&[1, 5, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn reads_gzip_members_and_stops_after_them() {
+        let member = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0xcf, 0x4b, 0xe5,
+            0x02, 0x00, 0x9f, 0xa8, 0x17, 0xf8, 0x04, 0x00, 0x00, 0x00,
+        ];
+        let mut input = [&member[..], &member[..], b"rest"].concat();
+        let mut reader = &input[..];
+        let mut out = String::new();
+        GzDecoder::new(&mut reader)
+            .read_to_string(&mut out)
+            .unwrap();
+        assert_eq!("one\none\n", out);
+        assert_eq!(b"rest", reader);
+
+        // A different checksum.
+        input[16] ^= 1;
+        let result = GzDecoder::new(&input[..]).read_to_end(&mut Vec::new());
+        assert_eq!(io::ErrorKind::InvalidData, result.unwrap_err().kind());
+    }
+}
@@ -0,0 +1,159 @@
+//! Reading the files in a tar archive as they stream past.
+//!
+//! Only regular files are passed on. Long names are taken from GNU `L`
+//! records and from the `path` of pax extended headers.
+
+use std::io::{self, BufRead, Read};
+use std::str;
+
+use super::{invalid, skip};
+
+const BLOCK: u64 = 512;
+
+pub fn entries<F>(reader: &mut (dyn BufRead + Send), mut f: F) -> io::Result<()>
+where
+    F: FnMut(&str, &mut (dyn BufRead + Send)) -> io::Result<()>,
+{
+    let mut long_name = None;
+
+    loop {
+        let mut header = [0; BLOCK as usize];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            // Some writers leave out the two zero blocks at the end.
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        if header.iter().all(|&b| b == 0) {
+            return Ok(());
+        }
+
+        let size = number(&header[124..136])?;
+        let name = long_name.take().unwrap_or_else(|| {
+            let name = text(&header[..100]);
+            let prefix = text(&header[345..500]);
+            if &header[257..262] == b"ustar" && !prefix.is_empty() {
+                format!("{}/{}", prefix, name)
+            } else {
+                name
+            }
+        });
+
+        let mut data = (&mut *reader).take(size);
+        match header[156] {
+            b'0' | b'\0' | b'7' => f(&name, &mut data)?,
+            b'L' => {
+                let mut name = Vec::new();
+                data.read_to_end(&mut name)?;
+                long_name = Some(text(&name));
+            }
+            b'x' => {
+                let mut records = Vec::new();
+                data.read_to_end(&mut records)?;
+                long_name = pax_path(&records);
+            }
+            _ => {}
+        }
+        skip(data)?;
+        skip((&mut *reader).take((BLOCK - size % BLOCK) % BLOCK))?;
+    }
+}
+
+/// A string field, which ends at its first NUL if it has one.
+fn text(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// A number field: octal digits, or for large numbers, big-endian binary
+/// after a byte with its top bit set.
+fn number(field: &[u8]) -> io::Result<u64> {
+    if field[0] & 0x80 != 0 {
+        return Ok(field[1..].iter().fold(0, |n, &b| n << 8 | u64::from(b)));
+    }
+
+    let digits = text(field);
+    let digits = digits.trim_matches(' ');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).map_err(|_| invalid("invalid number in tar header"))
+}
+
+/// The `path` in pax extended header records, which look like
+/// `30 path=some/long/file/name\n`, the number being the record's length.
+fn pax_path(mut records: &[u8]) -> Option<String> {
+    let mut path = None;
+
+    while let Some(space) = records.iter().position(|&b| b == b' ') {
+        let len: usize = str::from_utf8(&records[..space]).ok()?.parse().ok()?;
+        let record = records.get(space + 1..len)?.strip_suffix(b"\n")?;
+        if let Some(value) = record.strip_prefix(b"path=") {
+            path = Some(String::from_utf8_lossy(value).into_owned());
+        }
+        records = &records[len..];
+    }
+
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(name: &str, size: usize, kind: u8) -> Vec<u8> {
+        let mut header = vec![0; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header
+    }
+
+    fn entry(archive: &mut Vec<u8>, name: &str, kind: u8, data: &[u8]) {
+        archive.extend(header(name, data.len(), kind));
+        archive.extend(data);
+        archive.resize(archive.len().next_multiple_of(512), 0);
+    }
+
+    #[test]
+    fn lists_regular_files() {
+        let long = format!("{}/file.txt", "deep".repeat(40));
+        let mut archive = Vec::new();
+        entry(&mut archive, "dir/", b'5', b"");
+        entry(&mut archive, "dir/a.txt", b'0', b"first\n");
+        entry(&mut archive, "././@LongLink", b'L', long.as_bytes());
+        entry(&mut archive, "truncated", b'0', &[b'x'; 600]);
+        let pax = "21 path=from/pax.txt\n";
+        entry(&mut archive, "PaxHeader", b'x', pax.as_bytes());
+        entry(&mut archive, "ignored", b'0', b"last");
+        archive.extend([0; 1024]);
+
+        let mut found = Vec::new();
+        entries(&mut &archive[..], |name, data| {
+            // Leave some unread, which has to be skipped.
+            let mut start = [0; 4];
+            data.read_exact(&mut start)?;
+            found.push((name.to_string(), start.to_vec()));
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                ("dir/a.txt".to_string(), b"firs".to_vec()),
+                (long, b"xxxx".to_vec()),
+                ("from/pax.txt".to_string(), b"last".to_vec()),
+            ],
+            found
+        );
+    }
+
+    #[test]
+    fn reads_numbers() {
+        assert_eq!(0o644, number(b"0000644\0").unwrap());
+        assert_eq!(0o17, number(b"   17 \0\0").unwrap());
+        assert_eq!(1 << 40, number(b"\x80\0\0\0\0\0\x01\0\0\0\0\0").unwrap());
+        assert!(number(b"9\0").is_err());
+    }
+}
@@ -0,0 +1,157 @@
+//! Reading the files in a zip archive from front to back, by their local
+//! headers, without seeking to the central directory at the end.
+//!
+//! Files that are stored or deflated are passed on; encrypted files and
+//! other compression methods are skipped.
+
+use std::io::{self, BufRead, BufReader, Read};
+
+use super::{invalid, skip, Inflate};
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR: u32 = 0x0807_4b50;
+
+const ENCRYPTED: u16 = 1;
+/// The sizes and checksum follow the data instead of being in the header.
+const STREAMED: u16 = 1 << 3;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+pub fn entries<F>(reader: &mut (dyn BufRead + Send), mut f: F) -> io::Result<()>
+where
+    F: FnMut(&str, &mut (dyn BufRead + Send)) -> io::Result<()>,
+{
+    loop {
+        let mut header = [0; 30];
+        match reader.read_exact(&mut header[..4]) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        // The central directory, or whatever else follows the files.
+        if u32_at(&header, 0) != LOCAL_HEADER {
+            return Ok(());
+        }
+        reader.read_exact(&mut header[4..])?;
+
+        let flags = u16_at(&header, 6);
+        let method = u16_at(&header, 8);
+        let mut size = u64::from(u32_at(&header, 18));
+        let mut name = vec![0; usize::from(u16_at(&header, 26))];
+        reader.read_exact(&mut name)?;
+        let mut extra = vec![0; usize::from(u16_at(&header, 28))];
+        reader.read_exact(&mut extra)?;
+        let name = String::from_utf8_lossy(&name);
+
+        if size == u64::from(u32::MAX) {
+            size = zip64_size(&extra).ok_or_else(|| invalid("missing zip64 sizes"))?;
+        }
+
+        let is_file = !name.ends_with('/') && flags & ENCRYPTED == 0;
+        if flags & STREAMED == 0 {
+            let mut data = (&mut *reader).take(size);
+            match method {
+                STORED if is_file => f(&name, &mut data)?,
+                DEFLATED if is_file => f(&name, &mut BufReader::new(Inflate::new(&mut data)))?,
+                _ => {}
+            }
+            skip(data)?;
+            continue;
+        }
+
+        // Without its size, the data only ends where the compressed stream
+        // says it does.
+        if method != DEFLATED || flags & ENCRYPTED != 0 {
+            return Err(invalid("cannot find the end of a zip entry"));
+        }
+        let mut data = BufReader::new(Inflate::new(&mut *reader));
+        if is_file {
+            f(&name, &mut data)?;
+        }
+        skip(data)?;
+
+        let mut descriptor = [0; 16];
+        reader.read_exact(&mut descriptor[..12])?;
+        if u32_at(&descriptor, 0) == DATA_DESCRIPTOR {
+            reader.read_exact(&mut descriptor[12..])?;
+        }
+    }
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+/// The compressed size from the zip64 extra field, for files of 4 GiB or
+/// more.
+fn zip64_size(mut extra: &[u8]) -> Option<u64> {
+    while extra.len() >= 4 {
+        let id = u16_at(extra, 0);
+        let len = usize::from(u16_at(extra, 2));
+        let field = extra.get(4..4 + len)?;
+        if id == 1 {
+            // The uncompressed size comes first.
+            return Some(u64::from_le_bytes(field.get(8..16)?.try_into().unwrap()));
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local_header(name: &str, flags: u16, method: u16, size: u32) -> Vec<u8> {
+        let mut header = LOCAL_HEADER.to_le_bytes().to_vec();
+        header.extend([20, 0]);
+        header.extend(flags.to_le_bytes());
+        header.extend(method.to_le_bytes());
+        header.extend([0; 8]);
+        header.extend(size.to_le_bytes());
+        header.extend(size.to_le_bytes());
+        header.extend((name.len() as u16).to_le_bytes());
+        header.extend([0, 0]);
+        header.extend(name.as_bytes());
+        header
+    }
+
+    #[test]
+    fn lists_stored_and_deflated_files() {
+        let mut archive = local_header("dir/", 0, STORED, 0);
+        archive.extend(local_header("dir/stored.txt", 0, STORED, 6));
+        archive.extend(b"plain\n");
+        // A stored DEFLATE block, with the sizes after it.
+        archive.extend(local_header("dir/deflated.txt", STREAMED, DEFLATED, 0));
+        archive.extend([1, 5, 0, 0xfa, 0xff, b'h', b'e', b'l', b'l', b'o']);
+        archive.extend(DATA_DESCRIPTOR.to_le_bytes());
+        archive.extend([0; 12]);
+        archive.extend(local_header("last.txt", 0, STORED, 4));
+        archive.extend(b"last");
+        archive.extend(0x0201_4b50u32.to_le_bytes());
+        archive.extend([0; 42]);
+
+        let mut found = Vec::new();
+        entries(&mut &archive[..], |name, data| {
+            let mut start = [0; 4];
+            data.read_exact(&mut start)?;
+            found.push((name.to_string(), start.to_vec()));
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                ("dir/stored.txt".to_string(), b"plai".to_vec()),
+                ("dir/deflated.txt".to_string(), b"hell".to_vec()),
+                ("last.txt".to_string(), b"last".to_vec()),
+            ],
+            found
+        );
+    }
+}
@@ -0,0 +1,632 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use crate::encoding::{self, Encoding};
+use crate::fields::Format;
+use crate::printer::Color;
+use crate::replace::Replacement;
+use crate::Config;
+
+pub const USAGE: &str = "Usage: minigrep [OPTIONS] QUERY [PATH...]";
+
+pub const HELP: &str = "\
+Search for QUERY in each PATH. Directories are searched recursively and
+standard input is searched when PATH is `-` or missing.
+
+Usage: minigrep [OPTIONS] QUERY [PATH...]
+       minigrep [OPTIONS] -e PATTERN... [PATH...]
+       minigrep index [DIR]
+
+Lines matching any of the patterns are printed. With -e or -f, QUERY is
+not given and every positional argument is a path.
+
+`minigrep index` indexes the files below DIR, or the current directory, in
+DIR/.minigrep-index, and when run again only reads the files that changed.
+With --index, searches below DIR then skip the files that the index shows
+cannot match. `index` is only taken as the command on its own or before a
+directory, so `minigrep index FILE` still searches FILE for the word index;
+-e index always does.
+
+Options:
+  -e, --regexp PATTERN      search for PATTERN; may be given several times
+  -f, --file FILE           search for each line of FILE
+  -E, --extended-regexp     treat the patterns as regular expressions
+      --fuzzy NUM           also match the query with up to NUM typos
+                            (characters inserted, deleted or replaced)
+                            and show how many each line needed
+  -i, --ignore-case         match case-insensitively
+  -v, --invert-match        print the lines that do not match
+      --encoding NAME       read text as utf-8, utf-16le, utf-16be, latin-1
+                            or windows-1252; by default, as UTF-8 unless
+                            a byte order mark shows UTF-16
+  -U, --multiline           let matches span lines, printing every line a
+                            match touches; \n matches a newline, also in
+                            a literal pattern, and ^ and $ match at the
+                            start and end of each line; each file is read
+                            whole, and files over 256 MiB are skipped
+  -w, --word-regexp         only match whole words
+  -n, --line-number         prefix each line with its line number
+  -c, --count               print the number of matching lines per file
+  -l, --files-with-matches  print only the names of files with a match
+  -q, --quiet               print nothing, and stop at the first match;
+                            only the exit status tells what was found
+  -s, --no-messages         leave out the messages about files that
+                            cannot be read
+  -A, --after-context NUM   print NUM lines after each match
+  -B, --before-context NUM  print NUM lines before each match
+  -C, --context NUM         print NUM lines before and after each match
+      --color[=WHEN]        highlight matches: auto (the default), always
+                            or never; auto only colors output to a terminal
+      --json                print results as JSON Lines, one object per
+                            match, in a stable format
+      --replace TEXT        print lines with each match replaced by TEXT, in
+                            which $1 or ${1} stands for what group 1 of
+                            the pattern matched, $0 for the whole match
+                            and $$ for a dollar sign; there is no short
+                            form, since -r means recursive in grep, and
+                            directories are always searched recursively
+      --in-place            write the replacements back to the files
+      --dry-run             print the changes --in-place would make as a
+                            diff, without making them
+      --archives            also search the files inside tar and zip
+                            archives, naming them ARCHIVE:PATH
+      --watch               keep running, and search the lines added to
+                            the files, and new files, as they are written
+      --csv                 read CSV with a header row, and search only
+                            the column named by --field
+      --jsonl               read JSON Lines, and search only the value at
+                            the path given by --field, as in user.name
+                            or items.0.id
+      --field NAME          the column or path to search; whole records
+                            are printed
+      --interactive         search as the query is typed, in a terminal
+                            UI; every argument is a path, the current
+                            directory if none, -e gives a first query,
+                            and Enter opens the selected line in
+                            $VISUAL or $EDITOR
+      --index               only read the files that an index made by
+                            `minigrep index` shows may match; -c leaves
+                            out the others
+  -j, --threads NUM         search NUM files at a time
+  -h, --help                print this help
+  -V, --version             print the version
+
+Files compressed with gzip, bzip2, xz or zstd are decompressed as they are
+searched; the last three need the bzip2, xz or zstd program to be installed.
+
+Setting the CASE_INSENSITIVE environment variable has the same effect as -i.
+
+The exit status is 0 if a line was selected, 1 if none was and 2 if there
+was an error, such as a file that could not be read; the other files are
+still searched. With -q, a match gives 0 even if there was an error.
+--watch and --interactive give 0 when they stop, or 2 on an error.
+";
+
+pub enum Command {
+    Search(Config),
+    /// Index the directory, for `minigrep index`.
+    Index(String),
+    Help,
+    Version,
+}
+
+/// Parse the command line, without the program name.
+///
+/// Short flags can be combined (`-in`) and options may come before or
+/// after the positional arguments; everything after `--` is positional.
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let args: Vec<String> = args.into_iter().collect();
+    // `minigrep index FILE` searched FILE for "index" before the command
+    // existed, so only a directory, or nothing, makes it the command.
+    if let [command, rest @ ..] = &args[..] {
+        if command == "index" && rest.first().is_none_or(|dir| Path::new(dir).is_dir()) {
+            return match rest {
+                [] => Ok(Command::Index(".".to_string())),
+                [dir] => Ok(Command::Index(dir.clone())),
+                _ => Err("index takes a single directory".into()),
+            };
+        }
+    }
+    let mut args = args.into_iter();
+
+    let mut config = Config::default();
+    let mut ignore_case = false;
+    let mut patterns = None;
+    let mut positional = Vec::new();
+    let (mut csv, mut jsonl, mut field) = (false, false, None);
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            positional.extend(args.by_ref());
+            break;
+        }
+
+        let (flags, value) = if let Some(long) = arg.strip_prefix("--") {
+            match long.split_once('=') {
+                Some((name, value)) => (vec![name.to_string()], Some(value.to_string())),
+                None => (vec![long.to_string()], None),
+            }
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            let mut flags = Vec::new();
+            let mut value = None;
+            for (i, c) in arg.char_indices().skip(1) {
+                flags.push(c.to_string());
+                // The rest of `-j4` is the value of `-j`.
+                if takes_value(&c.to_string()) && i + 1 < arg.len() {
+                    value = Some(arg[i + 1..].to_string());
+                    break;
+                }
+            }
+            (flags, value)
+        } else {
+            positional.push(arg);
+            continue;
+        };
+
+        for flag in flags {
+            // The value of `--color` is optional, so it can only be given
+            // after an equals sign.
+            if flag == "color" {
+                config.color = match value.as_deref() {
+                    None | Some("auto") => Color::Auto,
+                    Some("always") => Color::Always,
+                    Some("never") => Color::Never,
+                    Some(_) => return Err("--color must be auto, always or never".into()),
+                };
+                continue;
+            }
+
+            let value = if takes_value(&flag) {
+                match value.clone().or_else(|| args.next()) {
+                    Some(value) => Some(value),
+                    None => return Err(format!("option '{}' needs a value", dashed(&flag))),
+                }
+            } else if value.is_some() {
+                return Err(format!("option '{}' does not take a value", dashed(&flag)));
+            } else {
+                None
+            };
+
+            match flag.as_str() {
+                "e" | "regexp" => patterns.get_or_insert_with(Vec::new).push(value.unwrap()),
+                "f" | "file" => {
+                    let file = value.unwrap();
+                    let bytes = fs::read(&file)
+                        .map_err(|e| format!("cannot read patterns from {}: {}", file, e))?;
+                    patterns
+                        .get_or_insert_with(Vec::new)
+                        .extend(encoding::decode(None, &bytes).lines().map(String::from));
+                }
+                "E" | "extended-regexp" => config.regex = true,
+                "fuzzy" => config.fuzzy = Some(number(&flag, value)?),
+                "i" | "ignore-case" => ignore_case = true,
+                "U" | "multiline" => config.multiline = true,
+                "encoding" => {
+                    let name = value.unwrap();
+                    config.encoding = match name.as_str() {
+                        "auto" => None,
+                        _ => Some(Encoding::from_name(&name).ok_or_else(|| {
+                            format!(
+                                "unknown encoding '{}'; use auto, utf-8, utf-16le, utf-16be, \
+                                 latin-1 or windows-1252",
+                                name
+                            )
+                        })?),
+                    };
+                }
+                "v" | "invert-match" => config.invert_match = true,
+                "w" | "word-regexp" => config.word_regexp = true,
+                "n" | "line-number" => config.line_number = true,
+                "c" | "count" => config.count = true,
+                "l" | "files-with-matches" => config.files_with_matches = true,
+                "q" | "quiet" => config.quiet = true,
+                "s" | "no-messages" => config.no_messages = true,
+                "A" | "after-context" => config.after_context = number(&flag, value)?,
+                "B" | "before-context" => config.before_context = number(&flag, value)?,
+                "C" | "context" => {
+                    config.after_context = number(&flag, value)?;
+                    config.before_context = config.after_context;
+                }
+                "json" => config.json = true,
+                "replace" => config.replace = Some(Replacement::new(&value.unwrap())),
+                // Taking grep's -r for --replace would turn the path after
+                // it into the replacement.
+                "r" => {
+                    return Err("unknown option '-r'; directories are searched recursively \
+                         without it, and --replace has no short form"
+                        .into())
+                }
+                "in-place" => config.in_place = true,
+                "dry-run" => config.dry_run = true,
+                "archives" => config.archives = true,
+                "index" => config.index = true,
+                "watch" => config.watch = true,
+                "interactive" => config.interactive = true,
+                "csv" => csv = true,
+                "jsonl" => jsonl = true,
+                "field" => field = value,
+                "j" | "threads" => {
+                    config.threads = match value.unwrap().parse() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err("the number of threads must be a positive number".into()),
+                    };
+                }
+                "h" | "help" => return Ok(Command::Help),
+                "V" | "version" => return Ok(Command::Version),
+                _ => return Err(format!("unknown option '{}'", dashed(&flag))),
+            }
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let patterns = match patterns {
+        Some(patterns) => patterns,
+        // The query is typed in, so every argument is a path.
+        None if config.interactive => Vec::new(),
+        None => vec![positional.next().ok_or("Didn't get a query string")?],
+    };
+    // Without -U, a pattern with newlines is several patterns, one per line.
+    config.patterns = if config.multiline {
+        patterns
+    } else {
+        patterns
+            .iter()
+            .flat_map(|pattern| pattern.split('\n'))
+            .map(String::from)
+            .collect()
+    };
+    config.paths = positional.collect();
+    if config.paths.is_empty() {
+        let default = if config.interactive { "." } else { "-" };
+        config.paths.push(default.to_string());
+    }
+
+    if config.json && (config.count || config.files_with_matches) {
+        return Err("--json cannot be combined with -c or -l".into());
+    }
+    if config.fuzzy.is_some() && (config.patterns.len() != 1 || config.regex || config.word_regexp)
+    {
+        return Err("--fuzzy needs a single query and cannot be combined with -E or -w".into());
+    }
+    if config.in_place || config.dry_run {
+        if config.replace.is_none() {
+            return Err("--in-place and --dry-run need --replace".into());
+        }
+        if config.invert_match || config.count || config.files_with_matches || config.json {
+            return Err(
+                "--in-place and --dry-run cannot be combined with -v, -c, -l or --json".into(),
+            );
+        }
+        if config.paths.iter().any(|path| path == "-") {
+            return Err("--in-place and --dry-run need files, not standard input".into());
+        }
+    }
+
+    if config.watch {
+        if config.count
+            || config.files_with_matches
+            || config.in_place
+            || config.dry_run
+            || config.index
+        {
+            return Err(
+                "--watch cannot be combined with -c, -l, --in-place, --dry-run or --index".into(),
+            );
+        }
+        if config.paths.iter().any(|path| path == "-") {
+            return Err("--watch needs files, not standard input".into());
+        }
+    }
+
+    if config.quiet && (config.in_place || config.dry_run || config.watch || config.interactive) {
+        return Err(
+            "-q cannot be combined with --in-place, --dry-run, --watch or --interactive".into(),
+        );
+    }
+
+    config.records = match (csv, jsonl, field) {
+        (false, false, None) => None,
+        (true, true, _) => return Err("--csv and --jsonl cannot be combined".into()),
+        (_, _, None) => return Err("--csv and --jsonl need --field".into()),
+        (false, false, Some(_)) => return Err("--field needs --csv or --jsonl".into()),
+        (csv, _, Some(field)) => {
+            config.field = field;
+            Some(if csv { Format::Csv } else { Format::JsonLines })
+        }
+    };
+    if config.records.is_some() && (config.replace.is_some() || config.watch || config.index) {
+        return Err("--field cannot be combined with --replace, --watch or --index".into());
+    }
+
+    if config.encoding.is_some() && (config.in_place || config.dry_run || config.watch) {
+        return Err("--encoding cannot be combined with --in-place, --dry-run or --watch".into());
+    }
+
+    if config.multiline
+        && (config.fuzzy.is_some()
+            || config.in_place
+            || config.dry_run
+            || config.watch
+            || config.interactive
+            || config.records.is_some())
+    {
+        return Err(
+            "-U cannot be combined with --fuzzy, --in-place, --dry-run, --watch, \
+                    --interactive or --field"
+                .into(),
+        );
+    }
+
+    if config.interactive {
+        if config.count
+            || config.files_with_matches
+            || config.invert_match
+            || config.json
+            || config.replace.is_some()
+            || config.watch
+            || config.records.is_some()
+        {
+            return Err(
+                "--interactive cannot be combined with -c, -l, -v, --json, --replace, \
+                        --watch or --field"
+                    .into(),
+            );
+        }
+        if config.patterns.len() > 1 {
+            return Err("--interactive starts from a single query".into());
+        }
+        if config.paths.iter().any(|path| path == "-") {
+            return Err("--interactive needs files, not standard input".into());
+        }
+    }
+
+    config.case_sensitive = !ignore_case && env::var("CASE_INSENSITIVE").is_err();
+
+    Ok(Command::Search(config))
+}
+
+fn takes_value(flag: &str) -> bool {
+    matches!(
+        flag,
+        "e" | "regexp"
+            | "f"
+            | "file"
+            | "A"
+            | "after-context"
+            | "B"
+            | "before-context"
+            | "C"
+            | "context"
+            | "fuzzy"
+            | "replace"
+            | "field"
+            | "encoding"
+            | "j"
+            | "threads"
+    )
+}
+
+fn number(flag: &str, value: Option<String>) -> Result<usize, String> {
+    value
+        .unwrap()
+        .parse()
+        .map_err(|_| format!("option '{}' needs a number", dashed(flag)))
+}
+
+fn dashed(flag: &str) -> String {
+    if flag.len() == 1 {
+        format!("-{}", flag)
+    } else {
+        format!("--{}", flag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_config(args: &[&str]) -> Config {
+        match parse(args.iter().map(|arg| arg.to_string())) {
+            Ok(Command::Search(config)) => config,
+            _ => panic!("expected a search"),
+        }
+    }
+
+    fn parse_error(args: &[&str]) -> String {
+        match parse(args.iter().map(|arg| arg.to_string())) {
+            Err(err) => err,
+            Ok(_) => panic!("expected an error"),
+        }
+    }
+
+    #[test]
+    fn parses_flags_and_positionals() {
+        let config = parse_config(&["-in", "to", "poem.txt", "--count", "-j2", "src"]);
+
+        assert_eq!(vec!["to"], config.patterns);
+        assert_eq!(vec!["poem.txt", "src"], config.paths);
+        assert!(!config.case_sensitive);
+        assert!(config.line_number);
+        assert!(config.count);
+        assert!(!config.invert_match);
+        assert_eq!(2, config.threads);
+
+        let config = parse_config(&["-C2", "-A", "3", "--color=never", "q", "f"]);
+        assert_eq!(2, config.before_context);
+        assert_eq!(3, config.after_context);
+        assert_eq!(Color::Never, config.color);
+
+        let config = parse_config(&["query"]);
+        assert_eq!(vec!["-"], config.paths);
+
+        let config = parse_config(&["--archives", "q", "backup.tar"]);
+        assert!(config.archives);
+
+        let config = parse_config(&["--threads=3", "--", "-v", "file"]);
+        assert_eq!(vec!["-v"], config.patterns);
+        assert_eq!(3, config.threads);
+    }
+
+    #[test]
+    fn collects_several_patterns() {
+        let config = parse_config(&["-e", "one", "-etwo", "--regexp=a\nb", "file", "dir"]);
+        assert_eq!(vec!["one", "two", "a", "b"], config.patterns);
+        assert_eq!(vec!["file", "dir"], config.paths);
+
+        let config = parse_config(&["-e", "", "-E"]);
+        assert_eq!(vec![""], config.patterns);
+        assert_eq!(vec!["-"], config.paths);
+        assert!(config.regex);
+
+        assert!(parse_error(&["-f", "/nonexistent/patterns"])
+            .starts_with("cannot read patterns from /nonexistent/patterns: "));
+    }
+
+    #[test]
+    fn reports_distinct_errors() {
+        assert_eq!("Didn't get a query string", parse_error(&[]));
+        assert_eq!(
+            "unknown option '--frobnicate'",
+            parse_error(&["--frobnicate"])
+        );
+        assert_eq!("unknown option '-z'", parse_error(&["-iz"]));
+        assert_eq!("option '-j' needs a value", parse_error(&["-j"]));
+        assert_eq!(
+            "option '--count' does not take a value",
+            parse_error(&["--count=2"])
+        );
+        assert_eq!(
+            "--json cannot be combined with -c or -l",
+            parse_error(&["--json", "-l", "q"])
+        );
+        assert_eq!(
+            "--fuzzy needs a single query and cannot be combined with -E or -w",
+            parse_error(&["--fuzzy=1", "-e", "a", "-e", "b"])
+        );
+        assert_eq!(
+            "option '--fuzzy' needs a number",
+            parse_error(&["--fuzzy=x", "q"])
+        );
+        assert_eq!(
+            "--in-place and --dry-run need --replace",
+            parse_error(&["--in-place", "q", "file"])
+        );
+        assert_eq!(
+            "unknown option '-r'; directories are searched recursively without it, \
+             and --replace has no short form",
+            parse_error(&["-r", "foo", "dir"])
+        );
+        assert_eq!(
+            "--in-place and --dry-run need files, not standard input",
+            parse_error(&["--dry-run", "--replace", "x", "q"])
+        );
+        assert_eq!(
+            "--watch cannot be combined with -c, -l, --in-place, --dry-run or --index",
+            parse_error(&["--watch", "-c", "q", "log"])
+        );
+        assert_eq!(
+            "--watch needs files, not standard input",
+            parse_error(&["--watch", "q"])
+        );
+        assert_eq!(
+            "--field needs --csv or --jsonl",
+            parse_error(&["--field", "name", "q"])
+        );
+        assert_eq!(
+            "--csv and --jsonl need --field",
+            parse_error(&["--jsonl", "q"])
+        );
+        assert_eq!(
+            "--field cannot be combined with --replace, --watch or --index",
+            parse_error(&["--csv", "--field=name", "--replace=x", "q"])
+        );
+    }
+
+    #[test]
+    fn multiline_keeps_newlines_in_patterns() {
+        assert_eq!(vec!["a", "b"], parse_config(&["a\nb"]).patterns);
+        let config = parse_config(&["-e", "a\nb", "-U", "file"]);
+        assert!(config.multiline);
+        assert_eq!(vec!["a\nb"], config.patterns);
+        assert_eq!(
+            "-U cannot be combined with --fuzzy, --in-place, --dry-run, --watch, \
+             --interactive or --field",
+            parse_error(&["-U", "--fuzzy=1", "q"])
+        );
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(None, parse_config(&["q"]).encoding);
+        let config = parse_config(&["--encoding=UTF-16LE", "q", "log"]);
+        assert_eq!(Some(Encoding::Utf16Le), config.encoding);
+        assert_eq!(
+            "unknown encoding 'ebcdic'; use auto, utf-8, utf-16le, utf-16be, latin-1 or \
+             windows-1252",
+            parse_error(&["--encoding", "ebcdic", "q"])
+        );
+    }
+
+    #[test]
+    fn quiet() {
+        let config = parse_config(&["-qs", "q", "a", "b"]);
+        assert!(config.quiet && config.no_messages);
+        assert_eq!(
+            "-q cannot be combined with --in-place, --dry-run, --watch or --interactive",
+            parse_error(&["--quiet", "--watch", "q", "log"])
+        );
+    }
+
+    #[test]
+    fn interactive() {
+        let config = parse_config(&["--interactive", "-i", "src", "README"]);
+        assert!(config.interactive);
+        assert!(config.patterns.is_empty());
+        assert_eq!(vec!["src", "README"], config.paths);
+        assert_eq!(vec!["."], parse_config(&["--interactive"]).paths);
+        assert_eq!(
+            "--interactive needs files, not standard input",
+            parse_error(&["--interactive", "-"])
+        );
+        assert_eq!(
+            "--interactive starts from a single query",
+            parse_error(&["--interactive", "-e", "a", "-e", "b", "src"])
+        );
+    }
+
+    #[test]
+    fn field_search() {
+        let config = parse_config(&["--jsonl", "--field", "user.name", "ann", "log.jsonl"]);
+        assert_eq!(Some(Format::JsonLines), config.records);
+        assert_eq!("user.name", config.field);
+        assert_eq!(vec!["log.jsonl"], config.paths);
+    }
+
+    #[test]
+    fn index_command() {
+        assert!(matches!(parse(vec!["index".to_string()]), Ok(Command::Index(dir)) if dir == "."));
+        let args = ["index", "src"].map(String::from);
+        assert!(matches!(parse(args), Ok(Command::Index(dir)) if dir == "src"));
+        let args = ["index", "src", "b"].map(String::from);
+        assert!(matches!(parse(args), Err(e) if e == "index takes a single directory"));
+
+        let config = parse_config(&["index", "Cargo.toml"]);
+        assert!(!config.index);
+        assert_eq!(vec!["index"], config.patterns);
+        assert_eq!(vec!["Cargo.toml"], config.paths);
+
+        let config = parse_config(&["--index", "-e", "index", "src"]);
+        assert!(config.index);
+        assert_eq!(vec!["index"], config.patterns);
+    }
+
+    #[test]
+    fn help_and_version() {
+        assert!(matches!(parse(vec!["-h".to_string()]), Ok(Command::Help)));
+        assert!(matches!(
+            parse(vec!["x".to_string(), "--version".to_string()]),
+            Ok(Command::Version)
+        ));
+    }
+}
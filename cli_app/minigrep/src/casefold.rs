@@ -0,0 +1,333 @@
+//! Unicode case folding for case-insensitive matching.
+//!
+//! Folding maps every character to a canonical form so that strings which
+//! differ only in case compare equal. It goes beyond lowercasing: `ß` folds
+//! to `ss`, final sigma `ς` to `σ`, `ﬁ` to `fi` and Cherokee small letters to
+//! their capitals.
+
+use std::ops::Range;
+
+/// Characters whose full case folding differs from their lowercase form,
+/// sorted by character. Generated from Unicode's CaseFolding.txt (statuses C
+/// and F); Cherokee is handled separately in `fold`.
+const FOLDS: &[(char, &str)] = &[
+    ('\u{b5}', "\u{3bc}"),
+    ('\u{df}', "\u{73}\u{73}"),
+    ('\u{149}', "\u{2bc}\u{6e}"),
+    ('\u{17f}', "\u{73}"),
+    ('\u{1f0}', "\u{6a}\u{30c}"),
+    ('\u{345}', "\u{3b9}"),
+    ('\u{390}', "\u{3b9}\u{308}\u{301}"),
+    ('\u{3b0}', "\u{3c5}\u{308}\u{301}"),
+    ('\u{3c2}', "\u{3c3}"),
+    ('\u{3d0}', "\u{3b2}"),
+    ('\u{3d1}', "\u{3b8}"),
+    ('\u{3d5}', "\u{3c6}"),
+    ('\u{3d6}', "\u{3c0}"),
+    ('\u{3f0}', "\u{3ba}"),
+    ('\u{3f1}', "\u{3c1}"),
+    ('\u{3f5}', "\u{3b5}"),
+    ('\u{587}', "\u{565}\u{582}"),
+    ('\u{1c80}', "\u{432}"),
+    ('\u{1c81}', "\u{434}"),
+    ('\u{1c82}', "\u{43e}"),
+    ('\u{1c83}', "\u{441}"),
+    ('\u{1c84}', "\u{442}"),
+    ('\u{1c85}', "\u{442}"),
+    ('\u{1c86}', "\u{44a}"),
+    ('\u{1c87}', "\u{463}"),
+    ('\u{1c88}', "\u{a64b}"),
+    ('\u{1e96}', "\u{68}\u{331}"),
+    ('\u{1e97}', "\u{74}\u{308}"),
+    ('\u{1e98}', "\u{77}\u{30a}"),
+    ('\u{1e99}', "\u{79}\u{30a}"),
+    ('\u{1e9a}', "\u{61}\u{2be}"),
+    ('\u{1e9b}', "\u{1e61}"),
+    ('\u{1e9e}', "\u{73}\u{73}"),
+    ('\u{1f50}', "\u{3c5}\u{313}"),
+    ('\u{1f52}', "\u{3c5}\u{313}\u{300}"),
+    ('\u{1f54}', "\u{3c5}\u{313}\u{301}"),
+    ('\u{1f56}', "\u{3c5}\u{313}\u{342}"),
+    ('\u{1f80}', "\u{1f00}\u{3b9}"),
+    ('\u{1f81}', "\u{1f01}\u{3b9}"),
+    ('\u{1f82}', "\u{1f02}\u{3b9}"),
+    ('\u{1f83}', "\u{1f03}\u{3b9}"),
+    ('\u{1f84}', "\u{1f04}\u{3b9}"),
+    ('\u{1f85}', "\u{1f05}\u{3b9}"),
+    ('\u{1f86}', "\u{1f06}\u{3b9}"),
+    ('\u{1f87}', "\u{1f07}\u{3b9}"),
+    ('\u{1f88}', "\u{1f00}\u{3b9}"),
+    ('\u{1f89}', "\u{1f01}\u{3b9}"),
+    ('\u{1f8a}', "\u{1f02}\u{3b9}"),
+    ('\u{1f8b}', "\u{1f03}\u{3b9}"),
+    ('\u{1f8c}', "\u{1f04}\u{3b9}"),
+    ('\u{1f8d}', "\u{1f05}\u{3b9}"),
+    ('\u{1f8e}', "\u{1f06}\u{3b9}"),
+    ('\u{1f8f}', "\u{1f07}\u{3b9}"),
+    ('\u{1f90}', "\u{1f20}\u{3b9}"),
+    ('\u{1f91}', "\u{1f21}\u{3b9}"),
+    ('\u{1f92}', "\u{1f22}\u{3b9}"),
+    ('\u{1f93}', "\u{1f23}\u{3b9}"),
+    ('\u{1f94}', "\u{1f24}\u{3b9}"),
+    ('\u{1f95}', "\u{1f25}\u{3b9}"),
+    ('\u{1f96}', "\u{1f26}\u{3b9}"),
+    ('\u{1f97}', "\u{1f27}\u{3b9}"),
+    ('\u{1f98}', "\u{1f20}\u{3b9}"),
+    ('\u{1f99}', "\u{1f21}\u{3b9}"),
+    ('\u{1f9a}', "\u{1f22}\u{3b9}"),
+    ('\u{1f9b}', "\u{1f23}\u{3b9}"),
+    ('\u{1f9c}', "\u{1f24}\u{3b9}"),
+    ('\u{1f9d}', "\u{1f25}\u{3b9}"),
+    ('\u{1f9e}', "\u{1f26}\u{3b9}"),
+    ('\u{1f9f}', "\u{1f27}\u{3b9}"),
+    ('\u{1fa0}', "\u{1f60}\u{3b9}"),
+    ('\u{1fa1}', "\u{1f61}\u{3b9}"),
+    ('\u{1fa2}', "\u{1f62}\u{3b9}"),
+    ('\u{1fa3}', "\u{1f63}\u{3b9}"),
+    ('\u{1fa4}', "\u{1f64}\u{3b9}"),
+    ('\u{1fa5}', "\u{1f65}\u{3b9}"),
+    ('\u{1fa6}', "\u{1f66}\u{3b9}"),
+    ('\u{1fa7}', "\u{1f67}\u{3b9}"),
+    ('\u{1fa8}', "\u{1f60}\u{3b9}"),
+    ('\u{1fa9}', "\u{1f61}\u{3b9}"),
+    ('\u{1faa}', "\u{1f62}\u{3b9}"),
+    ('\u{1fab}', "\u{1f63}\u{3b9}"),
+    ('\u{1fac}', "\u{1f64}\u{3b9}"),
+    ('\u{1fad}', "\u{1f65}\u{3b9}"),
+    ('\u{1fae}', "\u{1f66}\u{3b9}"),
+    ('\u{1faf}', "\u{1f67}\u{3b9}"),
+    ('\u{1fb2}', "\u{1f70}\u{3b9}"),
+    ('\u{1fb3}', "\u{3b1}\u{3b9}"),
+    ('\u{1fb4}', "\u{3ac}\u{3b9}"),
+    ('\u{1fb6}', "\u{3b1}\u{342}"),
+    ('\u{1fb7}', "\u{3b1}\u{342}\u{3b9}"),
+    ('\u{1fbc}', "\u{3b1}\u{3b9}"),
+    ('\u{1fbe}', "\u{3b9}"),
+    ('\u{1fc2}', "\u{1f74}\u{3b9}"),
+    ('\u{1fc3}', "\u{3b7}\u{3b9}"),
+    ('\u{1fc4}', "\u{3ae}\u{3b9}"),
+    ('\u{1fc6}', "\u{3b7}\u{342}"),
+    ('\u{1fc7}', "\u{3b7}\u{342}\u{3b9}"),
+    ('\u{1fcc}', "\u{3b7}\u{3b9}"),
+    ('\u{1fd2}', "\u{3b9}\u{308}\u{300}"),
+    ('\u{1fd3}', "\u{3b9}\u{308}\u{301}"),
+    ('\u{1fd6}', "\u{3b9}\u{342}"),
+    ('\u{1fd7}', "\u{3b9}\u{308}\u{342}"),
+    ('\u{1fe2}', "\u{3c5}\u{308}\u{300}"),
+    ('\u{1fe3}', "\u{3c5}\u{308}\u{301}"),
+    ('\u{1fe4}', "\u{3c1}\u{313}"),
+    ('\u{1fe6}', "\u{3c5}\u{342}"),
+    ('\u{1fe7}', "\u{3c5}\u{308}\u{342}"),
+    ('\u{1ff2}', "\u{1f7c}\u{3b9}"),
+    ('\u{1ff3}', "\u{3c9}\u{3b9}"),
+    ('\u{1ff4}', "\u{3ce}\u{3b9}"),
+    ('\u{1ff6}', "\u{3c9}\u{342}"),
+    ('\u{1ff7}', "\u{3c9}\u{342}\u{3b9}"),
+    ('\u{1ffc}', "\u{3c9}\u{3b9}"),
+    ('\u{fb00}', "\u{66}\u{66}"),
+    ('\u{fb01}', "\u{66}\u{69}"),
+    ('\u{fb02}', "\u{66}\u{6c}"),
+    ('\u{fb03}', "\u{66}\u{66}\u{69}"),
+    ('\u{fb04}', "\u{66}\u{66}\u{6c}"),
+    ('\u{fb05}', "\u{73}\u{74}"),
+    ('\u{fb06}', "\u{73}\u{74}"),
+    ('\u{fb13}', "\u{574}\u{576}"),
+    ('\u{fb14}', "\u{574}\u{565}"),
+    ('\u{fb15}', "\u{574}\u{56b}"),
+    ('\u{fb16}', "\u{57e}\u{576}"),
+    ('\u{fb17}', "\u{574}\u{56d}"),
+];
+
+/// The ASCII characters that some non-ASCII character folds to or into, as
+/// the Kelvin sign `K` folds to `k` and `ﬁ` to `fi`.
+const FOLDED_FROM_NON_ASCII: &[u8] = b"afhijklnstwy";
+
+/// The full case folding of `c`, which is one to three characters long.
+pub fn fold(c: char) -> Folded {
+    let mut folded = Folded {
+        chars: ['\0'; 3],
+        len: 0,
+        next: 0,
+    };
+    let mut push = |c| {
+        folded.chars[folded.len] = c;
+        folded.len += 1;
+    };
+
+    match c as u32 {
+        _ if c.is_ascii() => push(c.to_ascii_lowercase()),
+        // Cherokee folds to its capital letters, unlike every other script.
+        0x13a0..=0x13f5 => push(c),
+        small @ 0x13f8..=0x13fd => push(char::from_u32(small - 8).unwrap()),
+        small @ 0xab70..=0xabbf => push(char::from_u32(small - 0xab70 + 0x13a0).unwrap()),
+        _ => match FOLDS.binary_search_by_key(&c, |&(from, _)| from) {
+            Ok(i) => FOLDS[i].1.chars().for_each(push),
+            Err(_) => c.to_lowercase().for_each(push),
+        },
+    }
+
+    folded
+}
+
+/// Iterator over the characters of a case folding.
+pub struct Folded {
+    chars: [char; 3],
+    len: usize,
+    next: usize,
+}
+
+impl Iterator for Folded {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars[..self.len].get(self.next).copied();
+        self.next += 1;
+        c
+    }
+}
+
+/// Fold a whole string, typically the query.
+pub fn fold_str(text: &str) -> String {
+    text.chars().flat_map(fold).collect()
+}
+
+/// The folding of `c` if it is a single character, otherwise `c` itself.
+///
+/// Used where characters are compared one at a time, as in the regex
+/// engine, and multi-character folds such as `ß` to `ss` cannot apply.
+pub fn simple_fold(c: char) -> char {
+    let mut folded = fold(c);
+    match (folded.next(), folded.next()) {
+        (Some(single), None) => single,
+        _ => c,
+    }
+}
+
+/// Whether `c` is ASCII and only ASCII characters fold to it, so that
+/// ignoring case it can only match itself in either case.
+pub fn is_ascii_only(c: char) -> bool {
+    c.is_ascii() && !FOLDED_FROM_NON_ASCII.contains(&(c.to_ascii_lowercase() as u8))
+}
+
+/// Find `needle`, which must already be folded with `fold_str`, in
+/// `haystack` at or after byte offset `start`, ignoring case.
+///
+/// Matches start and end on character boundaries of `haystack`; a needle
+/// that only covers part of a character's folding (`s` in `ß`) does not
+/// match. While both sides are ASCII the search allocates nothing and
+/// compares bytes directly, and it only looks as far into `haystack` as
+/// the match, so finding each match along a line in turn stays linear.
+pub fn find(haystack: &str, needle: &str, start: usize) -> Option<Range<usize>> {
+    if needle.is_empty() {
+        return Some(start..start);
+    }
+
+    let rest = &haystack[start..];
+    let mut from = 0;
+    if needle.is_ascii() {
+        let bytes = rest.as_bytes();
+        while from + needle.len() <= bytes.len() {
+            let window = &bytes[from..from + needle.len()];
+            // Non-ASCII characters may fold to ASCII ones (`K` to `k`), so
+            // from here on the search goes character by character.
+            if !window.is_ascii() {
+                break;
+            }
+            if window.eq_ignore_ascii_case(needle.as_bytes()) {
+                return Some(start + from..start + from + needle.len());
+            }
+            from += 1;
+        }
+        // Everything before `from` is ASCII, so it is a character boundary,
+        // and all of `rest` is when every window was compared.
+        if from > 0 && from + needle.len() > bytes.len() {
+            return None;
+        }
+    }
+
+    rest[from..]
+        .char_indices()
+        .find_map(|(at, _)| folded_prefix(&rest[from + at..], needle).map(|len| at..at + len))
+        .map(|range| start + from + range.start..start + from + range.end)
+}
+
+/// If `text` starts with characters that fold to `needle`, their length in
+/// bytes.
+fn folded_prefix(text: &str, needle: &str) -> Option<usize> {
+    let mut wanted = needle.chars();
+
+    for (i, c) in text.char_indices() {
+        for folded in fold(c) {
+            if wanted.next() != Some(folded) {
+                return None;
+            }
+        }
+        if wanted.as_str().is_empty() {
+            return Some(i + c.len_utf8());
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(query: &str, text: &str) -> Option<Range<usize>> {
+        find(text, &fold_str(query), 0)
+    }
+
+    #[test]
+    fn folds_beyond_lowercase() {
+        assert_eq!("strasse", fold_str("Straße"));
+        assert_eq!("strasse", fold_str("STRASSE"));
+        assert_eq!(fold_str("σας"), fold_str("ΣΑΣ"));
+        assert_eq!("fish", fold_str("ﬁsh"));
+        assert_eq!("i\u{307}stanbul", fold_str("İstanbul"));
+        assert_eq!('Ꭰ', simple_fold('ꭰ'));
+        assert_eq!('k', simple_fold('\u{212a}'));
+    }
+
+    #[test]
+    fn knows_which_ascii_characters_only_ascii_folds_to() {
+        let mut folded_from_non_ascii = Vec::new();
+        for c in ('\u{80}'..=char::MAX).flat_map(fold) {
+            if c.is_ascii() && !folded_from_non_ascii.contains(&(c as u8)) {
+                folded_from_non_ascii.push(c as u8);
+            }
+        }
+        folded_from_non_ascii.sort();
+        assert_eq!(FOLDED_FROM_NON_ASCII, folded_from_non_ascii);
+
+        assert!(is_ascii_only('E'));
+        assert!(is_ascii_only('-'));
+        assert!(!is_ascii_only('K'));
+        assert!(!is_ascii_only('é'));
+    }
+
+    #[test]
+    fn finds_multilingual_matches() {
+        assert_eq!(Some(4..11), matches("STRASSE", "Die Straße"));
+        assert_eq!(Some(4..11), matches("straße", "Die STRASSE"));
+        assert_eq!(None, matches("stras", "Straße"));
+        assert_eq!(Some(0..10), matches("İstanbul", "i\u{307}stanbul"));
+        assert_eq!(Some(0..9), matches("i\u{307}STANBUL", "İstanbul"));
+        assert_eq!(Some(0..6), matches("σας", "ΣΑΣ"));
+        assert_eq!(Some(11..23), matches("москва", "Город МОСКВА"));
+        assert_eq!(Some(0..5), matches("file", "ﬁle"));
+    }
+
+    #[test]
+    fn ascii_fast_path() {
+        assert_eq!(Some(1..5), find("Trust me.", "rust", 0));
+        assert_eq!(Some(6..10), find("Trust RUST", "rust", 2));
+        assert_eq!(None, find("Trust", "rusty", 0));
+        // Non-ASCII text can still fold to an ASCII needle.
+        assert_eq!(Some(0..5), find("\u{212a}ey", "key", 0));
+        assert_eq!(Some(7..12), find("a key, \u{212a}ey", "key", 3));
+        assert_eq!(Some(5..13), find("fast STRAẞE", "strasse", 0));
+        assert_eq!(None, find("no key", "keys", 0));
+        assert_eq!(None, find("k", "key", 0));
+    }
+}
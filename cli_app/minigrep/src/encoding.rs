@@ -0,0 +1,256 @@
+//! Reading text that is not UTF-8, for `--encoding` and files that start
+//! with a byte order mark. Text is turned into UTF-8 as it is read, so the
+//! rest of the search never sees anything else; offsets are then into the
+//! UTF-8 text.
+
+use std::borrow::Cow;
+use std::io::{self, BufRead, Read};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// ISO-8859-1, where every byte is the character with that number.
+    Latin1,
+    Windows1252,
+}
+
+/// The characters Windows-1252 has in place of Latin-1's C1 controls, with
+/// the five bytes it leaves undefined kept as those controls.
+const WINDOWS_1252: [char; 32] = [
+    '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}', '\u{17d}', '\u{8f}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}', '\u{17e}', '\u{178}',
+];
+
+impl Encoding {
+    /// Look up an encoding by one of its usual names, ignoring case.
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-16le" | "utf16le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            "latin-1" | "latin1" | "iso-8859-1" => Some(Encoding::Latin1),
+            "windows-1252" | "cp1252" => Some(Encoding::Windows1252),
+            _ => None,
+        }
+    }
+
+    fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => b"\xef\xbb\xbf",
+            Encoding::Utf16Le => b"\xff\xfe",
+            Encoding::Utf16Be => b"\xfe\xff",
+            Encoding::Latin1 | Encoding::Windows1252 => b"",
+        }
+    }
+}
+
+/// The encoding of text starting with `head`: `encoding` if given, or else
+/// the one its byte order mark shows, or else UTF-8. Also returns the
+/// length of the byte order mark, to skip it.
+pub fn detect(encoding: Option<Encoding>, head: &[u8]) -> (Encoding, usize) {
+    let encoding = encoding.unwrap_or_else(|| {
+        [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be]
+            .into_iter()
+            .find(|encoding| head.starts_with(encoding.bom()))
+            .unwrap_or(Encoding::Utf8)
+    });
+    let bom = encoding.bom();
+    (encoding, if head.starts_with(bom) { bom.len() } else { 0 })
+}
+
+/// Decode the whole of `bytes` as `detect` says.
+pub fn decode(encoding: Option<Encoding>, bytes: &[u8]) -> Cow<'_, str> {
+    let (encoding, bom) = detect(encoding, bytes);
+    let bytes = &bytes[bom..];
+    if encoding == Encoding::Utf8 {
+        return String::from_utf8_lossy(bytes);
+    }
+    let mut text = String::new();
+    // Reading from memory cannot fail.
+    let _ = Decoder::new(bytes, encoding).read_to_string(&mut text);
+    Cow::Owned(text)
+}
+
+/// Reads text in some encoding from `reader` as UTF-8. Bytes that are not
+/// valid in the encoding, such as half of a surrogate pair, become the
+/// replacement character.
+pub struct Decoder<R> {
+    reader: R,
+    encoding: Encoding,
+    /// Decoded text, of which `pos` bytes have been read.
+    text: String,
+    pos: usize,
+    /// Bytes of a character that goes on in the next read.
+    pending: Vec<u8>,
+}
+
+impl<R: BufRead> Decoder<R> {
+    pub fn new(reader: R, encoding: Encoding) -> Decoder<R> {
+        Decoder {
+            reader,
+            encoding,
+            text: String::new(),
+            pos: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Decode what the reader has buffered, leaving `text` empty only at
+    /// the end.
+    fn decode(&mut self) -> io::Result<()> {
+        self.text.clear();
+        self.pos = 0;
+
+        while self.text.is_empty() {
+            let input = self.reader.fill_buf()?;
+            if input.is_empty() {
+                if !self.pending.is_empty() {
+                    self.pending.clear();
+                    self.text.push(char::REPLACEMENT_CHARACTER);
+                }
+                return Ok(());
+            }
+
+            let len = input.len();
+            match self.encoding {
+                Encoding::Utf8 => {
+                    self.pending.extend_from_slice(input);
+                    // Keep the start of a character cut off at the end.
+                    let total = self.pending.len();
+                    let end = (total.saturating_sub(3)..total)
+                        .find(|&i| self.pending[i] >= 0xc0)
+                        .filter(|&i| {
+                            matches!(std::str::from_utf8(&self.pending[i..]),
+                                     Err(e) if e.error_len().is_none())
+                        })
+                        .unwrap_or(total);
+                    self.text
+                        .push_str(&String::from_utf8_lossy(&self.pending[..end]));
+                    self.pending.drain(..end);
+                }
+                Encoding::Latin1 => self.text.extend(input.iter().map(|&b| char::from(b))),
+                Encoding::Windows1252 => self.text.extend(input.iter().map(|&b| match b {
+                    0x80..=0x9f => WINDOWS_1252[usize::from(b - 0x80)],
+                    _ => char::from(b),
+                })),
+                Encoding::Utf16Le | Encoding::Utf16Be => {
+                    self.pending.extend_from_slice(input);
+                    let unit = |pair: &[u8]| match self.encoding {
+                        Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                        _ => u16::from_be_bytes([pair[0], pair[1]]),
+                    };
+                    let mut end = self.pending.len() / 2 * 2;
+                    // Keep the first half of a surrogate pair for the second.
+                    if end > 0 && (0xd800..0xdc00).contains(&unit(&self.pending[end - 2..end])) {
+                        end -= 2;
+                    }
+                    let units = self.pending[..end].chunks(2).map(unit);
+                    self.text.extend(
+                        char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)),
+                    );
+                    self.pending.drain(..end);
+                }
+            }
+            self.reader.consume(len);
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for Decoder<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.text.len() {
+            self.decode()?;
+        }
+        Ok(&self.text.as_bytes()[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A reader that hands out one byte at a time, to cut every character.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.0.len().min(1).min(buf.len());
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    impl BufRead for Trickle<'_> {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            Ok(&self.0[..self.0.len().min(1)])
+        }
+
+        fn consume(&mut self, amt: usize) {
+            self.0 = &self.0[amt..];
+        }
+    }
+
+    fn read(bytes: &[u8], encoding: Encoding) -> String {
+        let mut text = String::new();
+        Decoder::new(Trickle(bytes), encoding)
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(decode(Some(encoding), bytes), text);
+        text
+    }
+
+    #[test]
+    fn decodes_each_encoding() {
+        let utf16: Vec<u16> = "caf\u{e9} \u{1f980}\n".encode_utf16().collect();
+        let le: Vec<u8> = utf16.iter().flat_map(|unit| unit.to_le_bytes()).collect();
+        let be: Vec<u8> = utf16.iter().flat_map(|unit| unit.to_be_bytes()).collect();
+        assert_eq!("caf\u{e9} \u{1f980}\n", read(&le, Encoding::Utf16Le));
+        assert_eq!("caf\u{e9} \u{1f980}\n", read(&be, Encoding::Utf16Be));
+        assert_eq!("a\u{fffd}", read(b"a\0\x3d\xd8", Encoding::Utf16Le));
+        assert_eq!("a\u{fffd}", read(b"\0a\0", Encoding::Utf16Be));
+
+        assert_eq!("caf\u{e9} \u{80}", read(b"caf\xe9 \x80", Encoding::Latin1));
+        assert_eq!(
+            "\u{20ac}5 \u{2018}caf\u{e9}\u{2019}\u{81}",
+            read(b"\x805 \x91caf\xe9\x92\x81", Encoding::Windows1252)
+        );
+        assert_eq!(
+            "caf\u{e9} \u{fffd}",
+            read(&"café \u{ff}".as_bytes()[..7], Encoding::Utf8)
+        );
+    }
+
+    #[test]
+    fn detects_byte_order_marks() {
+        assert_eq!((Encoding::Utf16Le, 2), detect(None, b"\xff\xfea\0"));
+        assert_eq!((Encoding::Utf16Be, 2), detect(None, b"\xfe\xff\0a"));
+        assert_eq!((Encoding::Utf8, 3), detect(None, b"\xef\xbb\xbfa"));
+        assert_eq!((Encoding::Utf8, 0), detect(None, b"\xff"));
+        assert_eq!(
+            (Encoding::Latin1, 0),
+            detect(Some(Encoding::Latin1), b"\xff\xfea")
+        );
+        assert_eq!("hi", decode(None, b"\xff\xfeh\0i\0"));
+        assert_eq!(Some(Encoding::Windows1252), Encoding::from_name("CP1252"));
+    }
+}
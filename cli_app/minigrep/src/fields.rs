@@ -0,0 +1,193 @@
+//! Reading input as records of CSV or JSON Lines, for `--csv`, `--jsonl`
+//! and `--field`, so that a search can look at one field of each.
+//!
+//! CSV has a header row naming the columns, and a quoted field may span
+//! lines. In JSON Lines each line is a record, and a field is named by its
+//! path through objects and arrays, as in `user.name` or `tags.0`.
+
+mod csv;
+mod json;
+
+use std::io::{self, BufRead};
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    JsonLines,
+}
+
+/// A record, with the value of the field searched if it has that field.
+#[derive(Debug, PartialEq)]
+pub struct Record {
+    /// The line the record starts on, counting from 1.
+    pub number: usize,
+    /// Byte offset of the start of the record.
+    pub offset: usize,
+    /// The record without its line terminator.
+    pub text: String,
+    pub value: Option<String>,
+    /// Where `value` is in `text`, if it is there as it is, without quotes
+    /// or escapes to decode.
+    pub span: Option<Range<usize>>,
+}
+
+enum Field {
+    Column(usize),
+    Path(Vec<String>),
+}
+
+pub struct Records<R> {
+    reader: R,
+    field: Field,
+    /// Lines and bytes read so far.
+    lines: usize,
+    offset: usize,
+}
+
+impl<R: BufRead> Records<R> {
+    /// Start reading records from `reader`, which for CSV means reading the
+    /// header to find the column named `field`.
+    pub fn new(format: Format, field: &str, reader: R) -> io::Result<Records<R>> {
+        let mut records = Records {
+            reader,
+            field: Field::Path(field.split('.').map(String::from).collect()),
+            lines: 0,
+            offset: 0,
+        };
+
+        if format == Format::Csv {
+            let column = match records.read()? {
+                Some((_, _, header)) => csv::fields(&header)
+                    .iter()
+                    .position(|(name, _)| name == field),
+                None => return Ok(records),
+            };
+            records.field = Field::Column(column.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("no column named {}", field),
+                )
+            })?);
+        }
+        Ok(records)
+    }
+
+    /// The next record that is not blank, with its line number and offset.
+    /// A record goes on past the end of a line inside quotes, which is
+    /// harmless for JSON, where strings cannot hold a newline.
+    fn read(&mut self) -> io::Result<Option<(usize, usize, String)>> {
+        let mut raw = Vec::new();
+
+        loop {
+            raw.clear();
+            let number = self.lines + 1;
+            let offset = self.offset;
+            loop {
+                let len = self.reader.read_until(b'\n', &mut raw)?;
+                if len == 0 {
+                    break;
+                }
+                self.lines += 1;
+                self.offset += len;
+                if raw.iter().filter(|&&b| b == b'"').count() % 2 == 0 {
+                    break;
+                }
+            }
+            if raw.is_empty() {
+                return Ok(None);
+            }
+
+            if raw.ends_with(b"\n") {
+                raw.pop();
+                if raw.ends_with(b"\r") {
+                    raw.pop();
+                }
+            }
+            if !raw.iter().all(u8::is_ascii_whitespace) {
+                return Ok(Some((
+                    number,
+                    offset,
+                    String::from_utf8_lossy(&raw).into_owned(),
+                )));
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Records<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        let (number, offset, text) = match self.read() {
+            Ok(record) => record?,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let found = match &self.field {
+            Field::Column(column) => csv::fields(&text).into_iter().nth(*column),
+            Field::Path(path) => json::lookup(&text, path),
+        };
+        let (value, span) = match found {
+            Some((value, span)) => (Some(value), span),
+            None => (None, None),
+        };
+        Some(Ok(Record {
+            number,
+            offset,
+            text,
+            value,
+            span,
+        }))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+
+    fn records(format: Format, field: &str, input: &str) -> Vec<(usize, Option<String>)> {
+        let records = Records::new(format, field, input.as_bytes()).unwrap();
+        let mut found = Vec::new();
+        for record in records {
+            let record = record.unwrap();
+            if let Some(span) = &record.span {
+                assert_eq!(record.value.as_deref(), Some(&record.text[span.clone()]));
+            }
+            found.push((record.number, record.value));
+        }
+        found
+    }
+
+    #[test]
+    fn reads_fields_of_records() {
+        let csv = "id,name,note\n1,ann,\"says \"\"hi\"\"\"\n\n2,\"bob\",\"two\nlines\"\n3\n";
+        assert_eq!(
+            vec![
+                (2, Some("ann".to_string())),
+                (4, Some("bob".to_string())),
+                (6, None),
+            ],
+            records(Format::Csv, "name", csv)
+        );
+        assert_eq!(
+            Some("two\nlines".to_string()),
+            records(Format::Csv, "note", csv)[1].1
+        );
+        let error = Records::new(Format::Csv, "email", csv.as_bytes())
+            .err()
+            .unwrap();
+        assert_eq!("no column named email", error.to_string());
+
+        let jsonl = "{\"user\": {\"name\": \"ann\", \"tags\": [\"a\", 7]}}\n\n{\"user\": null}\n";
+        assert_eq!(
+            vec![(1, Some("ann".to_string())), (3, None)],
+            records(Format::JsonLines, "user.name", jsonl)
+        );
+        assert_eq!(
+            vec![(1, Some("7".to_string())), (3, None)],
+            records(Format::JsonLines, "user.tags.1", jsonl)
+        );
+    }
+}
@@ -0,0 +1,78 @@
+//! Splitting a CSV record into fields, as RFC 4180 describes: fields are
+//! separated by commas, and a field in double quotes may contain commas,
+//! newlines and quotes, which are doubled.
+
+use std::ops::Range;
+
+/// The fields of `record`, each with where it is in `record` if it is
+/// there as it is.
+pub fn fields(record: &str) -> Vec<(String, Option<Range<usize>>)> {
+    let mut fields = Vec::new();
+    let mut rest = record;
+
+    loop {
+        let start = record.len() - rest.len();
+        let (field, after) = match rest.strip_prefix('"') {
+            Some(quoted) => {
+                let (mut value, len) = unquote(quoted);
+                let span = (value.len() == len).then(|| start + 1..start + 1 + len);
+                // Anything between the closing quote and the comma is kept,
+                // as it would be without the quotes.
+                let after = &quoted[(len + 1).min(quoted.len())..];
+                let end = after.find(',').unwrap_or(after.len());
+                value.push_str(&after[..end]);
+                let span = span.filter(|_| end == 0);
+                ((value, span), &after[end..])
+            }
+            None => {
+                let end = rest.find(',').unwrap_or(rest.len());
+                (
+                    (rest[..end].to_string(), Some(start..start + end)),
+                    &rest[end..],
+                )
+            }
+        };
+        fields.push(field);
+
+        match after.strip_prefix(',') {
+            Some(next) => rest = next,
+            None => return fields,
+        }
+    }
+}
+
+/// The value of a quoted field, given what follows its opening quote, and
+/// how far it goes before the closing quote.
+fn unquote(quoted: &str) -> (String, usize) {
+    let mut value = String::new();
+    let mut chars = quoted.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if c == '"' && chars.next_if(|&(_, c)| c == '"').is_none() {
+            return (value, i);
+        }
+        value.push(c);
+    }
+    (value, quoted.len())
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_quoted_fields() {
+        assert_eq!(
+            vec![
+                ("a".to_string(), Some(0..1)),
+                ("".to_string(), Some(2..2)),
+                ("b, c".to_string(), Some(4..8)),
+                ("say \"hi\"".to_string(), None),
+                ("".to_string(), Some(23..23)),
+            ],
+            fields("a,,\"b, c\",\"say \"\"hi\"\"\",")
+        );
+        assert_eq!(vec![("open".to_string(), Some(1..5))], fields("\"open"));
+    }
+}
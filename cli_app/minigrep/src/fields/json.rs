@@ -0,0 +1,229 @@
+//! Finding a value by its path in a JSON document, without building the
+//! rest of it. A document that is not valid JSON may still give a value,
+//! as only the parts on the way to it are looked at.
+
+use std::ops::Range;
+
+/// The value at `path` in `text`, with where it is in `text` if it is there
+/// as it is. Strings are decoded; anything else is given as its JSON.
+pub fn lookup(text: &str, path: &[String]) -> Option<(String, Option<Range<usize>>)> {
+    let mut parser = Parser { text, pos: 0 };
+
+    for key in path {
+        parser.whitespace();
+        if parser.eat(b'{') {
+            parser.member(key)?;
+        } else if parser.eat(b'[') {
+            parser.element(key.parse().ok()?)?;
+        } else {
+            return None;
+        }
+    }
+
+    parser.whitespace();
+    let start = parser.pos;
+    if parser.eat(b'"') {
+        let (value, escaped) = parser.string()?;
+        let span = (!escaped).then(|| start + 1..parser.pos - 1);
+        return Some((value, span));
+    }
+    parser.value()?;
+    Some((text[start..parser.pos].to_string(), Some(start..parser.pos)))
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let found = self.peek() == Some(byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\r' | b'\n')) {
+            self.pos += 1;
+        }
+    }
+
+    /// Move to the value of the member named `key` of an object, just past
+    /// its `{`.
+    fn member(&mut self, key: &str) -> Option<()> {
+        self.whitespace();
+        if self.eat(b'}') {
+            return None;
+        }
+        loop {
+            self.whitespace();
+            if !self.eat(b'"') {
+                return None;
+            }
+            let (name, _) = self.string()?;
+            self.whitespace();
+            if !self.eat(b':') {
+                return None;
+            }
+            if name == key {
+                return Some(());
+            }
+            self.whitespace();
+            self.value()?;
+            self.whitespace();
+            if !self.eat(b',') {
+                return None;
+            }
+        }
+    }
+
+    /// Move to the element at `index` of an array, just past its `[`.
+    fn element(&mut self, index: usize) -> Option<()> {
+        self.whitespace();
+        if self.eat(b']') {
+            return None;
+        }
+        for _ in 0..index {
+            self.whitespace();
+            self.value()?;
+            self.whitespace();
+            if !self.eat(b',') {
+                return None;
+            }
+        }
+        Some(())
+    }
+
+    /// Skip a value.
+    fn value(&mut self) -> Option<()> {
+        match self.peek()? {
+            b'"' => {
+                self.pos += 1;
+                self.string().map(|_| ())
+            }
+            open @ (b'{' | b'[') => {
+                let close = if open == b'{' { b'}' } else { b']' };
+                self.pos += 1;
+                self.whitespace();
+                if self.eat(close) {
+                    return Some(());
+                }
+                loop {
+                    self.whitespace();
+                    self.value()?;
+                    self.whitespace();
+                    if open == b'{' && self.eat(b':') {
+                        continue;
+                    }
+                    if self.eat(close) {
+                        return Some(());
+                    }
+                    if !self.eat(b',') {
+                        return None;
+                    }
+                }
+            }
+            _ => {
+                let start = self.pos;
+                while !matches!(
+                    self.peek(),
+                    None | Some(b',' | b'}' | b']' | b' ' | b'\t' | b'\r' | b'\n')
+                ) {
+                    self.pos += 1;
+                }
+                (self.pos > start).then_some(())
+            }
+        }
+    }
+
+    /// Decode a string, just past its opening quote, and say whether it had
+    /// escapes.
+    fn string(&mut self) -> Option<(String, bool)> {
+        let mut value = String::new();
+        let mut escaped = false;
+
+        loop {
+            let rest = &self.text[self.pos..];
+            let end = rest.find(['"', '\\'])?;
+            value.push_str(&rest[..end]);
+            self.pos += end + 1;
+            if rest.as_bytes()[end] == b'"' {
+                return Some((value, escaped));
+            }
+
+            escaped = true;
+            let c = match self.peek()? {
+                b'"' => '"',
+                b'\\' => '\\',
+                b'/' => '/',
+                b'b' => '\u{8}',
+                b'f' => '\u{c}',
+                b'n' => '\n',
+                b'r' => '\r',
+                b't' => '\t',
+                b'u' => {
+                    self.pos += 1;
+                    let high = self.hex()?;
+                    let c = if (0xd800..0xdc00).contains(&high)
+                        && self.text[self.pos..].starts_with("\\u")
+                    {
+                        self.pos += 2;
+                        let low = self.hex()?;
+                        char::from_u32(
+                            0x10000 + ((high - 0xd800) << 10) + (low.checked_sub(0xdc00)?),
+                        )
+                    } else {
+                        char::from_u32(high)
+                    };
+                    value.push(c.unwrap_or(char::REPLACEMENT_CHARACTER));
+                    continue;
+                }
+                _ => return None,
+            };
+            self.pos += 1;
+            value.push(c);
+        }
+    }
+
+    fn hex(&mut self) -> Option<u32> {
+        let digits = self.text.get(self.pos..self.pos + 4)?;
+        let n = u32::from_str_radix(digits, 16).ok()?;
+        self.pos += 4;
+        Some(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(text: &str, path: &str) -> Option<String> {
+        let path: Vec<String> = path.split('.').map(String::from).collect();
+        let (value, span) = lookup(text, &path)?;
+        if let Some(span) = span {
+            assert_eq!(value, text[span]);
+        }
+        Some(value)
+    }
+
+    #[test]
+    fn finds_values_by_path() {
+        let text = r#"{"a": [1, {"b": "x"}], "c\"d": {}, "e": {"f": "tab\tand \ud83d\ude00", "g": [true, null]}}"#;
+        assert_eq!(Some("[1, {\"b\": \"x\"}]".to_string()), find(text, "a"));
+        assert_eq!(Some("x".to_string()), find(text, "a.1.b"));
+        assert_eq!(Some("{}".to_string()), find(text, "c\"d"));
+        assert_eq!(Some("tab\tand 😀".to_string()), find(text, "e.f"));
+        assert_eq!(Some("null".to_string()), find(text, "e.g.1"));
+        assert_eq!(None, find(text, "a.2"));
+        assert_eq!(None, find(text, "e.h"));
+        assert_eq!(None, find(text, "e.f.g"));
+        assert_eq!(None, find("not json", "a"));
+    }
+}
@@ -0,0 +1,267 @@
+//! Approximate matching: finding the places where a query occurs with at
+//! most a given number of edits (insertions, deletions or substitutions of
+//! one character), for `--fuzzy`.
+//!
+//! The text is scanned once with Myers' bit-parallel algorithm, which keeps
+//! a whole column of the edit distance table in two machine words, so only
+//! queries of up to 64 characters get it; longer ones fall back to updating
+//! the column one cell at a time. That finds where matches end, and the
+//! start of each is then found by running the table backwards from there.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::casefold;
+
+pub struct Fuzzy {
+    query: Vec<char>,
+    max_distance: usize,
+    case_insensitive: bool,
+}
+
+/// One column of the edit distance table between the query and the text
+/// ending at the current character, where a match may start anywhere.
+enum Column {
+    /// Myers' algorithm: the vertical differences between neighbouring
+    /// cells, as bit sets of +1s and -1s.
+    Bits {
+        /// The positions of each character in the query.
+        positions: HashMap<char, u64>,
+        plus: u64,
+        minus: u64,
+        last: u64,
+        score: usize,
+    },
+    Cells(Vec<usize>),
+}
+
+impl Column {
+    fn new(query: &[char]) -> Column {
+        if query.len() > 64 {
+            return Column::Cells((0..=query.len()).collect());
+        }
+
+        let mut positions = HashMap::new();
+        for (i, &c) in query.iter().enumerate() {
+            *positions.entry(c).or_insert(0) |= 1 << i;
+        }
+        Column::Bits {
+            positions,
+            plus: u64::MAX,
+            minus: 0,
+            last: 1 << (query.len() - 1),
+            score: query.len(),
+        }
+    }
+
+    /// Move on to the next character of the text and return the distance
+    /// of the best match ending with it.
+    fn step(&mut self, query: &[char], c: char) -> usize {
+        match self {
+            Column::Bits {
+                positions,
+                plus,
+                minus,
+                last,
+                score,
+            } => {
+                let eq = positions.get(&c).copied().unwrap_or(0);
+                let xv = eq | *minus;
+                let xh = ((eq & *plus).wrapping_add(*plus) ^ *plus) | eq;
+                let mut ph = *minus | !(xh | *plus);
+                let mut mh = *plus & xh;
+
+                if ph & *last != 0 {
+                    *score += 1;
+                } else if mh & *last != 0 {
+                    *score -= 1;
+                }
+
+                // The top row stays at zero, so a match can start anywhere.
+                ph <<= 1;
+                mh <<= 1;
+                *plus = mh | !(xv | ph);
+                *minus = ph & xv;
+                *score
+            }
+            Column::Cells(column) => {
+                update(column, c, false, |i| query[i]);
+                column[query.len()]
+            }
+        }
+    }
+}
+
+/// Update a column of the edit distance table one cell at a time for the
+/// next character `c` of the text, given the characters of the query by
+/// index. With `anchored` the match has to start where the text did,
+/// otherwise it may start anywhere.
+fn update<Q: Fn(usize) -> char>(column: &mut [usize], c: char, anchored: bool, query: Q) {
+    let mut diagonal = column[0];
+    if anchored {
+        column[0] += 1;
+    }
+    for i in 1..column.len() {
+        let cost = usize::from(query(i - 1) != c);
+        let cell = (diagonal + cost).min(column[i] + 1).min(column[i - 1] + 1);
+        diagonal = column[i];
+        column[i] = cell;
+    }
+}
+
+impl Fuzzy {
+    pub fn new(query: &str, max_distance: usize, case_insensitive: bool) -> Fuzzy {
+        let fold = |c| {
+            if case_insensitive {
+                casefold::simple_fold(c)
+            } else {
+                c
+            }
+        };
+        Fuzzy {
+            query: query.chars().map(fold).collect(),
+            max_distance,
+            case_insensitive,
+        }
+    }
+
+    fn chars<'t>(&self, text: &'t str) -> impl DoubleEndedIterator<Item = (usize, char)> + 't {
+        let case_insensitive = self.case_insensitive;
+        text.char_indices().map(move |(i, c)| {
+            if case_insensitive {
+                (i, casefold::simple_fold(c))
+            } else {
+                (i, c)
+            }
+        })
+    }
+
+    /// The smallest number of edits with which the query occurs in `text`,
+    /// if it is within the limit.
+    pub fn distance(&self, text: &str) -> Option<usize> {
+        if self.query.is_empty() {
+            return Some(0);
+        }
+
+        let mut column = Column::new(&self.query);
+        let best = self
+            .chars(text)
+            .map(|(_, c)| column.step(&self.query, c))
+            .min()?;
+        (best <= self.max_distance).then_some(best)
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.distance(text).is_some()
+    }
+
+    /// Find the first match at or after byte offset `start`, with its
+    /// distance.
+    ///
+    /// The match starts as far left as possible for the first place where
+    /// a match within the limit ends. From that start it takes the end with
+    /// the fewest edits, and the furthest of those, so that `frog` finds all
+    /// of `froog` rather than `fro` one edit away.
+    pub fn find_at(&self, text: &str, start: usize) -> Option<(Range<usize>, usize)> {
+        if self.query.is_empty() {
+            return Some((start..start, 0));
+        }
+
+        let mut column = Column::new(&self.query);
+        let (first_end, distance) = self.chars(&text[start..]).find_map(|(i, c)| {
+            let distance = column.step(&self.query, c);
+            (distance <= self.max_distance).then_some((start + i + c.len_utf8(), distance))
+        })?;
+
+        let match_start = self.start_of(text, start, first_end, distance);
+        let (end, distance) = self.end_of(text, match_start, first_end);
+        Some((match_start..end, distance))
+    }
+
+    /// The leftmost start of a match ending at `end` with `distance` edits,
+    /// comparing the reversed query with the text before `end`.
+    fn start_of(&self, text: &str, min_start: usize, end: usize, distance: usize) -> usize {
+        let m = self.query.len();
+        let mut column: Vec<usize> = (0..=m).collect();
+        let mut best = end;
+
+        for (i, c) in self
+            .chars(&text[min_start..end])
+            .rev()
+            .take(m + self.max_distance)
+        {
+            update(&mut column, c, true, |j| self.query[m - 1 - j]);
+            if column[m] == distance {
+                best = min_start + i;
+            }
+        }
+
+        best
+    }
+
+    /// The best end, at or after `min_end`, of a match starting at `start`,
+    /// with its distance.
+    fn end_of(&self, text: &str, start: usize, min_end: usize) -> (usize, usize) {
+        let m = self.query.len();
+        let mut column: Vec<usize> = (0..=m).collect();
+        let mut best = (min_end, usize::MAX);
+
+        for (i, c) in self.chars(&text[start..]).take(m + self.max_distance) {
+            update(&mut column, c, true, |j| self.query[j]);
+            let end = start + i + c.len_utf8();
+            if end >= min_end && column[m] <= best.1 {
+                best = (end, column[m]);
+            }
+        }
+
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find<'t>(query: &str, max_distance: usize, text: &'t str) -> Option<(&'t str, usize)> {
+        Fuzzy::new(query, max_distance, false)
+            .find_at(text, 0)
+            .map(|(range, distance)| (&text[range], distance))
+    }
+
+    #[test]
+    fn finds_approximate_matches() {
+        assert_eq!(Some(("frog", 0)), find("frog", 1, "like a frog"));
+        assert_eq!(Some(("frg", 1)), find("frog", 1, "like a frg!"));
+        assert_eq!(Some(("froog", 1)), find("frog", 1, "like a froog"));
+        assert_eq!(Some(("fog", 1)), find("frog", 2, "a fog, a frog"));
+        assert_eq!(Some(("recieve", 2)), find("receive", 2, "we recieve it"));
+        assert_eq!(None, find("receive", 1, "we recieve it"));
+        assert_eq!(Some(("frg", 1)), find("frog", 1, "frg frg"));
+        assert_eq!(Some(("", 0)), find("", 0, "anything"));
+
+        let fuzzy = Fuzzy::new("ÉTÉ", 1, true);
+        assert_eq!(Some(0), fuzzy.distance("un été chaud"));
+        assert_eq!(Some(1), fuzzy.distance("l'etÉ"));
+        assert_eq!(None, fuzzy.distance("l'ete"));
+        assert_eq!(Some((3..8, 0)), fuzzy.find_at("un été", 0));
+    }
+
+    #[test]
+    fn long_queries_agree_with_short_ones() {
+        let query = "approximate ".repeat(6);
+        let mut text = query.replace("mat", "nat").replacen("pp", "p", 1);
+        text.insert_str(0, "some ");
+        assert!(query.chars().count() > 64);
+
+        assert_eq!(None, Fuzzy::new(&query, 1, false).distance(&text));
+        let (range, distance) = Fuzzy::new(&query, 8, false).find_at(&text, 0).unwrap();
+        assert_eq!((5..text.len(), 7), (range, distance));
+
+        let short = &query[..24];
+        let bits = Fuzzy::new(short, 3, false);
+        let mut cells = Column::Cells((0..=24).collect());
+        let chars: Vec<char> = short.chars().collect();
+        let expected = text.chars().map(|c| cells.step(&chars, c)).min();
+        assert_eq!(expected, bits.distance(&text));
+    }
+}
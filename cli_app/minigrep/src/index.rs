@@ -0,0 +1,497 @@
+//! A trigram index of a directory tree, for `minigrep index` and `--index`.
+//!
+//! For each file the index records every sequence of three bytes in it,
+//! with ASCII letters lowercased, along with the file's size and when it was
+//! modified. A search then only reads the files that have the trigrams of
+//! the strings every match must contain. Files that changed since they were
+//! indexed are always read, and indexing again only reads those.
+//!
+//! The index is kept in `.minigrep-index` at the top of the tree. It starts
+//! with a line naming the format, followed by the number of files and then,
+//! for each file in path order: the path relative to the top, the size, the
+//! modification time in seconds and nanoseconds, and either 0 for a file
+//! that is always read or 1 and the number of trigrams followed by the
+//! differences between them in ascending order. Numbers are LEB128.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::UNIX_EPOCH;
+
+use crate::archive::Format;
+use crate::casefold;
+use crate::encoding;
+use crate::matcher::Matcher;
+use crate::parallel;
+use crate::walk::Walk;
+use crate::Config;
+
+pub const FILE_NAME: &str = ".minigrep-index";
+
+const HEADER: &[u8] = b"minigrep index 1\n";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Index {
+    files: HashMap<PathBuf, Entry>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Entry {
+    len: u64,
+    modified: (u64, u32),
+    /// Sorted, or `None` for files that are always read, such as compressed
+    /// ones whose trigrams are not those of what is searched.
+    trigrams: Option<Vec<u32>>,
+}
+
+impl Entry {
+    fn is_current(&self, metadata: &Metadata) -> bool {
+        self.len == metadata.len() && self.modified == modified(metadata)
+    }
+}
+
+/// What `update` did.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Update {
+    pub files: usize,
+    pub updated: usize,
+    pub removed: usize,
+}
+
+/// Index the files below `root`, reading only those that changed since it
+/// was last indexed.
+pub fn update(root: &Path, threads: usize) -> Result<Update, Box<dyn Error>> {
+    if !root.is_dir() {
+        return Err(format!("{}: not a directory", root.display()).into());
+    }
+    let path = root.join(FILE_NAME);
+    let old = match Index::read(&path) {
+        Ok(index) => index,
+        // Build a new one over an index that is missing or unreadable.
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::InvalidData
+            ) =>
+        {
+            Index::default()
+        }
+        Err(e) => return Err(with_path(&path, e).into()),
+    };
+
+    let mut index = Index::default();
+    let mut update = Update::default();
+    parallel::map_ordered(
+        Walk::new(&[root]),
+        threads,
+        |file| -> Result<(PathBuf, Entry, bool), Box<dyn Error + Send + Sync>> {
+            let file = file?;
+            let key = file.strip_prefix(root).unwrap_or(&file).to_path_buf();
+            let metadata = fs::metadata(&file).map_err(|e| with_path(&file, e))?;
+            if let Some(entry) = old.files.get(&key) {
+                if entry.is_current(&metadata) {
+                    return Ok((key, entry.clone(), false));
+                }
+            }
+
+            let contents = fs::read(&file).map_err(|e| with_path(&file, e))?;
+            let trigrams = match Format::detect(&contents) {
+                Some(_) => None,
+                None => Some(trigrams(encoding::decode(None, &contents).as_bytes())),
+            };
+            let entry = Entry {
+                len: metadata.len(),
+                modified: modified(&metadata),
+                trigrams,
+            };
+            Ok((key, entry, true))
+        },
+        |result| -> Result<(), Box<dyn Error>> {
+            match result {
+                Ok((key, entry, updated)) => {
+                    update.files += 1;
+                    if updated {
+                        update.updated += 1;
+                    }
+                    index.files.insert(key, entry);
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            }
+        },
+    )?;
+
+    update.removed = old
+        .files
+        .keys()
+        .filter(|key| !index.files.contains_key(*key))
+        .count();
+    index.write(&path).map_err(|e| with_path(&path, e))?;
+    Ok(update)
+}
+
+impl Index {
+    fn read(path: &Path) -> io::Result<Index> {
+        let bytes = fs::read(path)?;
+        let mut input = bytes
+            .strip_prefix(HEADER)
+            .ok_or_else(|| invalid("not a minigrep index"))?;
+
+        let mut files = HashMap::new();
+        for _ in 0..number(&mut input)? {
+            let len = number(&mut input)? as usize;
+            if input.len() < len {
+                return Err(invalid("truncated index"));
+            }
+            let (name, rest) = input.split_at(len);
+            input = rest;
+            let name = String::from_utf8(name.to_vec()).map_err(|_| invalid("bad path"))?;
+
+            let len = number(&mut input)?;
+            let modified = (number(&mut input)?, number(&mut input)? as u32);
+            let trigrams = match number(&mut input)? {
+                0 => None,
+                _ => {
+                    let mut trigrams = Vec::new();
+                    let mut trigram = 0;
+                    for _ in 0..number(&mut input)? {
+                        trigram += number(&mut input)? as u32;
+                        trigrams.push(trigram);
+                    }
+                    Some(trigrams)
+                }
+            };
+            files.insert(
+                PathBuf::from(name),
+                Entry {
+                    len,
+                    modified,
+                    trigrams,
+                },
+            );
+        }
+
+        Ok(Index { files })
+    }
+
+    /// Write the index to `path`, replacing what was there only once it is
+    /// complete.
+    fn write(&self, path: &Path) -> io::Result<()> {
+        let mut files: Vec<_> = self
+            .files
+            .iter()
+            .filter_map(|(key, entry)| Some((key.to_str()?, entry)))
+            .collect();
+        files.sort_by_key(|&(key, _)| key);
+
+        let mut out = HEADER.to_vec();
+        put_number(&mut out, files.len() as u64);
+        for (key, entry) in files {
+            put_number(&mut out, key.len() as u64);
+            out.extend_from_slice(key.as_bytes());
+            put_number(&mut out, entry.len);
+            put_number(&mut out, entry.modified.0);
+            put_number(&mut out, entry.modified.1.into());
+            match &entry.trigrams {
+                None => put_number(&mut out, 0),
+                Some(trigrams) => {
+                    put_number(&mut out, 1);
+                    put_number(&mut out, trigrams.len() as u64);
+                    let mut last = 0;
+                    for &trigram in trigrams {
+                        put_number(&mut out, (trigram - last).into());
+                        last = trigram;
+                    }
+                }
+            }
+        }
+
+        let temp = path.with_file_name(format!("{}.{}", FILE_NAME, process::id()));
+        let result = File::create(&temp).and_then(|mut file| {
+            file.write_all(&out)?;
+            file.sync_all()?;
+            fs::rename(&temp, path)
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        result
+    }
+
+    /// Whether the file at `path`, which is `key` in the index, may have a
+    /// match for `query`.
+    fn may_match(&self, key: &Path, path: &Path, query: &Query) -> bool {
+        let Some(entry) = self.files.get(key) else {
+            return true;
+        };
+        let Some(trigrams) = &entry.trigrams else {
+            return true;
+        };
+        match fs::metadata(path) {
+            Ok(metadata) if entry.is_current(&metadata) => query.matches(trigrams),
+            _ => true,
+        }
+    }
+}
+
+/// The trigrams a file has to have to be worth reading: all of those in at
+/// least one of the lists, or with `None`, nothing is known.
+#[derive(Clone, Debug, PartialEq)]
+struct Query(Option<Vec<Vec<u32>>>);
+
+impl Query {
+    fn new(config: &Config, matcher: &Matcher) -> Query {
+        // Lines without a match and typos can be anywhere, and the index
+        // only knows files decoded as UTF-8 or as their byte order mark says.
+        if config.invert_match || config.fuzzy.is_some() || config.encoding.is_some() {
+            return Query(None);
+        }
+
+        let required = match matcher {
+            Matcher::Regex(regex) => regex.required().to_vec(),
+            _ if config.case_sensitive => config
+                .patterns
+                .iter()
+                .map(|pattern| vec![pattern.clone()])
+                .collect(),
+            // Ignoring case, only the parts that match nothing but
+            // themselves in either case can be looked up.
+            _ => config
+                .patterns
+                .iter()
+                .map(|pattern| {
+                    pattern
+                        .split(|c| !casefold::is_ascii_only(c))
+                        .map(String::from)
+                        .collect()
+                })
+                .collect(),
+        };
+
+        let mut clauses = Vec::new();
+        for strings in required {
+            let mut clause: Vec<u32> = strings
+                .iter()
+                .flat_map(|string| trigrams(string.as_bytes()))
+                .collect();
+            if clause.is_empty() {
+                return Query(None);
+            }
+            clause.sort_unstable();
+            clause.dedup();
+            clauses.push(clause);
+        }
+        Query(Some(clauses))
+    }
+
+    fn matches(&self, trigrams: &[u32]) -> bool {
+        match &self.0 {
+            None => true,
+            Some(clauses) => clauses.iter().any(|clause| {
+                clause
+                    .iter()
+                    .all(|trigram| trigrams.binary_search(trigram).is_ok())
+            }),
+        }
+    }
+}
+
+/// Decides which of the files a search with `--index` comes across to read.
+pub struct Candidates {
+    query: Query,
+    indexes: Vec<Index>,
+    /// The paths searched, where they are in the tree of their index, and
+    /// which index that is.
+    roots: Vec<(PathBuf, PathBuf, usize)>,
+}
+
+impl Candidates {
+    /// Find and read the indexes of the paths searched, in the directories
+    /// they are in or above them.
+    pub fn new(config: &Config, matcher: &Matcher) -> io::Result<Candidates> {
+        let mut tops: Vec<PathBuf> = Vec::new();
+        let mut indexes = Vec::new();
+        let mut roots = Vec::new();
+
+        for path in config.paths.iter().filter(|path| *path != "-") {
+            let full = fs::canonicalize(path).map_err(|e| with_path(Path::new(path), e))?;
+            let top = full
+                .ancestors()
+                .skip(usize::from(!full.is_dir()))
+                .find(|dir| dir.join(FILE_NAME).is_file())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!(
+                            "{}: no index found; create one with `minigrep index DIR`",
+                            path
+                        ),
+                    )
+                })?;
+
+            let i = match tops.iter().position(|known| known == top) {
+                Some(i) => i,
+                None => {
+                    let file = top.join(FILE_NAME);
+                    indexes.push(Index::read(&file).map_err(|e| with_path(&file, e))?);
+                    tops.push(top.to_path_buf());
+                    tops.len() - 1
+                }
+            };
+            let key = full.strip_prefix(top).unwrap().to_path_buf();
+            roots.push((PathBuf::from(path), key, i));
+        }
+
+        Ok(Candidates {
+            query: Query::new(config, matcher),
+            indexes,
+            roots,
+        })
+    }
+
+    /// Whether the file at `path` has to be read.
+    pub fn may_match(&self, path: &Path) -> bool {
+        if self.query.0.is_none() {
+            return true;
+        }
+
+        for (root, key, i) in &self.roots {
+            if let Ok(rest) = path.strip_prefix(root) {
+                return self.indexes[*i].may_match(&key.join(rest), path, &self.query);
+            }
+        }
+        true
+    }
+}
+
+/// The sorted trigrams of `text`, with ASCII letters lowercased.
+fn trigrams(text: &[u8]) -> Vec<u32> {
+    let mut trigrams: Vec<u32> = text
+        .windows(3)
+        .map(|w| {
+            u32::from(w[0].to_ascii_lowercase()) << 16
+                | u32::from(w[1].to_ascii_lowercase()) << 8
+                | u32::from(w[2].to_ascii_lowercase())
+        })
+        .collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+fn modified(metadata: &Metadata) -> (u64, u32) {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or((0, 0), |time| (time.as_secs(), time.subsec_nanos()))
+}
+
+fn put_number(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn number(input: &mut &[u8]) -> io::Result<u64> {
+    let mut n = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = input
+            .split_first()
+            .ok_or_else(|| invalid("truncated index"))?;
+        *input = rest;
+        n |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(invalid("bad number in index"))
+}
+
+fn with_path(path: &Path, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn query(patterns: &[&str], case_sensitive: bool, regex: bool) -> Query {
+        let config = Config {
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+            case_sensitive,
+            regex,
+            ..Config::default()
+        };
+        Query::new(&config, &Matcher::new(&config).unwrap())
+    }
+
+    #[test]
+    fn needs_the_trigrams_of_every_pattern() {
+        let text = trigrams(b"Trust rust, the crab, is proper code.");
+        assert!(query(&["rust"], true, false).matches(&text));
+        assert!(query(&["RUST"], true, false).matches(&text));
+        assert!(!query(&["rusty"], true, false).matches(&text));
+        assert!(query(&["rusty", "crab"], true, false).matches(&text));
+        assert!(query(&[r"cra\w|nothing"], true, true).matches(&text));
+        assert!(!query(&[r"\w+ rusty"], true, true).matches(&text));
+
+        // Too short to know anything from.
+        assert_eq!(Query(None), query(&["ru"], true, false));
+        assert_eq!(Query(None), query(&["rusty", ".*"], true, true));
+        // `k` and `s` may be the Kelvin sign and long s.
+        assert_eq!(Query(None), query(&["kiss"], false, false));
+        assert!(query(&["PROPER CODE"], false, false).matches(&text));
+        assert!(!query(&["PROPER MODE"], false, false).matches(&text));
+    }
+
+    #[test]
+    fn updates_only_changed_files() {
+        let dir = env::temp_dir().join(format!("minigrep-index-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/lib.rs"), "fn search() {}\n").unwrap();
+        fs::write(dir.join("notes.txt"), "nothing here\n").unwrap();
+
+        let first = update(&dir, 2).unwrap();
+        assert_eq!((2, 2, 0), (first.files, first.updated, first.removed));
+
+        // Far enough apart for the modification time to differ.
+        thread::sleep(Duration::from_millis(20));
+        fs::write(dir.join("notes.txt"), "search me\n").unwrap();
+        fs::write(dir.join("new.txt"), "new\n").unwrap();
+        fs::remove_file(dir.join("src/lib.rs")).unwrap();
+        let second = update(&dir, 2).unwrap();
+        assert_eq!((2, 2, 1), (second.files, second.updated, second.removed));
+
+        let config = Config {
+            patterns: vec!["search".to_string()],
+            paths: vec![
+                dir.join("notes.txt").display().to_string(),
+                dir.display().to_string(),
+            ],
+            ..Config::default()
+        };
+        let candidates = Candidates::new(&config, &Matcher::new(&config).unwrap()).unwrap();
+        assert!(candidates.may_match(&dir.join("notes.txt")));
+        assert!(!candidates.may_match(&dir.join("new.txt")));
+        // Changed since, so it has to be read.
+        fs::write(dir.join("new.txt"), "newer\n").unwrap();
+        assert!(candidates.may_match(&dir.join("new.txt")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
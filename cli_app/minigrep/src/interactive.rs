@@ -0,0 +1,538 @@
+//! `--interactive`: a terminal UI that searches the files again each time
+//! the query changes, with the same matching as a search from the command
+//! line, and opens the selected line in an editor.
+//!
+//! The files are read once at the start, and again only after they were
+//! opened in the editor, so typing never waits on the disk.
+
+#[cfg(target_os = "linux")]
+mod terminal;
+
+use std::error::Error;
+use std::fs;
+use std::iter;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use crate::archive::Format;
+use crate::encoding;
+use crate::matcher::Matcher;
+use crate::printer::{Color, FILENAME, LINE_NUMBER, MATCH, RESET, SEPARATOR};
+use crate::regex;
+use crate::walk::Walk;
+use crate::{search_lines, Config};
+
+/// Searching stops after this many lines, to keep up with the typing.
+const MAX_HITS: usize = 10_000;
+
+const REVERSE: &str = "\x1b[7m";
+const CLEAR_LINE: &str = "\x1b[K";
+
+const HELP: &str = "↑↓ move  Enter open  Esc quit";
+
+#[cfg(target_os = "linux")]
+pub fn interactive(config: &Config) -> Result<(), Box<dyn Error>> {
+    use std::{env, process};
+
+    let mut files = load(config);
+    let mut terminal = terminal::Terminal::new()?;
+    let color = config.color != Color::Never;
+    let mut state = State::new(config.patterns.first().cloned().unwrap_or_default());
+    state.search(config, &files);
+
+    let mut input = Vec::new();
+    let mut buffer = [0; 256];
+    let mut drawn = None;
+
+    loop {
+        let (rows, cols) = terminal.size();
+        if drawn != Some((rows, cols)) {
+            state.scroll_to_selected(rows.saturating_sub(2));
+            terminal.write(&state.render(&files, rows, cols, color))?;
+            drawn = Some((rows, cols));
+        }
+
+        let len = terminal.read(&mut buffer)?;
+        if len == 0 {
+            continue;
+        }
+        input.extend_from_slice(&buffer[..len]);
+        let (keys, used) = parse_keys(&input);
+        input.drain(..used);
+
+        let page = rows.saturating_sub(2).max(1) as isize;
+        // Typing is searched for once all of it has been read, or before a
+        // key that moves through the hits.
+        let mut stale = false;
+        for key in keys {
+            if stale && !matches!(key, Key::Char(_) | Key::Backspace | Key::Clear) {
+                state.search(config, &files);
+                stale = false;
+            }
+            match key {
+                Key::Char(c) => state.query.push(c),
+                Key::Backspace => {
+                    state.query.pop();
+                }
+                Key::Clear => state.query.clear(),
+                Key::Up => state.move_by(-1),
+                Key::Down => state.move_by(1),
+                Key::PageUp => state.move_by(-page),
+                Key::PageDown => state.move_by(page),
+                Key::Enter => {
+                    let Some(hit) = state.hits.get(state.selected) else {
+                        continue;
+                    };
+                    let file = &mut files[hit.file];
+                    let editor = env::var("VISUAL")
+                        .or_else(|_| env::var("EDITOR"))
+                        .unwrap_or_else(|_| "vi".to_string());
+                    let mut words = editor.split_whitespace();
+
+                    terminal.suspend()?;
+                    let status = process::Command::new(words.next().unwrap_or("vi"))
+                        .args(words)
+                        .arg(format!("+{}", hit.number))
+                        .arg(&file.path)
+                        .status();
+                    terminal.resume()?;
+
+                    // The file may have changed, or be gone.
+                    file.contents = fs::read(&file.path)
+                        .map(|bytes| encoding::decode(config.encoding, &bytes).into_owned())
+                        .unwrap_or_default();
+                    state.search(config, &files);
+                    if let Err(e) = status {
+                        state.error = Some(format!("cannot run {}: {}", editor, e));
+                    }
+                }
+                Key::Quit => return Ok(()),
+            }
+            if matches!(key, Key::Char(_) | Key::Backspace | Key::Clear) {
+                state.selected = 0;
+                stale = true;
+            }
+        }
+        if stale {
+            state.search(config, &files);
+        }
+        drawn = None;
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn interactive(_: &Config) -> Result<(), Box<dyn Error>> {
+    Err("--interactive is only supported on Linux".into())
+}
+
+struct Loaded {
+    path: PathBuf,
+    contents: String,
+}
+
+/// Read the files to search, leaving out binary and compressed ones.
+fn load(config: &Config) -> Vec<Loaded> {
+    let mut files = Vec::new();
+    for path in Walk::new(&config.paths) {
+        let path = match path {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("minigrep: {}", e);
+                continue;
+            }
+        };
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("minigrep: {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        if Format::detect(&bytes).is_some() {
+            continue;
+        }
+        let contents = encoding::decode(config.encoding, &bytes).into_owned();
+        if !contents.contains('\0') {
+            files.push(Loaded { path, contents });
+        }
+    }
+    files
+}
+
+/// A line with a match.
+struct Hit {
+    /// Index into the loaded files.
+    file: usize,
+    number: usize,
+    /// Byte range of the line in the file.
+    line: Range<usize>,
+    ranges: Vec<Range<usize>>,
+}
+
+struct State {
+    query: String,
+    hits: Vec<Hit>,
+    /// Why the query could not be searched for, such as a regex error.
+    error: Option<String>,
+    selected: usize,
+    /// The first hit on the screen.
+    scroll: usize,
+}
+
+impl State {
+    fn new(query: String) -> State {
+        State {
+            query,
+            hits: Vec::new(),
+            error: None,
+            selected: 0,
+            scroll: 0,
+        }
+    }
+
+    /// Search for the query, keeping the old hits if it is not valid.
+    fn search(&mut self, config: &Config, files: &[Loaded]) {
+        match search(config, files, &self.query) {
+            Ok(hits) => {
+                self.hits = hits;
+                self.error = None;
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+        self.selected = self.selected.min(self.hits.len().saturating_sub(1));
+    }
+
+    fn move_by(&mut self, rows: isize) {
+        let last = self.hits.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(rows).min(last);
+    }
+
+    /// Scroll so that the selected hit is among the `rows` shown.
+    fn scroll_to_selected(&mut self, rows: usize) {
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if rows > 0 && self.selected >= self.scroll + rows {
+            self.scroll = self.selected + 1 - rows;
+        }
+    }
+
+    /// Draw the whole screen: the query on the first row, the hits, and a
+    /// status line at the bottom.
+    fn render(&self, files: &[Loaded], rows: usize, cols: usize, color: bool) -> String {
+        let mut screen = String::from("\x1b[H");
+        let prompt_width = push_visible(&mut screen, "> ", &[], cols, "", false);
+        let query_width = push_visible(
+            &mut screen,
+            &self.query,
+            &[],
+            cols.saturating_sub(prompt_width),
+            "",
+            false,
+        );
+        screen.push_str(CLEAR_LINE);
+
+        let shown = rows.saturating_sub(2);
+        for index in self.scroll..self.scroll + shown {
+            screen.push_str("\r\n");
+            let Some(hit) = self.hits.get(index) else {
+                screen.push_str(CLEAR_LINE);
+                continue;
+            };
+
+            let base = if index == self.selected { REVERSE } else { "" };
+            screen.push_str(base);
+            let file = &files[hit.file];
+            let colored = |text: &str, code: &str| {
+                if color {
+                    format!("{}{}{}{}", code, text, RESET, base)
+                } else {
+                    text.to_string()
+                }
+            };
+            let mut prefix = colored(&file.path.display().to_string(), FILENAME);
+            prefix.push_str(&colored(":", SEPARATOR));
+            prefix.push_str(&colored(&hit.number.to_string(), LINE_NUMBER));
+            prefix.push_str(&colored(":", SEPARATOR));
+            screen.push_str(&prefix);
+
+            let used =
+                file.path.display().to_string().chars().count() + hit.number.to_string().len() + 2;
+            let line = &file.contents[hit.line.clone()];
+            let used = used
+                + push_visible(
+                    &mut screen,
+                    line,
+                    &hit.ranges,
+                    cols.saturating_sub(used),
+                    base,
+                    color,
+                );
+            if base.is_empty() {
+                screen.push_str(CLEAR_LINE);
+            } else {
+                // Fill the row, so the whole of it shows as selected.
+                screen.extend(iter::repeat_n(' ', cols.saturating_sub(used)));
+                screen.push_str(RESET);
+            }
+        }
+
+        if rows > 1 {
+            screen.push_str("\r\n");
+            let status = match &self.error {
+                Some(error) => error.clone(),
+                None if self.query.is_empty() => "Type to search".to_string(),
+                None => {
+                    let files = 1 + self
+                        .hits
+                        .windows(2)
+                        .filter(|w| w[0].file != w[1].file)
+                        .count();
+                    let more = if self.hits.len() == MAX_HITS { "+" } else { "" };
+                    match self.hits.len() {
+                        0 => "No matches".to_string(),
+                        1 => "1 match".to_string(),
+                        n => format!(
+                            "{}{} matches in {} {}",
+                            n,
+                            more,
+                            files,
+                            plural(files, "file")
+                        ),
+                    }
+                }
+            };
+            let width = push_visible(&mut screen, &status, &[], cols, "", false);
+            if width + HELP.chars().count() + 2 <= cols {
+                let gap = cols - width - HELP.chars().count();
+                screen.extend(iter::repeat_n(' ', gap));
+                screen.push_str(HELP);
+            } else {
+                screen.push_str(CLEAR_LINE);
+            }
+        }
+
+        // Leave the cursor where the query is typed.
+        screen.push_str(&format!("\x1b[1;{}H", prompt_width + query_width + 1));
+        screen
+    }
+}
+
+fn plural(n: usize, word: &str) -> String {
+    if n == 1 {
+        word.to_string()
+    } else {
+        format!("{}s", word)
+    }
+}
+
+/// The lines of `files` with a match for `query`, searched for as `config`
+/// says, as with -i, -E, -w or --fuzzy.
+fn search(config: &Config, files: &[Loaded], query: &str) -> Result<Vec<Hit>, regex::Error> {
+    let mut hits = Vec::new();
+    if query.is_empty() {
+        return Ok(hits);
+    }
+
+    let config = Config {
+        patterns: vec![query.to_string()],
+        case_sensitive: config.case_sensitive,
+        regex: config.regex,
+        word_regexp: config.word_regexp,
+        fuzzy: config.fuzzy,
+        ..Config::default()
+    };
+    let matcher = Matcher::new(&config)?;
+    for (index, file) in files.iter().enumerate() {
+        for found in search_lines(&matcher, &file.contents) {
+            hits.push(Hit {
+                file: index,
+                number: found.number,
+                line: found.offset..found.offset + found.line.len(),
+                ranges: found.ranges,
+            });
+            if hits.len() == MAX_HITS {
+                return Ok(hits);
+            }
+        }
+    }
+    Ok(hits)
+}
+
+/// Append as much of `text` as fits in `width` columns to `screen`, with
+/// `ranges` highlighted and control characters such as tabs shown as
+/// spaces, and return the columns used. Each character is taken to be one
+/// column wide. `base` is the style to go back to after a highlight.
+fn push_visible(
+    screen: &mut String,
+    text: &str,
+    ranges: &[Range<usize>],
+    width: usize,
+    base: &str,
+    color: bool,
+) -> usize {
+    let mut used = 0;
+    let mut highlighted = false;
+
+    for (i, c) in text.char_indices() {
+        if used == width {
+            break;
+        }
+        let in_match = color && ranges.iter().any(|range| range.contains(&i));
+        if in_match != highlighted {
+            screen.push_str(if in_match { MATCH } else { RESET });
+            if !in_match {
+                screen.push_str(base);
+            }
+            highlighted = in_match;
+        }
+        screen.push(if c.is_control() { ' ' } else { c });
+        used += 1;
+    }
+    if highlighted {
+        screen.push_str(RESET);
+        screen.push_str(base);
+    }
+    used
+}
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Char(char),
+    Backspace,
+    /// Ctrl-U, to start the query again.
+    Clear,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Enter,
+    Quit,
+}
+
+/// The keys in what was typed, and how much of it they took up; the rest
+/// is the start of a character still to come.
+fn parse_keys(input: &[u8]) -> (Vec<Key>, usize) {
+    let mut keys = Vec::new();
+    let mut i = 0;
+
+    while i < input.len() {
+        let key = match input[i] {
+            0x1b => match input.get(i + 1) {
+                // Escape on its own, as the sequences come all at once.
+                None => Key::Quit,
+                Some(b'[' | b'O') => {
+                    let end = input[i + 2..]
+                        .iter()
+                        .position(|b| (0x40..=0x7e).contains(b))
+                        .map_or(input.len(), |at| i + 2 + at + 1);
+                    let sequence = &input[i + 2..end];
+                    i = end;
+                    match sequence {
+                        b"A" => keys.push(Key::Up),
+                        b"B" => keys.push(Key::Down),
+                        b"5~" => keys.push(Key::PageUp),
+                        b"6~" => keys.push(Key::PageDown),
+                        _ => {}
+                    }
+                    continue;
+                }
+                Some(_) => Key::Quit,
+            },
+            0x03 => Key::Quit,
+            b'\r' | b'\n' => Key::Enter,
+            0x7f | 0x08 => Key::Backspace,
+            0x15 => Key::Clear,
+            0x10 => Key::Up,
+            0x0e => Key::Down,
+            byte if byte < 0x20 => {
+                i += 1;
+                continue;
+            }
+            byte => {
+                let len = match byte {
+                    0xc0..=0xdf => 2,
+                    0xe0..=0xef => 3,
+                    0xf0..=0xf7 => 4,
+                    _ => 1,
+                };
+                if i + len > input.len() {
+                    break;
+                }
+                let c = std::str::from_utf8(&input[i..i + len])
+                    .ok()
+                    .and_then(|s| s.chars().next());
+                i += len;
+                if let Some(c) = c {
+                    keys.push(Key::Char(c));
+                }
+                continue;
+            }
+        };
+        keys.push(key);
+        i += 1;
+    }
+
+    (keys, i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_keys() {
+        let (keys, used) = parse_keys(b"a\xc3\xa9\x1b[A\x1b[6~\x7f\r\x15\xe2\x82");
+        assert_eq!(
+            vec![
+                Key::Char('a'),
+                Key::Char('é'),
+                Key::Up,
+                Key::PageDown,
+                Key::Backspace,
+                Key::Enter,
+                Key::Clear,
+            ],
+            keys
+        );
+        assert_eq!(13, used);
+        assert_eq!((vec![Key::Quit], 1), parse_keys(b"\x1b"));
+    }
+
+    #[test]
+    fn renders_hits_for_the_query() {
+        let files = vec![
+            Loaded {
+                path: PathBuf::from("a.txt"),
+                contents: "one fish\ntwo fish\n".to_string(),
+            },
+            Loaded {
+                path: PathBuf::from("b.txt"),
+                contents: "red\tfish, blue fish, and a very long line\n".to_string(),
+            },
+        ];
+        let config = Config {
+            regex: true,
+            ..Config::default()
+        };
+        let mut state = State::new("fish".to_string());
+        state.search(&config, &files);
+        state.move_by(5);
+        assert_eq!(2, state.selected);
+        state.scroll_to_selected(2);
+        assert_eq!(1, state.scroll);
+
+        assert_eq!(
+            "\x1b[H> fish\x1b[K\
+             \r\na.txt:2:two fish\x1b[K\
+             \r\n\x1b[7mb.txt:1:red fish, blue fish,\x1b[0m\
+             \r\n3 matches in 2 files\x1b[K\
+             \x1b[1;7H",
+            state.render(&files, 4, 28, false)
+        );
+
+        state.query.push('(');
+        state.search(&config, &files);
+        assert_eq!(3, state.hits.len());
+        assert!(state.error.is_some());
+    }
+}
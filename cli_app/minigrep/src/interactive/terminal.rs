@@ -0,0 +1,138 @@
+//! Just enough of termios to read keys as they are pressed from the
+//! controlling terminal and draw on its alternate screen, which leaves
+//! standard input and output free.
+
+use std::ffi::{c_int, c_uint, c_ulong, c_ushort};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+
+const TCSANOW: c_int = 0;
+const TIOCGWINSZ: c_ulong = 0x5413;
+const VTIME: usize = 5;
+const VMIN: usize = 6;
+
+/// Switch to the alternate screen and back, so that what was on the
+/// terminal before is there again afterwards.
+const ENTER: &str = "\x1b[?1049h";
+const LEAVE: &str = "\x1b[?1049l";
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Termios {
+    c_iflag: c_uint,
+    c_oflag: c_uint,
+    c_cflag: c_uint,
+    c_lflag: c_uint,
+    c_line: u8,
+    c_cc: [u8; 32],
+    c_ispeed: c_uint,
+    c_ospeed: c_uint,
+}
+
+#[repr(C)]
+struct Winsize {
+    ws_row: c_ushort,
+    ws_col: c_ushort,
+    ws_xpixel: c_ushort,
+    ws_ypixel: c_ushort,
+}
+
+extern "C" {
+    fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+    fn tcsetattr(fd: c_int, action: c_int, termios: *const Termios) -> c_int;
+    fn cfmakeraw(termios: *mut Termios);
+    fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+}
+
+pub struct Terminal {
+    tty: File,
+    /// The settings to put back when done.
+    saved: Termios,
+}
+
+impl Terminal {
+    pub fn new() -> io::Result<Terminal> {
+        let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+        let mut saved = Termios {
+            c_iflag: 0,
+            c_oflag: 0,
+            c_cflag: 0,
+            c_lflag: 0,
+            c_line: 0,
+            c_cc: [0; 32],
+            c_ispeed: 0,
+            c_ospeed: 0,
+        };
+        // SAFETY: `saved` is a `termios` for the call to fill in.
+        if unsafe { tcgetattr(tty.as_raw_fd(), &mut saved) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut terminal = Terminal { tty, saved };
+        terminal.resume()?;
+        Ok(terminal)
+    }
+
+    /// The number of rows and columns, or the traditional 24 by 80 if the
+    /// terminal does not say.
+    pub fn size(&self) -> (usize, usize) {
+        let mut size = Winsize {
+            ws_row: 0,
+            ws_col: 0,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        // SAFETY: TIOCGWINSZ fills in the `winsize` it is given.
+        let result = unsafe { ioctl(self.tty.as_raw_fd(), TIOCGWINSZ, &mut size as *mut Winsize) };
+        if result < 0 || size.ws_row == 0 || size.ws_col == 0 {
+            return (24, 80);
+        }
+        (size.ws_row as usize, size.ws_col as usize)
+    }
+
+    /// Read what was typed, waiting up to a tenth of a second for it, so
+    /// that nothing read means no key was pressed.
+    pub fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self.tty.read(buffer) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => Ok(0),
+            result => result,
+        }
+    }
+
+    pub fn write(&mut self, text: &str) -> io::Result<()> {
+        self.tty.write_all(text.as_bytes())?;
+        self.tty.flush()
+    }
+
+    /// Hand the terminal back as it was, as for running an editor.
+    pub fn suspend(&mut self) -> io::Result<()> {
+        self.write(LEAVE)?;
+        self.set(&self.saved)
+    }
+
+    /// Take the terminal again after `suspend`.
+    pub fn resume(&mut self) -> io::Result<()> {
+        let mut raw = self.saved;
+        // SAFETY: `raw` is a valid `termios` to change.
+        unsafe { cfmakeraw(&mut raw) };
+        raw.c_cc[VMIN] = 0;
+        raw.c_cc[VTIME] = 1;
+        self.set(&raw)?;
+        self.write(ENTER)
+    }
+
+    fn set(&self, termios: &Termios) -> io::Result<()> {
+        // SAFETY: `termios` is a valid `termios` that outlives the call.
+        if unsafe { tcsetattr(self.tty.as_raw_fd(), TCSANOW, termios) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.suspend();
+    }
+}
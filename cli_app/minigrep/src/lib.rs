@@ -1,51 +1,919 @@
-use std::env;
+pub mod aho_corasick;
+pub mod archive;
+pub mod args;
+pub mod casefold;
+pub mod encoding;
+pub mod fields;
+pub mod fuzzy;
+pub mod index;
+pub mod interactive;
+pub mod literal;
+pub mod matcher;
+pub mod parallel;
+pub mod printer;
+pub mod regex;
+pub mod replace;
+pub mod searcher;
+pub mod walk;
+pub mod watch;
+
+use std::borrow::Cow;
 use std::error::Error;
-use std::fs;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::iter;
+use std::ops::Range;
+use std::path::Path;
+use std::thread;
+
+use archive::{Format, GzDecoder};
+use encoding::Encoding;
+use index::Candidates;
+use matcher::Matcher;
+use printer::{Color, Printer, Stats, Summary};
+use regex::Regex;
+use replace::Replacement;
+use walk::Walk;
+
+/// Name printed for standard input, which is searched for the path `-`.
+const STDIN_LABEL: &str = "(standard input)";
+
+/// How much of a file is searched at once when lines need not be looked at
+/// one by one.
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// How much more is read to finish the last line of a chunk. A line longer
+/// than this is searched in pieces, so a match across a cut can be missed.
+const MAX_LINE_TAIL: u64 = 1024 * 1024;
+
+/// The largest input searched with `-U`, which holds all of it in memory.
+/// Larger files are skipped with an error.
+const MAX_MULTILINE_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Build or update the index of the tree at `root`, for `minigrep index`.
+pub fn run_index(root: &str) -> Result<(), Box<dyn Error>> {
+    let update = index::update(Path::new(root), Config::default().threads)?;
+    println!(
+        "Indexed {} files in {} ({} updated, {} removed)",
+        update.files, root, update.updated, update.removed
+    );
+    Ok(())
+}
+
+/// How a search went, which decides the exit status.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Outcome {
+    /// Whether any line was selected.
+    pub matched: bool,
+    /// Whether some file could not be searched. The errors have already
+    /// been reported.
+    pub failed: bool,
+}
+
+impl Outcome {
+    /// How `--watch` and `--interactive` end when they stop without an
+    /// error. They have no final set of matches to go by, so they exit with
+    /// 0, where grep's "no match" would be misleading.
+    pub const CLEAN_EXIT: Outcome = Outcome {
+        matched: true,
+        failed: false,
+    };
+
+    /// The exit status grep would give: 0 if a line was selected, 1 if none
+    /// was, and 2 if there was an error. `--watch` and `--interactive` give
+    /// 0 when stopped cleanly, as `CLEAN_EXIT`, and 2 on an error, which
+    /// `run` returns rather than an outcome.
+    pub fn exit_code(&self) -> i32 {
+        if self.failed {
+            2
+        } else if self.matched {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+/// Search as `config` says. Errors with one file are reported on stderr
+/// and the search goes on; only errors that stop the whole search, such
+/// as an invalid pattern or failing to write the output, are returned.
+pub fn run(mut config: Config) -> Result<Outcome, Box<dyn Error>> {
+    if config.interactive {
+        interactive::interactive(&config)?;
+        return Ok(Outcome::CLEAN_EXIT);
+    }
+    if config.quiet {
+        // Each file is searched as with -l, only up to its first match, and
+        // nothing is printed.
+        config.files_with_matches = true;
+        config.count = false;
+        config.json = false;
+    }
+    let matcher = Matcher::new(&config)?;
+    let color = match config.color {
+        Color::Always => true,
+        Color::Never => false,
+        Color::Auto => io::stdout().is_terminal(),
+    };
+
+    if config.watch {
+        watch::watch(&config, &matcher, color)?;
+        return Ok(Outcome::CLEAN_EXIT);
+    }
+
+    let mut outcome = Outcome::default();
+    let mut report = |e: &dyn Error| {
+        outcome.failed = true;
+        if !config.no_messages {
+            eprintln!("minigrep: {}", e);
+        }
+    };
+
+    // A single file or standard input is streamed straight to stdout.
+    if let [path] = &config.paths[..] {
+        if !Path::new(path).is_dir() && !config.in_place && !config.dry_run {
+            let out = BufWriter::new(io::stdout().lock());
+            let out: Box<dyn Write> = if config.quiet {
+                Box::new(io::sink())
+            } else {
+                Box::new(out)
+            };
+            let mut summary = Summary::default();
+            let mut out = match search_path(&config, &matcher, color, Path::new(path), false, out) {
+                Ok((out, stats)) => {
+                    summary.add(stats);
+                    out
+                }
+                Err(e) => {
+                    report(&e);
+                    Box::new(io::stdout().lock())
+                }
+            };
+            summary.print(&config, &mut out)?;
+            out.flush()?;
+            return Ok(Outcome {
+                matched: summary.matched_lines > 0,
+                ..outcome
+            });
+        }
+    }
+
+    let candidates = match config.index {
+        true => Some(Candidates::new(&config, &matcher)?),
+        false => None,
+    };
+    let files = Walk::new(&config.paths).filter(|path| match (path, &candidates) {
+        (Ok(path), Some(candidates)) => candidates.may_match(path),
+        _ => true,
+    });
 
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string(config.filename)?;
+    if config.in_place || config.dry_run {
+        let mut matched = false;
+        parallel::map_ordered(
+            files,
+            config.threads,
+            |path| -> Result<Option<Vec<u8>>, Box<dyn Error + Send + Sync>> {
+                Ok(replace::rewrite(&config, &matcher, &path?)?)
+            },
+            |diff| -> io::Result<()> {
+                match diff {
+                    Ok(Some(diff)) => {
+                        matched = true;
+                        io::stdout().write_all(&diff)
+                    }
+                    Ok(None) => Ok(()),
+                    Err(e) => {
+                        report(&*e);
+                        Ok(())
+                    }
+                }
+            },
+        )?;
+        return Ok(Outcome { matched, ..outcome });
+    }
 
-    let results = if config.case_sensitive {
-        search(&config.query, &contents)
+    let mut summary = Summary::default();
+    let result = parallel::map_ordered(
+        files,
+        config.threads,
+        |path| -> Result<(Vec<u8>, Stats), Box<dyn Error + Send + Sync>> {
+            // Each file's lines are printed together, never interleaved
+            // with those of files searched at the same time.
+            Ok(search_path(
+                &config,
+                &matcher,
+                color,
+                &path?,
+                true,
+                Vec::new(),
+            )?)
+        },
+        // Stops with `None` once -q has found a match.
+        |output| -> Result<(), Option<io::Error>> {
+            match output {
+                Ok((_, stats)) if config.quiet && stats.matched_lines > 0 => {
+                    summary.add(stats);
+                    Err(None)
+                }
+                Ok((output, stats)) => {
+                    summary.add(stats);
+                    io::stdout().write_all(&output).map_err(Some)
+                }
+                Err(e) => {
+                    report(&*e);
+                    Ok(())
+                }
+            }
+        },
+    );
+    if let Err(Some(e)) = result {
+        return Err(e.into());
+    }
+
+    summary.print(&config, &mut io::stdout())?;
+    Ok(Outcome {
+        matched: summary.matched_lines > 0,
+        // With -q, finding a match is a success even if some file could
+        // not be searched, as with grep.
+        failed: outcome.failed && !(config.quiet && summary.matched_lines > 0),
+    })
+}
+
+fn search_path<W: Write>(
+    config: &Config,
+    matcher: &Matcher,
+    color: bool,
+    path: &Path,
+    with_filename: bool,
+    out: W,
+) -> io::Result<(W, Stats)> {
+    let with_path = |e: io::Error| io::Error::new(e.kind(), format!("{}: {}", path.display(), e));
+    if path == Path::new("-") {
+        let mut stdin = BufReader::new(io::stdin());
+        return search_input(
+            config,
+            matcher,
+            color,
+            &mut stdin,
+            STDIN_LABEL,
+            with_filename,
+            out,
+        )
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", STDIN_LABEL, e)));
+    }
+
+    let file = File::open(path).map_err(with_path)?;
+    let name = path.display().to_string();
+    search_input(
+        config,
+        matcher,
+        color,
+        &mut BufReader::new(file),
+        &name,
+        with_filename,
+        out,
+    )
+    .map_err(with_path)
+}
+
+/// Search `reader`, first decompressing it if it is compressed, and with
+/// `--archives`, searching each file in it if it is a tar or zip archive.
+/// Those files are named `archive:path` as if they were always one of
+/// several.
+fn search_input<W: Write>(
+    config: &Config,
+    matcher: &Matcher,
+    color: bool,
+    reader: &mut (dyn BufRead + Send),
+    name: &str,
+    with_filename: bool,
+    out: W,
+) -> io::Result<(W, Stats)> {
+    let search = |reader: &mut (dyn BufRead + Send), out| {
+        search_input(config, matcher, color, reader, name, with_filename, out)
+    };
+
+    match Format::detect(reader.fill_buf()?) {
+        Some(Format::Gzip) => search(&mut BufReader::new(GzDecoder::new(reader)), out),
+        Some(format @ (Format::Bzip2 | Format::Xz | Format::Zstd)) => {
+            archive::decompress(format, reader, |reader| search(reader, out))
+        }
+        Some(format @ (Format::Tar | Format::Zip)) if config.archives => {
+            let mut out = Some(out);
+            let mut stats = Stats::default();
+            archive::entries(format, reader, |path, entry| {
+                let name = format!("{}:{}", name, path);
+                let found = search_input(
+                    config,
+                    matcher,
+                    color,
+                    entry,
+                    &name,
+                    true,
+                    out.take().unwrap(),
+                )?;
+                out = Some(found.0);
+                stats += found.1;
+                Ok(())
+            })?;
+            Ok((out.unwrap(), stats))
+        }
+        _ => search_reader(config, matcher, color, reader, name, with_filename, out),
+    }
+}
+
+/// Search `reader`, first turning it into UTF-8 if it is in another
+/// encoding, given with `--encoding` or shown by a byte order mark.
+fn search_reader<R: BufRead, W: Write>(
+    config: &Config,
+    matcher: &Matcher,
+    color: bool,
+    mut reader: R,
+    name: &str,
+    with_filename: bool,
+    out: W,
+) -> io::Result<(W, Stats)> {
+    let (encoding, bom) = encoding::detect(config.encoding, reader.fill_buf()?);
+    reader.consume(bom);
+    if encoding == Encoding::Utf8 {
+        search_text(config, matcher, color, reader, name, with_filename, out)
     } else {
-        search_case_insensitive(&config.query, &contents)
+        let reader = encoding::Decoder::new(reader, encoding);
+        search_text(config, matcher, color, reader, name, with_filename, out)
+    }
+}
+
+/// Search `reader` without holding more than a chunk of it in memory.
+///
+/// Invalid UTF-8 is replaced with U+FFFD rather than treated as an error.
+/// Input with a NUL byte near the start is taken to be binary and skipped,
+/// as grep does.
+fn search_text<R: BufRead, W: Write>(
+    config: &Config,
+    matcher: &Matcher,
+    color: bool,
+    mut reader: R,
+    name: &str,
+    with_filename: bool,
+    out: W,
+) -> io::Result<(W, Stats)> {
+    if reader.fill_buf()?.contains(&0) {
+        return Ok((out, Stats::default()));
+    }
+
+    let label = (with_filename || config.json).then(|| name.to_string());
+    let mut printer = Printer::new(config, label, color, out);
+    let has_context = config.before_context > 0 || config.after_context > 0;
+    let selected = if let Some(format) = config.records {
+        search_records(config, matcher, format, reader, &mut printer)?
+    } else if config.multiline {
+        search_multiline(config, matcher, reader, &mut printer)?
+    } else if matcher.is_line_safe() && !config.invert_match && !has_context {
+        search_chunks(config, matcher, reader, &mut printer)?
+    } else {
+        search_each_line(config, matcher, reader, &mut printer)?
     };
 
-    for line in results {
-        println!("{}", line);
+    let (mut out, mut stats) = printer.finish()?;
+    if config.count || config.files_with_matches {
+        // The printer saw none of the lines, but whether any were selected
+        // still decides the exit status.
+        stats.matched_lines = selected;
+    }
+    if config.files_with_matches {
+        if selected > 0 {
+            writeln!(out, "{}", name)?;
+        }
+    } else if config.count {
+        if with_filename {
+            write!(out, "{}:", name)?;
+        }
+        writeln!(out, "{}", selected)?;
     }
 
-    Ok(())
+    Ok((out, stats))
 }
 
-pub struct Config {
-    pub query: String,
-    pub filename: String,
-    pub case_sensitive: bool,
+/// Search a chunk of lines at a time with `search_lines`, so that the lines
+/// without a match are never looked at one by one. Returns the number of
+/// selected lines.
+fn search_chunks<R: BufRead, W: Write>(
+    config: &Config,
+    matcher: &Matcher,
+    mut reader: R,
+    printer: &mut Printer<W>,
+) -> io::Result<usize> {
+    let mut selected = 0;
+    let mut chunk = Vec::new();
+    // The start of a character cut off at the end of the last chunk.
+    let mut carry = Vec::new();
+    // Lines and bytes before the current chunk.
+    let mut lines = 0;
+    let mut offset = 0;
+
+    loop {
+        chunk.clear();
+        chunk.append(&mut carry);
+        // End the chunk at the end of a line, which is also never in the
+        // middle of a UTF-8 sequence, unless the line is too long to hold.
+        (&mut reader).take(CHUNK_SIZE).read_to_end(&mut chunk)?;
+        let tail = (&mut reader)
+            .take(MAX_LINE_TAIL)
+            .read_until(b'\n', &mut chunk)?;
+        if tail as u64 == MAX_LINE_TAIL && !chunk.ends_with(b"\n") {
+            // Cut between characters, so the next chunk picks up the line
+            // where this one leaves it.
+            let len = chunk.len();
+            let cut = (len.saturating_sub(3)..len)
+                .find(|&i| {
+                    chunk[i] >= 0xc0
+                        && matches!(std::str::from_utf8(&chunk[i..]),
+                                    Err(e) if e.error_len().is_none())
+                })
+                .unwrap_or(len);
+            carry.extend_from_slice(&chunk[cut..]);
+            chunk.truncate(cut);
+        }
+        if chunk.is_empty() {
+            break;
+        }
+
+        let text = String::from_utf8_lossy(&chunk);
+        // Where the line numbered `raw_line` starts in `chunk`.
+        let (mut raw_line, mut raw_start) = (1, 0);
+
+        for found in search_lines(matcher, &text) {
+            selected += 1;
+            if config.files_with_matches {
+                return Ok(selected);
+            } else if config.count {
+                continue;
+            }
+
+            let line_start = if let Cow::Borrowed(_) = text {
+                found.offset
+            } else {
+                // Replacement characters moved the text around, so find
+                // the line in the bytes that were read.
+                while raw_line < found.number {
+                    raw_start += literal::memchr(b'\n', &chunk[raw_start..]).unwrap() + 1;
+                    raw_line += 1;
+                }
+                raw_start
+            };
+            print_match(
+                printer,
+                config,
+                matcher,
+                lines + found.number,
+                offset + line_start,
+                found.line,
+                Some(found.ranges),
+            )?;
+        }
+        lines += literal::count(b'\n', &chunk);
+        offset += chunk.len();
+    }
+
+    Ok(selected)
 }
 
-impl Config {
-    pub fn new(mut args: env::Args) -> Result<Config, &'static str> {
-        args.next();
+/// Search `reader` line by line, for when unselected lines matter or a
+/// match may depend on where the line starts and ends. Returns the number
+/// of selected lines.
+fn search_each_line<R: BufRead, W: Write>(
+    config: &Config,
+    matcher: &Matcher,
+    mut reader: R,
+    printer: &mut Printer<W>,
+) -> io::Result<usize> {
+    let listing = config.count || config.files_with_matches;
+    let mut selected = 0;
+    let mut buffer = Vec::new();
+    let mut number = 0;
+    let mut next_offset = 0;
+
+    loop {
+        buffer.clear();
+        let offset = next_offset;
+        next_offset += reader.read_until(b'\n', &mut buffer)?;
+        if next_offset == offset {
+            break;
+        }
+        number += 1;
+
+        if buffer.ends_with(b"\n") {
+            buffer.pop();
+            if buffer.ends_with(b"\r") {
+                buffer.pop();
+            }
+        }
+        let line = String::from_utf8_lossy(&buffer);
+
+        let is_selected = matcher.is_match(&line) != config.invert_match;
+        if is_selected {
+            selected += 1;
+        }
+
+        if config.files_with_matches && is_selected {
+            break;
+        } else if listing {
+            continue;
+        } else if is_selected && !config.invert_match {
+            print_match(printer, config, matcher, number, offset, &line, None)?;
+        } else if is_selected {
+            printer.selected(number, offset, &line, &[], None)?;
+        } else {
+            printer.unselected(number, offset, &line)?;
+        }
+    }
+
+    Ok(selected)
+}
 
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string"),
+/// Read all of `reader`, failing if it holds more than `limit` bytes.
+fn read_at_most<R: Read>(reader: R, limit: u64) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(limit + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > limit {
+        return Err(io::Error::new(
+            io::ErrorKind::FileTooLarge,
+            format!(
+                "larger than {} MiB, too large to search with -U",
+                limit / (1024 * 1024)
+            ),
+        ));
+    }
+    Ok(bytes)
+}
+
+/// Search the whole of `reader` at once with `--multiline`, so that a
+/// match can span lines. A match selects every line it touches, and
+/// matches on the same lines are printed together. Returns the number of
+/// selected lines.
+fn search_multiline<R: BufRead, W: Write>(
+    config: &Config,
+    matcher: &Matcher,
+    mut reader: R,
+    printer: &mut Printer<W>,
+) -> io::Result<usize> {
+    let listing = config.count || config.files_with_matches;
+    let bytes = read_at_most(&mut reader, MAX_MULTILINE_SIZE)?;
+    // Offsets are into the text with any invalid UTF-8 replaced.
+    let text = String::from_utf8_lossy(&bytes);
+    let mut blocks = matching_blocks(matcher, &text).into_iter().peekable();
+    let mut selected = 0;
+    let mut number = 1;
+    let mut start = 0;
+
+    while start < text.len() {
+        if let Some((lines, ranges)) = blocks.next_if(|(lines, _)| lines.start == start) {
+            let block = &text[lines.clone()];
+            let count = 1 + literal::count(b'\n', block.as_bytes());
+            if config.invert_match {
+                let mut offset = start;
+                for (i, line) in block.split('\n').enumerate() {
+                    let next = offset + line.len() + 1;
+                    let line = line.strip_suffix('\r').unwrap_or(line);
+                    printer.unselected(number + i, offset, line)?;
+                    offset = next;
+                }
+            } else {
+                selected += count;
+                if config.files_with_matches {
+                    break;
+                } else if !listing {
+                    let ranges = ranges.iter().map(|r| r.start - start..r.end - start);
+                    print_match(
+                        printer,
+                        config,
+                        matcher,
+                        number,
+                        start,
+                        block,
+                        Some(ranges.collect()),
+                    )?;
+                }
+            }
+            number += count;
+            start = lines.end + 1;
+            continue;
+        }
+
+        let end =
+            literal::memchr(b'\n', &text.as_bytes()[start..]).map_or(text.len(), |at| start + at);
+        let line = &text[start..end];
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if !config.invert_match {
+            printer.unselected(number, start, line)?;
+        } else {
+            selected += 1;
+            if config.files_with_matches {
+                break;
+            } else if !listing {
+                printer.selected(number, start, line, &[], None)?;
+            }
+        }
+        number += 1;
+        start = end + 1;
+    }
+
+    Ok(selected)
+}
+
+/// The matches of `text` grouped by the lines they are on: the byte range
+/// of each run of lines touched by a match, without the final newline,
+/// with the matches in it.
+fn matching_blocks(matcher: &Matcher, text: &str) -> Vec<(Range<usize>, Vec<Range<usize>>)> {
+    let mut blocks: Vec<(Range<usize>, Vec<Range<usize>>)> = Vec::new();
+    let mut start = 0;
+
+    while start <= text.len() {
+        let Some(found) = matcher.find_at(text, start) else {
+            break;
+        };
+        // After a final newline there is no line left to match.
+        if found.start == text.len() && (text.is_empty() || text.ends_with('\n')) {
+            break;
+        }
+        start = if found.is_empty() {
+            found.end + text[found.end..].chars().next().map_or(1, char::len_utf8)
+        } else {
+            found.end
         };
 
-        let filename = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string"),
+        let line_start = text[..found.start].rfind('\n').map_or(0, |at| at + 1);
+        // A match ending in a newline does not go on to the next line.
+        let last = if text[found.clone()].ends_with('\n') {
+            found.end - 1
+        } else {
+            found.end
         };
+        let line_end = text[last..].find('\n').map_or(text.len(), |at| last + at);
+
+        match blocks.last_mut() {
+            Some((lines, ranges)) if line_start <= lines.end => {
+                lines.end = lines.end.max(line_end);
+                ranges.push(found);
+            }
+            _ => blocks.push((line_start..line_end, vec![found])),
+        }
+    }
 
-        let case_sensitive = env::var("CASE_INSENSITIVE").is_err();
+    blocks
+}
 
-        Ok(Config {
-            query,
-            filename,
-            case_sensitive,
-        })
+/// Search the field of each record with `--field`, selecting whole records.
+/// Returns the number of selected records.
+fn search_records<R: BufRead, W: Write>(
+    config: &Config,
+    matcher: &Matcher,
+    format: fields::Format,
+    reader: R,
+    printer: &mut Printer<W>,
+) -> io::Result<usize> {
+    let listing = config.count || config.files_with_matches;
+    let mut selected = 0;
+
+    for record in fields::Records::new(format, &config.field, reader)? {
+        let record = record?;
+        // A record without the field never matches.
+        let value = record.value.as_deref();
+        let is_match = value.is_some_and(|value| matcher.is_match(value));
+        let is_selected = is_match != config.invert_match;
+        if is_selected {
+            selected += 1;
+        }
+
+        let (number, offset, text) = (record.number, record.offset, &record.text);
+        if config.files_with_matches && is_selected {
+            break;
+        } else if listing {
+            continue;
+        } else if is_selected && !config.invert_match {
+            let value = value.unwrap();
+            // Matches can only be shown where the value is in the record
+            // as it is, not quoted or escaped.
+            let ranges = match &record.span {
+                Some(span) if printer.wants_matches() => matcher
+                    .find_iter(value)
+                    .into_iter()
+                    .map(|range| span.start + range.start..span.start + range.end)
+                    .collect(),
+                _ => Vec::new(),
+            };
+            printer.selected(number, offset, text, &ranges, matcher.distance(value))?;
+        } else if is_selected {
+            printer.selected(number, offset, text, &[], None)?;
+        } else {
+            printer.unselected(number, offset, text)?;
+        }
     }
+
+    Ok(selected)
+}
+
+/// Print a line with a match, first replacing the matches with `--replace`.
+/// `ranges` are the matches in `line` if they are already known.
+fn print_match<W: Write>(
+    printer: &mut Printer<W>,
+    config: &Config,
+    matcher: &Matcher,
+    number: usize,
+    offset: usize,
+    line: &str,
+    ranges: Option<Vec<Range<usize>>>,
+) -> io::Result<()> {
+    let distance = matcher.distance(line);
+    let (line, ranges) = match &config.replace {
+        Some(replacement) => {
+            let (line, ranges) = replacement.replace_all(matcher, line);
+            (Cow::Owned(line), ranges)
+        }
+        None if printer.wants_matches() => (
+            Cow::Borrowed(line),
+            ranges.unwrap_or_else(|| matcher.find_iter(line)),
+        ),
+        None => (Cow::Borrowed(line), Vec::new()),
+    };
+
+    let ranges = if printer.wants_matches() {
+        &ranges[..]
+    } else {
+        &[]
+    };
+    printer.selected(number, offset, &line, ranges, distance)
+}
+
+pub struct Config {
+    /// A line is selected when it matches any of these.
+    pub patterns: Vec<String>,
+    pub paths: Vec<String>,
+    pub case_sensitive: bool,
+    pub regex: bool,
+    pub invert_match: bool,
+    pub word_regexp: bool,
+    /// Allow matches with up to this many edits.
+    pub fuzzy: Option<usize>,
+    pub line_number: bool,
+    pub count: bool,
+    pub files_with_matches: bool,
+    pub before_context: usize,
+    pub after_context: usize,
+    pub color: Color,
+    pub json: bool,
+    /// Print the lines with their matches replaced, or with `in_place`,
+    /// write them back to the files.
+    pub replace: Option<Replacement>,
+    pub in_place: bool,
+    /// Show what `in_place` would change as a diff, without changing it.
+    pub dry_run: bool,
+    pub threads: usize,
+    /// Search the files inside tar and zip archives.
+    pub archives: bool,
+    /// Skip the files that the index says cannot match.
+    pub index: bool,
+    /// Keep searching the lines added to the files.
+    pub watch: bool,
+    /// Read the input as records of this format, and search only `field`
+    /// of each: a column of CSV, or a path like `user.name` in JSON.
+    pub records: Option<fields::Format>,
+    pub field: String,
+    /// Search as the query is typed, in a terminal UI.
+    pub interactive: bool,
+    /// Let matches span lines.
+    pub multiline: bool,
+    /// Read text in this encoding rather than UTF-8, or as a byte order
+    /// mark says.
+    pub encoding: Option<Encoding>,
+    /// Print nothing, and stop at the first match.
+    pub quiet: bool,
+    /// Leave out the messages about files that could not be searched.
+    pub no_messages: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            patterns: Vec::new(),
+            paths: Vec::new(),
+            case_sensitive: true,
+            regex: false,
+            invert_match: false,
+            word_regexp: false,
+            fuzzy: None,
+            line_number: false,
+            count: false,
+            files_with_matches: false,
+            before_context: 0,
+            after_context: 0,
+            color: Color::Auto,
+            json: false,
+            replace: None,
+            in_place: false,
+            dry_run: false,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            archives: false,
+            index: false,
+            watch: false,
+            records: None,
+            field: String::new(),
+            interactive: false,
+            multiline: false,
+            encoding: None,
+            quiet: false,
+            no_messages: false,
+        }
+    }
+}
+
+/// A line containing at least one match.
+#[derive(Debug, PartialEq)]
+pub struct LineMatch<'a> {
+    /// Counting from 1.
+    pub number: usize,
+    /// Byte offset of the start of the line.
+    pub offset: usize,
+    pub line: &'a str,
+    /// Byte ranges of the matches within the line.
+    pub ranges: Vec<Range<usize>>,
+}
+
+/// Find the lines of `contents` with a match.
+///
+/// When the matcher allows it, the whole text is searched for the next
+/// match and only then is the line around it located, which skips over
+/// lines without a match much faster than taking each line in turn.
+pub fn search_lines<'a>(matcher: &Matcher, contents: &'a str) -> Vec<LineMatch<'a>> {
+    if !matcher.is_line_safe() {
+        let mut offset = 0;
+        return contents
+            .split_inclusive('\n')
+            .enumerate()
+            .filter_map(|(index, line)| {
+                let line_offset = offset;
+                offset += line.len();
+
+                let line = line.strip_suffix('\n').unwrap_or(line);
+                let line = line.strip_suffix('\r').unwrap_or(line);
+                let ranges = matcher.find_iter(line);
+                if ranges.is_empty() {
+                    None
+                } else {
+                    Some(LineMatch {
+                        number: index + 1,
+                        offset: line_offset,
+                        line,
+                        ranges,
+                    })
+                }
+            })
+            .collect();
+    }
+
+    let mut number = 1;
+    let mut counted = 0;
+    matching_lines(matcher, contents)
+        .map(|range| {
+            number += literal::count(b'\n', &contents.as_bytes()[counted..range.start]);
+            counted = range.start;
+
+            let offset = range.start;
+            let line = &contents[range];
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            LineMatch {
+                number,
+                offset,
+                line,
+                ranges: matcher.find_iter(line),
+            }
+        })
+        .collect()
+}
+
+/// The byte ranges of the lines of `text` with a match, without their
+/// newlines. Only correct for matchers whose matches never span lines.
+fn matching_lines<'a>(
+    matcher: &'a Matcher,
+    text: &'a str,
+) -> impl Iterator<Item = Range<usize>> + 'a {
+    let mut start = 0;
+
+    iter::from_fn(move || {
+        if start >= text.len() {
+            return None;
+        }
+        let found = matcher.find_at(text, start)?;
+
+        let line_start = text[start..found.start]
+            .rfind('\n')
+            .map_or(start, |at| start + at + 1);
+        let line_end = literal::memchr(b'\n', &text.as_bytes()[found.end..])
+            .map_or(text.len(), |at| found.end + at);
+
+        start = line_end + 1;
+        Some(line_start..line_end)
+    })
 }
 
 pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
@@ -55,20 +923,26 @@ pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
         .collect()
 }
 
+/// Find the lines containing `query`, ignoring case as defined by Unicode
+/// case folding.
 pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = query.to_lowercase();
-    let mut results = Vec::new();
+    let query = casefold::fold_str(query);
 
-    for line in contents.lines() {
-        if line.to_lowercase().contains(&query) {
-            results.push(line);
-        }
-    }
+    contents
+        .lines()
+        .filter(|line| casefold::find(line, &query, 0).is_some())
+        .collect()
+}
 
-    results
+pub fn search_regex<'a>(regex: &Regex, contents: &'a str) -> Vec<&'a str> {
+    contents
+        .lines()
+        .filter(|line| regex.is_match(line))
+        .collect()
 }
 
 #[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
 mod tests {
     use super::*;
 
@@ -96,4 +970,332 @@ Trust me.";
             search_case_insensitive(query, contents)
         );
     }
+
+    #[test]
+    fn case_insensitive_multilingual() {
+        let contents = "\
+Die Straße ist lang.
+DIE STRASSE IST LANG.
+İstanbul'da
+ΟΔΥΣΣΕΥΣ
+odysseus";
+        assert_eq!(
+            vec!["Die Straße ist lang.", "DIE STRASSE IST LANG."],
+            search_case_insensitive("strasse", contents)
+        );
+        assert_eq!(
+            vec!["İstanbul'da"],
+            search_case_insensitive("i\u{307}stanbul", contents)
+        );
+        assert_eq!(
+            vec!["ΟΔΥΣΣΕΥΣ"],
+            search_case_insensitive("οδυσσευς", contents)
+        );
+    }
+
+    #[test]
+    fn regex_case_sensitive() {
+        let regex = Regex::new(r"^\w+, (f|s)\w+").unwrap();
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Duct tape.";
+        assert_eq!(
+            vec!["safe, fast, productive."],
+            search_regex(&regex, contents)
+        );
+    }
+
+    #[test]
+    fn regex_case_insensitive() {
+        let regex = Regex::new("(?i)r[aeiou]st").unwrap();
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+        assert_eq!(vec!["Rust:", "Trust me."], search_regex(&regex, contents));
+    }
+
+    #[test]
+    fn match_positions() {
+        let config = Config {
+            patterns: vec!["rUsT".to_string()],
+            case_sensitive: false,
+            ..Config::default()
+        };
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me, rust.";
+
+        assert_eq!(
+            vec![
+                LineMatch {
+                    number: 1,
+                    offset: 0,
+                    line: "Rust:",
+                    ranges: vec![0..4],
+                },
+                LineMatch {
+                    number: 4,
+                    offset: 42,
+                    line: "Trust me, rust.",
+                    ranges: vec![1..5, 10..14],
+                },
+            ],
+            search_lines(&Matcher::new(&config).unwrap(), contents)
+        );
+    }
+
+    #[test]
+    fn finds_lines_of_several_patterns() {
+        let config = Config {
+            patterns: vec!["fast".to_string(), "Duct".to_string(), "".to_string()],
+            ..Config::default()
+        };
+        let contents = "\
+Rust:\r
+safe, fast, productive.\r
+Pick three.
+Duct tape.";
+        let lines: Vec<_> = search_lines(&Matcher::new(&config).unwrap(), contents)
+            .into_iter()
+            .map(|found| (found.number, found.line))
+            .collect();
+        assert_eq!(
+            vec![
+                (1, "Rust:"),
+                (2, "safe, fast, productive."),
+                (3, "Pick three."),
+                (4, "Duct tape."),
+            ],
+            lines
+        );
+
+        let config = Config {
+            patterns: vec!["fast".to_string(), "Duct".to_string()],
+            ..config
+        };
+        let found = search_lines(&Matcher::new(&config).unwrap(), contents);
+        assert_eq!(
+            vec![(2, vec![6..10]), (4, vec![0..4])],
+            found
+                .into_iter()
+                .map(|found| (found.number, found.ranges))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn numbers_lines_across_chunks() {
+        let config = Config {
+            patterns: vec!["needle".to_string()],
+            line_number: true,
+            ..Config::default()
+        };
+        let matcher = Matcher::new(&config).unwrap();
+        let mut input = String::new();
+        for number in 1..=50_000 {
+            input.push_str(if number % 20_000 == 0 {
+                "a needle\n"
+            } else {
+                "hay\n"
+            });
+        }
+        assert!(input.len() as u64 > 2 * CHUNK_SIZE);
+
+        let out = search_reader(
+            &config,
+            &matcher,
+            false,
+            input.as_bytes(),
+            "-",
+            false,
+            Vec::new(),
+        )
+        .unwrap()
+        .0;
+        assert_eq!(
+            "20000:a needle\n40000:a needle\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn searches_overlong_lines_in_pieces() {
+        let config = Config {
+            patterns: vec!["needle".to_string()],
+            line_number: true,
+            ..Config::default()
+        };
+        let matcher = Matcher::new(&config).unwrap();
+        // Two-byte characters, so that a cut lands inside one.
+        let long = "\u{e9}".repeat(MAX_LINE_TAIL as usize + 1);
+        let input = format!("hay\n{}needle\nneedle\n", long);
+
+        let out = search_reader(
+            &config,
+            &matcher,
+            false,
+            input.as_bytes(),
+            "-",
+            false,
+            Vec::new(),
+        )
+        .unwrap()
+        .0;
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("2:\u{e9}"));
+        assert!(out.ends_with("\u{e9}needle\n3:needle\n"));
+        assert!(!out.contains('\u{fffd}'));
+        assert!(out.len() < long.len());
+    }
+
+    #[test]
+    fn streams_lines_lossily() {
+        let config = Config {
+            patterns: vec!["caf".to_string()],
+            line_number: true,
+            color: Color::Never,
+            ..Config::default()
+        };
+        let matcher = Matcher::new(&config).unwrap();
+        let input = &b"caf\xe9 au lait\r\nthe\ncafe\n"[..];
+
+        let out = search_reader(&config, &matcher, false, input, "-", false, Vec::new())
+            .unwrap()
+            .0;
+        assert_eq!(
+            "1:caf\u{fffd} au lait\n3:cafe\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let config = Config {
+            json: true,
+            ..config
+        };
+        let out = search_reader(&config, &matcher, false, input, "-", false, Vec::new())
+            .unwrap()
+            .0;
+        assert_eq!(
+            r#"{"type":"begin","path":"-"}
+{"type":"match","path":"-","line_number":1,"offset":0,"line":"caf� au lait","submatches":[{"start":0,"end":3,"text":"caf"}]}
+{"type":"match","path":"-","line_number":3,"offset":18,"line":"cafe","submatches":[{"start":0,"end":3,"text":"caf"}]}
+{"type":"end","path":"-","matched_lines":2,"matches":2}
+"#,
+            String::from_utf8(out).unwrap()
+        );
+
+        let binary = &b"caf\0e"[..];
+        let out = search_reader(&config, &matcher, false, binary, "-", false, Vec::new())
+            .unwrap()
+            .0;
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn refuses_multiline_input_over_the_limit() {
+        assert_eq!(b"12345", &read_at_most(&b"12345"[..], 5).unwrap()[..]);
+        let error = read_at_most(&b"123456"[..], 5).unwrap_err();
+        assert_eq!(io::ErrorKind::FileTooLarge, error.kind());
+    }
+
+    #[test]
+    fn matches_across_lines_with_multiline() {
+        let search = |patterns: &[&str], regex, invert_match| {
+            let config = Config {
+                patterns: patterns.iter().map(|p| p.to_string()).collect(),
+                regex,
+                invert_match,
+                multiline: true,
+                line_number: true,
+                color: Color::Never,
+                ..Config::default()
+            };
+            let matcher = Matcher::new(&config).unwrap();
+            let input = &b"fn main() {\r\n    run();\n}\n\nfn run() {}\n"[..];
+            let out = search_reader(&config, &matcher, false, input, "-", false, Vec::new())
+                .unwrap()
+                .0;
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            "1:fn main() {\n2:    run();\n",
+            search(&["{\r\n    run"], false, false)
+        );
+        assert_eq!(
+            "2:    run();\n3:}\n5:fn run() {}\n",
+            search(&[r"\(\);\n\}", r"^fn run"], true, false)
+        );
+        assert_eq!(
+            "3:}\n4:\n5:fn run() {}\n",
+            search(&[r"\{\s+run"], true, true)
+        );
+        assert_eq!("3:}\n4:\n", search(&[r"}\n\n"], false, false));
+    }
+
+    #[test]
+    fn reports_the_lines_a_multiline_match_spans_in_json() {
+        let config = Config {
+            patterns: vec![r"foo\nbar".to_string()],
+            multiline: true,
+            json: true,
+            ..Config::default()
+        };
+        let matcher = Matcher::new(&config).unwrap();
+        let input = &b"foo\nbar\nbaz\n"[..];
+        let out = search_reader(&config, &matcher, false, input, "f", false, Vec::new())
+            .unwrap()
+            .0;
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .contains(r#""line_number":1,"end_line_number":2,"offset":0,"line":"foo\nbar","#));
+    }
+
+    #[test]
+    fn exit_status_follows_the_lines_selected() {
+        for (count, files_with_matches) in [(false, false), (true, false), (false, true)] {
+            let config = Config {
+                patterns: vec!["fast".to_string()],
+                count,
+                files_with_matches,
+                ..Config::default()
+            };
+            let matcher = Matcher::new(&config).unwrap();
+            let input = &b"Rust:\nsafe, fast, productive.\nfast again\n"[..];
+            let (_, stats) =
+                search_reader(&config, &matcher, false, input, "-", false, Vec::new()).unwrap();
+            assert!(stats.matched_lines > 0);
+        }
+
+        let outcome = |matched, failed| Outcome { matched, failed }.exit_code();
+        assert_eq!(
+            (0, 1, 2, 2),
+            (
+                outcome(true, false),
+                outcome(false, false),
+                outcome(false, true),
+                outcome(true, true)
+            )
+        );
+    }
+
+    #[test]
+    fn watch_and_interactive_exit_cleanly_or_with_an_error() {
+        assert_eq!(0, Outcome::CLEAN_EXIT.exit_code());
+
+        // An error, here in the pattern, is returned before anything is
+        // watched, and main exits with 2 for it.
+        let config = Config {
+            patterns: vec!["(".to_string()],
+            regex: true,
+            watch: true,
+            ..Config::default()
+        };
+        assert!(run(config).is_err());
+    }
 }
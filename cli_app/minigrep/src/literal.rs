@@ -0,0 +1,242 @@
+//! Substring search over bytes.
+//!
+//! `Finder` looks for the two rarest bytes of the needle at their offsets,
+//! eight haystack positions at a time, and only compares the whole needle
+//! where both occur. When those bytes turn out to be common in the haystack
+//! it switches to Boyer–Moore–Horspool, whose skip table bounds the work on
+//! bad inputs.
+
+const LO: u64 = 0x0101_0101_0101_0101;
+const HI: u64 = 0x8080_8080_8080_8080;
+
+/// The index of the first `byte` in `haystack`.
+///
+/// Compares eight bytes at a time using the classic "has zero byte" trick
+/// on `u64` words, which compilers turn into wide vector code.
+pub fn memchr(byte: u8, haystack: &[u8]) -> Option<usize> {
+    let repeated = LO * byte as u64;
+    let mut chunks = haystack.chunks_exact(8);
+    let mut offset = 0;
+
+    for chunk in &mut chunks {
+        // The lowest set bit marks the first occurrence.
+        let zero = zero_bytes(load(chunk, 0) ^ repeated);
+        if zero != 0 {
+            return Some(offset + zero.trailing_zeros() as usize / 8);
+        }
+        offset += 8;
+    }
+
+    chunks
+        .remainder()
+        .iter()
+        .position(|&b| b == byte)
+        .map(|i| offset + i)
+}
+
+fn load(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// Sets the high bit of every zero byte of `word`. A byte above a zero byte
+/// may be marked too, so only the lowest mark is certain.
+fn zero_bytes(word: u64) -> u64 {
+    word.wrapping_sub(LO) & !word & HI
+}
+
+/// Count the occurrences of `byte`, typically newlines.
+pub fn count(byte: u8, haystack: &[u8]) -> usize {
+    let repeated = LO * byte as u64;
+    let mut chunks = haystack.chunks_exact(8);
+    let mut total = 0;
+
+    for chunk in &mut chunks {
+        let word = load(chunk, 0) ^ repeated;
+        // Unlike `zero_bytes` this is exact: the high bit of each byte ends
+        // up set if and only if the byte is not zero.
+        let nonzero = ((word & !HI).wrapping_add(!HI) | word) & HI;
+        total += (!nonzero & HI).count_ones() as usize;
+    }
+
+    total + chunks.remainder().iter().filter(|&&b| b == byte).count()
+}
+
+/// A rough rank of how common a byte is in text and source code; lower is
+/// rarer.
+fn frequency(byte: u8) -> u8 {
+    match byte {
+        b' ' | b'e' | b't' | b'a' | b'o' | b'i' | b'n' | b's' | b'r' => 9,
+        b'h' | b'l' | b'd' | b'c' | b'u' | b'm' | b'\n' => 8,
+        b'a'..=b'z' | b'_' | b'.' | b',' | b'(' | b')' | b'\t' | b'0'..=b'9' => 6,
+        b'A'..=b'Z' | b'!'..=b'/' | b':'..=b'@' | b'['..=b'`' | b'{'..=b'~' => 4,
+        0x80..=0xff => 2,
+        _ => 1,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Finder {
+    needle: Vec<u8>,
+    /// Indexes in the needle of its two rarest bytes.
+    rare: (usize, usize),
+    /// Horspool shift for each byte that ends a window.
+    skip: [usize; 256],
+}
+
+impl Finder {
+    pub fn new(needle: &[u8]) -> Finder {
+        let mut by_rarity: Vec<usize> = (0..needle.len()).collect();
+        by_rarity.sort_by_key(|&i| frequency(needle[i]));
+        let first = by_rarity.first().copied().unwrap_or(0);
+        // Prefer a second byte that differs from the first.
+        let second = by_rarity
+            .iter()
+            .copied()
+            .find(|&i| needle[i] != needle[first])
+            .or_else(|| by_rarity.get(1).copied())
+            .unwrap_or(first);
+
+        let mut skip = [needle.len(); 256];
+        for (i, &b) in needle
+            .iter()
+            .enumerate()
+            .take(needle.len().saturating_sub(1))
+        {
+            skip[b as usize] = needle.len() - 1 - i;
+        }
+
+        Finder {
+            needle: needle.to_vec(),
+            rare: (first, second),
+            skip,
+        }
+    }
+
+    pub fn needle(&self) -> &[u8] {
+        &self.needle
+    }
+
+    pub fn find(&self, haystack: &[u8]) -> Option<usize> {
+        let n = self.needle.len();
+        if n == 0 {
+            return Some(0);
+        }
+        if n > haystack.len() {
+            return None;
+        }
+        if n == 1 {
+            return memchr(self.needle[0], haystack);
+        }
+
+        let (first, second) = self.rare;
+        let first_byte = LO * self.needle[first] as u64;
+        let second_byte = LO * self.needle[second] as u64;
+        let candidates = haystack.len() - n + 1;
+        let mut pos = 0;
+        let mut false_positives = 0;
+
+        while pos + 8 <= candidates {
+            // Skip ahead two words at a time while the rarest byte is
+            // absent, which is where the time goes on most haystacks.
+            while pos + 16 <= candidates
+                && zero_bytes(load(haystack, pos + first) ^ first_byte)
+                    | zero_bytes(load(haystack, pos + first + 8) ^ first_byte)
+                    == 0
+            {
+                pos += 16;
+            }
+            if pos + 8 > candidates {
+                break;
+            }
+
+            // Bit 8k + 7 is set when both rare bytes are in place for the
+            // window starting at `pos + k`, give or take false positives.
+            let mut found = zero_bytes(load(haystack, pos + first) ^ first_byte);
+            if found != 0 {
+                found &= zero_bytes(load(haystack, pos + second) ^ second_byte);
+            }
+
+            while found != 0 {
+                let start = pos + found.trailing_zeros() as usize / 8;
+                if haystack[start..start + n] == self.needle[..] {
+                    return Some(start);
+                }
+                found &= found - 1;
+
+                // Too many candidates per byte scanned: the rare bytes are
+                // not rare in this haystack.
+                false_positives += 1;
+                if false_positives >= 16 && pos < false_positives * 8 {
+                    return self.horspool(haystack, start + 1);
+                }
+            }
+            pos += 8;
+        }
+
+        (pos..candidates).find(|&start| haystack[start..start + n] == self.needle[..])
+    }
+
+    fn horspool(&self, haystack: &[u8], mut pos: usize) -> Option<usize> {
+        let n = self.needle.len();
+        let last = self.needle[n - 1];
+
+        while pos + n <= haystack.len() {
+            let end = haystack[pos + n - 1];
+            if end == last && haystack[pos..pos + n - 1] == self.needle[..n - 1] {
+                return Some(pos);
+            }
+            pos += self.skip[end as usize];
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memchr_finds_first_occurrence() {
+        let haystack = b"0123456789abcdefghij\x80x";
+        for (i, &b) in haystack.iter().enumerate() {
+            assert_eq!(Some(i), memchr(b, haystack));
+        }
+        assert_eq!(None, memchr(b'z', haystack));
+        assert_eq!(Some(9), memchr(b'\x80', b"aaaaaaaa\x7f\x80"));
+    }
+
+    #[test]
+    fn counts_bytes() {
+        let haystack = b"a\nb\n\n\x8a\x0b\ncd\n\n\n\x0a";
+        assert_eq!(8, count(b'\n', haystack));
+        assert_eq!(1, count(b'\x8a', haystack));
+        assert_eq!(0, count(b'z', haystack));
+    }
+
+    #[test]
+    fn finds_needles() {
+        let haystack = b"safe, fast, productive. Pick three.";
+        for needle in ["safe", "fast", "duct", "three.", "e", ", p", "Pick three."] {
+            assert_eq!(
+                haystack
+                    .windows(needle.len())
+                    .position(|w| w == needle.as_bytes()),
+                Finder::new(needle.as_bytes()).find(haystack),
+                "{}",
+                needle
+            );
+        }
+        assert_eq!(None, Finder::new(b"slow").find(haystack));
+        assert_eq!(Some(0), Finder::new(b"").find(haystack));
+    }
+
+    #[test]
+    fn falls_back_on_repetitive_input() {
+        // `b` is the rarest byte of the needle but is everywhere here.
+        let mut haystack = b"ab".repeat(5000);
+        haystack.extend_from_slice(b"abx");
+        assert_eq!(Some(10_000), Finder::new(b"abx").find(&haystack));
+        assert_eq!(None, Finder::new(b"bax").find(&haystack));
+    }
+}
@@ -1,19 +1,40 @@
 use std::env;
 use std::process;
 
-use minigrep::{run, Config};
+use minigrep::args::{self, Command};
+use minigrep::{run, run_index};
 
 fn main() {
-    let config = Config::new(env::args()).unwrap_or_else(|err| {
-        eprintln!("Problem parsing arguments: {}", err);
-        process::exit(1);
-    });
+    let config = match args::parse(env::args().skip(1)) {
+        Ok(Command::Search(config)) => config,
+        Ok(Command::Index(dir)) => {
+            if let Err(e) = run_index(&dir) {
+                eprintln!("Application error: {}", e);
+                process::exit(2);
+            }
+            return;
+        }
+        Ok(Command::Help) => {
+            print!("{}", args::HELP);
+            return;
+        }
+        Ok(Command::Version) => {
+            println!("minigrep {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(err) => {
+            eprintln!("Problem parsing arguments: {}", err);
+            eprintln!("{}", args::USAGE);
+            process::exit(2);
+        }
+    };
 
-    println!("Searching for {}", config.query);
-    println!("In file {}\n", config.filename);
-
-    if let Err(e) = run(config) {
-        eprintln!("Application error: {}", e);
-        process::exit(1);
+    // As with grep: 0 if a line was selected, 1 if none was, 2 on error.
+    match run(config) {
+        Ok(outcome) => process::exit(outcome.exit_code()),
+        Err(e) => {
+            eprintln!("Application error: {}", e);
+            process::exit(2);
+        }
     }
 }
@@ -0,0 +1,213 @@
+use std::ops::Range;
+
+use crate::aho_corasick::AhoCorasick;
+use crate::casefold;
+use crate::fuzzy::Fuzzy;
+use crate::literal::Finder;
+use crate::regex::{self, Regex};
+use crate::Config;
+
+/// Finds the patterns of a `Config` in a line.
+pub enum Matcher {
+    Literal(Finder),
+    /// Holds the case-folded pattern.
+    CaseInsensitive(String),
+    /// Several literal patterns, or none at all.
+    Multi(AhoCorasick),
+    Regex(Regex),
+    Fuzzy(Fuzzy),
+}
+
+impl Matcher {
+    pub fn new(config: &Config) -> Result<Matcher, regex::Error> {
+        // With -U, `\n` in a literal pattern is a newline, as in a regex.
+        let unescaped: Vec<String>;
+        let patterns = if config.multiline && !config.regex {
+            unescaped = config
+                .patterns
+                .iter()
+                .map(|pattern| pattern.replace(r"\n", "\n"))
+                .collect();
+            &unescaped
+        } else {
+            &config.patterns
+        };
+        if let (Some(max_distance), [query]) = (config.fuzzy, &patterns[..]) {
+            return Ok(Matcher::Fuzzy(Fuzzy::new(
+                query,
+                max_distance,
+                !config.case_sensitive,
+            )));
+        }
+        if patterns.is_empty() {
+            return Ok(Matcher::Multi(AhoCorasick::new(patterns, false)));
+        }
+
+        if !config.regex && !config.word_regexp {
+            match &patterns[..] {
+                [pattern] if config.case_sensitive => {
+                    return Ok(Matcher::Literal(Finder::new(pattern.as_bytes())))
+                }
+                [pattern] => return Ok(Matcher::CaseInsensitive(casefold::fold_str(pattern))),
+                // Unicode case folding is left to the regex engine.
+                _ if config.case_sensitive => {
+                    return Ok(Matcher::Multi(AhoCorasick::new(patterns, false)))
+                }
+                _ => {}
+            }
+        }
+
+        let mut alternatives = patterns.iter().map(|pattern| {
+            if config.regex {
+                pattern.clone()
+            } else {
+                regex::escape(pattern)
+            }
+        });
+        let mut pattern = if patterns.len() == 1 {
+            alternatives.next().unwrap()
+        } else {
+            alternatives
+                .map(|alternative| format!("(?:{})", alternative))
+                .collect::<Vec<_>>()
+                .join("|")
+        };
+        if config.word_regexp {
+            pattern = format!(r"\b(?:{})\b", pattern);
+        }
+        if !config.case_sensitive {
+            pattern = format!("(?i){}", pattern);
+        }
+        if config.multiline {
+            pattern = format!("(?m){}", pattern);
+        }
+        Ok(Matcher::Regex(Regex::new(&pattern)?))
+    }
+
+    /// Whether every match lies within a single line, so that a whole
+    /// buffer can be searched at once. Regexes may match a newline or
+    /// anchor to line boundaries and fuzzy matches may take a newline for
+    /// a typo, so they are run line by line.
+    pub fn is_line_safe(&self) -> bool {
+        !matches!(self, Matcher::Regex(_) | Matcher::Fuzzy(_))
+    }
+
+    /// How many edits a fuzzy match in `line` needs at the least.
+    pub fn distance(&self, line: &str) -> Option<usize> {
+        match self {
+            Matcher::Fuzzy(fuzzy) => fuzzy.distance(line),
+            _ => None,
+        }
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        match self {
+            Matcher::Literal(finder) => finder.find(line.as_bytes()).is_some(),
+            Matcher::CaseInsensitive(query) => casefold::find(line, query, 0).is_some(),
+            Matcher::Multi(ac) => ac.is_match(line.as_bytes()),
+            Matcher::Regex(regex) => regex.is_match(line),
+            Matcher::Fuzzy(fuzzy) => fuzzy.is_match(line),
+        }
+    }
+
+    /// Find the first match starting at or after byte offset `start`.
+    pub fn find_at(&self, line: &str, start: usize) -> Option<Range<usize>> {
+        match self {
+            Matcher::Literal(finder) => finder
+                .find(&line.as_bytes()[start..])
+                .map(|at| start + at..start + at + finder.needle().len()),
+            Matcher::CaseInsensitive(query) => casefold::find(line, query, start),
+            Matcher::Multi(ac) => ac.find_at(line.as_bytes(), start),
+            Matcher::Regex(regex) => regex.find_at(line, start),
+            Matcher::Fuzzy(fuzzy) => fuzzy.find_at(line, start).map(|(range, _)| range),
+        }
+    }
+
+    /// The spans of the groups of the first match at or after `start`,
+    /// starting with the whole match. Only regexes have other groups.
+    pub fn captures_at(&self, line: &str, start: usize) -> Option<Vec<Option<Range<usize>>>> {
+        match self {
+            Matcher::Regex(regex) => {
+                let captures = regex.captures_at(line, start)?;
+                Some((0..captures.len()).map(|i| captures.get(i)).collect())
+            }
+            _ => Some(vec![Some(self.find_at(line, start)?)]),
+        }
+    }
+
+    /// The byte ranges of all non-overlapping matches in `line`.
+    pub fn find_iter(&self, line: &str) -> Vec<Range<usize>> {
+        let mut matches = Vec::new();
+        let mut start = 0;
+
+        while start <= line.len() {
+            let range = match self.find_at(line, start) {
+                Some(range) => range,
+                None => break,
+            };
+            start = if range.is_empty() {
+                range.end + line[range.end..].chars().next().map_or(1, char::len_utf8)
+            } else {
+                range.end
+            };
+            matches.push(range);
+        }
+
+        matches
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+
+    fn matcher(patterns: &[&str], case_sensitive: bool, regex: bool) -> Matcher {
+        Matcher::new(&Config {
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+            case_sensitive,
+            regex,
+            ..Config::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn finds_all_match_positions() {
+        let line = "Trust rust, RUST!";
+
+        assert_eq!(
+            vec![1..5, 6..10],
+            matcher(&["rust"], true, false).find_iter(line)
+        );
+        assert_eq!(
+            vec![1..5, 6..10, 12..16],
+            matcher(&["rust"], false, false).find_iter(line)
+        );
+        assert_eq!(
+            vec![0..5, 6..10, 12..16],
+            matcher(&[r"\w+"], true, true).find_iter(line)
+        );
+        assert_eq!(vec![1..3], matcher(&["é"], false, false).find_iter("xÉy"));
+    }
+
+    #[test]
+    fn finds_any_of_several_patterns() {
+        let line = "Trust rust, RUST!";
+
+        assert_eq!(
+            vec![0..2, 6..10],
+            matcher(&["rust", "Tr"], true, false).find_iter(line)
+        );
+        assert_eq!(
+            vec![0..5, 6..10, 12..16],
+            matcher(&["rust", "Trust"], false, false).find_iter(line)
+        );
+        assert_eq!(
+            vec![5..6, 10..11, 11..12],
+            matcher(&[r"\s", ","], true, true).find_iter(line)
+        );
+        assert!(!matcher(&[], true, false).is_match(line));
+        assert!(!matcher(&[], false, true).is_match(line));
+    }
+}
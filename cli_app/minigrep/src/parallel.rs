@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Run `work` on every item using `threads` worker threads and pass the
+/// results to `output` in the order of the items.
+///
+/// Items are produced on a thread of their own while the workers run, so
+/// searching starts before a directory walk has finished. If `output`
+/// returns an error, the remaining items are abandoned and the error is
+/// returned once the workers have stopped.
+pub fn map_ordered<I, R, E, W, O>(items: I, threads: usize, work: W, mut output: O) -> Result<(), E>
+where
+    I: Iterator + Send,
+    I::Item: Send,
+    R: Send,
+    W: Fn(I::Item) -> R + Sync,
+    O: FnMut(R) -> Result<(), E>,
+{
+    let threads = threads.max(1);
+
+    thread::scope(|scope| {
+        // Bounded, so that the walk does not run far ahead of the search.
+        let (job_sender, jobs) = mpsc::sync_channel(threads * 4);
+        let jobs = Arc::new(Mutex::new(jobs));
+        let (result_sender, results) = mpsc::channel();
+
+        scope.spawn(move || {
+            for job in items.enumerate() {
+                // Fails once every worker has gone away.
+                if job_sender.send(job).is_err() {
+                    break;
+                }
+            }
+        });
+
+        for _ in 0..threads {
+            let jobs = Arc::clone(&jobs);
+            let result_sender = result_sender.clone();
+            let work = &work;
+
+            scope.spawn(move || loop {
+                let job = jobs.lock().unwrap().recv();
+                let (index, item) = match job {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+                if result_sender.send((index, work(item))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(jobs);
+        drop(result_sender);
+
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        for (index, result) in results {
+            pending.insert(index, result);
+            while let Some(result) = pending.remove(&next) {
+                output(result)?;
+                next += 1;
+            }
+        }
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn keeps_results_in_order() {
+        let mut seen = Vec::new();
+        let result: Result<(), ()> = map_ordered(
+            0..50u64,
+            8,
+            |n| {
+                // Make early items finish last.
+                thread::sleep(Duration::from_millis(50 - n));
+                n * 2
+            },
+            |n| {
+                seen.push(n);
+                Ok(())
+            },
+        );
+
+        assert_eq!(Ok(()), result);
+        assert_eq!((0..50).map(|n| n * 2).collect::<Vec<_>>(), seen);
+    }
+
+    #[test]
+    fn stops_on_output_error() {
+        let mut seen = 0;
+        let result = map_ordered(
+            0..10_000,
+            4,
+            |n| n,
+            |n| {
+                seen += 1;
+                if n == 3 {
+                    Err("stop")
+                } else {
+                    Ok(())
+                }
+            },
+        );
+
+        assert_eq!(Err("stop"), result);
+        assert_eq!(4, seen);
+    }
+}
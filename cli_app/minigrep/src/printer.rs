@@ -0,0 +1,337 @@
+mod json;
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::ops::{AddAssign, Range};
+
+use crate::Config;
+
+pub(crate) const FILENAME: &str = "\x1b[35m";
+pub(crate) const LINE_NUMBER: &str = "\x1b[32m";
+pub(crate) const SEPARATOR: &str = "\x1b[36m";
+pub(crate) const MATCH: &str = "\x1b[1;31m";
+pub(crate) const RESET: &str = "\x1b[0m";
+
+/// When to color the output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+/// What was found in one file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub matched_lines: usize,
+    pub matches: usize,
+}
+
+impl AddAssign for Stats {
+    fn add_assign(&mut self, other: Stats) {
+        self.matched_lines += other.matched_lines;
+        self.matches += other.matches;
+    }
+}
+
+/// What was found in all the files searched.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Summary {
+    pub files_searched: usize,
+    pub files_with_matches: usize,
+    pub matched_lines: usize,
+    pub matches: usize,
+}
+
+impl Summary {
+    pub fn add(&mut self, stats: Stats) {
+        self.files_searched += 1;
+        if stats.matched_lines > 0 {
+            self.files_with_matches += 1;
+        }
+        self.matched_lines += stats.matched_lines;
+        self.matches += stats.matches;
+    }
+
+    /// Print the summary, which only the JSON format has.
+    pub fn print<W: Write>(&self, config: &Config, out: &mut W) -> io::Result<()> {
+        if config.json {
+            json::summary(out, self)?;
+        }
+        Ok(())
+    }
+}
+
+/// A line to print and where it is in the file.
+struct Line<'l> {
+    number: usize,
+    /// Byte offset of the start of the line.
+    offset: usize,
+    text: &'l str,
+    matches: &'l [Range<usize>],
+    /// The edits needed for the query to match, with `--fuzzy`.
+    distance: Option<usize>,
+}
+
+/// Formats the lines of one file in grep's style, or as JSON with `--json`.
+///
+/// Every line of the file is passed in order, either as selected or not;
+/// the printer decides which of the unselected lines to show as context and
+/// where to put `--` between groups of lines.
+pub struct Printer<'a, W> {
+    config: &'a Config,
+    path: Option<String>,
+    color: bool,
+    out: W,
+    /// Unselected lines that may yet be printed as before-context, with
+    /// their numbers and offsets.
+    before: VecDeque<(usize, usize, String)>,
+    /// How many more lines to print as after-context.
+    after: usize,
+    last_printed: Option<usize>,
+    stats: Stats,
+}
+
+impl<'a, W: Write> Printer<'a, W> {
+    /// `path` is printed before every line when given. The JSON format
+    /// always needs it.
+    pub fn new(config: &'a Config, path: Option<String>, color: bool, out: W) -> Printer<'a, W> {
+        Printer {
+            config,
+            path,
+            color,
+            out,
+            before: VecDeque::new(),
+            after: 0,
+            last_printed: None,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Whether `selected` needs the positions of the matches, which are
+    /// otherwise not worth finding.
+    pub fn wants_matches(&self) -> bool {
+        self.color || self.config.json
+    }
+
+    /// A line selected for output, with the ranges to highlight. `offset`
+    /// is the byte offset of the line in the file and `distance` how far
+    /// off a fuzzy match was.
+    pub fn selected(
+        &mut self,
+        number: usize,
+        offset: usize,
+        line: &str,
+        matches: &[Range<usize>],
+        distance: Option<usize>,
+    ) -> io::Result<()> {
+        self.stats.matched_lines += 1 + line.matches('\n').count();
+        self.stats.matches += matches.len();
+
+        while let Some((number, offset, line)) = self.before.pop_front() {
+            self.print(&Line::context(number, offset, &line), '-')?;
+        }
+        let line = Line {
+            number,
+            offset,
+            text: line,
+            matches,
+            distance,
+        };
+        self.print(&line, ':')?;
+        self.after = self.config.after_context;
+        Ok(())
+    }
+
+    pub fn unselected(&mut self, number: usize, offset: usize, line: &str) -> io::Result<()> {
+        if self.after > 0 {
+            self.after -= 1;
+            self.print(&Line::context(number, offset, line), '-')?;
+        } else if self.config.before_context > 0 {
+            if self.before.len() == self.config.before_context {
+                self.before.pop_front();
+            }
+            self.before.push_back((number, offset, line.to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<(W, Stats)> {
+        if self.config.json && self.last_printed.is_some() {
+            let path = self.path.as_deref().unwrap_or_default();
+            json::end(&mut self.out, path, &self.stats)?;
+        }
+        Ok((self.out, self.stats))
+    }
+
+    fn print(&mut self, line: &Line, separator: char) -> io::Result<()> {
+        if self.config.json {
+            let path = self.path.as_deref().unwrap_or_default();
+            if self.last_printed.is_none() {
+                json::begin(&mut self.out, path)?;
+            }
+            self.last_printed = Some(line.number);
+            let kind = if separator == ':' { "match" } else { "context" };
+            return json::line(&mut self.out, kind, path, line);
+        }
+
+        // A line from `--multiline` may be several lines, each printed with
+        // its own number and its part of the matches.
+        let mut start = 0;
+        for (i, text) in line.text.split('\n').enumerate() {
+            let number = line.number + i;
+            let end = start + text.len();
+            let text = text.strip_suffix('\r').unwrap_or(text);
+
+            let has_context = self.config.before_context > 0 || self.config.after_context > 0;
+            if has_context && self.last_printed.is_some_and(|last| last + 1 < number) {
+                self.paint(SEPARATOR, "--")?;
+                self.out.write_all(b"\n")?;
+            }
+            self.last_printed = Some(number);
+
+            if let Some(path) = self.path.take() {
+                self.paint(FILENAME, &path)?;
+                self.paint(SEPARATOR, &separator.to_string())?;
+                self.path = Some(path);
+            }
+            if self.config.line_number {
+                self.paint(LINE_NUMBER, &number.to_string())?;
+                self.paint(SEPARATOR, &separator.to_string())?;
+            }
+            if let Some(distance) = line.distance {
+                self.paint(LINE_NUMBER, &format!("~{}", distance))?;
+                self.paint(SEPARATOR, &separator.to_string())?;
+            }
+
+            let mut at = 0;
+            for range in line.matches {
+                let from = range.start.max(start) - start;
+                let to = range.end.min(start + text.len()).saturating_sub(start);
+                if from >= to {
+                    continue;
+                }
+                self.out.write_all(&text.as_bytes()[at..from])?;
+                self.paint(MATCH, &text[from..to])?;
+                at = to;
+            }
+            self.out.write_all(&text.as_bytes()[at..])?;
+            self.out.write_all(b"\n")?;
+            start = end + 1;
+        }
+        Ok(())
+    }
+
+    fn paint(&mut self, color: &str, text: &str) -> io::Result<()> {
+        if self.color && !text.is_empty() {
+            write!(self.out, "{}{}{}", color, text, RESET)
+        } else {
+            self.out.write_all(text.as_bytes())
+        }
+    }
+}
+
+impl<'l> Line<'l> {
+    fn context(number: usize, offset: usize, text: &'l str) -> Line<'l> {
+        Line {
+            number,
+            offset,
+            text,
+            matches: &[],
+            distance: None,
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+
+    fn print(config: &Config, color: bool, selected: &[usize]) -> String {
+        let mut printer = Printer::new(config, Some("poem".to_string()), color, Vec::new());
+        for number in 1..=10 {
+            let line = format!("line {}", number);
+            let offset = (number - 1) * 7;
+            if selected.contains(&number) {
+                let distance = config.fuzzy.map(|_| number % 2);
+                printer
+                    .selected(number, offset, &line, &[0..4], distance)
+                    .unwrap();
+            } else {
+                printer.unselected(number, offset, &line).unwrap();
+            }
+        }
+        String::from_utf8(printer.finish().unwrap().0).unwrap()
+    }
+
+    #[test]
+    fn prints_context_with_separators() {
+        let config = Config {
+            before_context: 1,
+            after_context: 1,
+            line_number: true,
+            ..Config::default()
+        };
+
+        assert_eq!(
+            "\
+poem:1:line 1
+poem-2-line 2
+--
+poem-4-line 4
+poem:5:line 5
+poem:6:line 6
+poem-7-line 7
+",
+            print(&config, false, &[1, 5, 6])
+        );
+    }
+
+    #[test]
+    fn highlights_matches() {
+        let config = Config::default();
+        assert_eq!(
+            "\x1b[35mpoem\x1b[0m\x1b[36m:\x1b[0m\x1b[1;31mline\x1b[0m 3\n",
+            print(&config, true, &[3])
+        );
+    }
+
+    #[test]
+    fn prints_json_records() {
+        let config = Config {
+            before_context: 1,
+            json: true,
+            ..Config::default()
+        };
+
+        assert_eq!(
+            r#"{"type":"begin","path":"poem"}
+{"type":"context","path":"poem","line_number":2,"offset":7,"line":"line 2","submatches":[]}
+{"type":"match","path":"poem","line_number":3,"offset":14,"line":"line 3","submatches":[{"start":0,"end":4,"text":"line"}]}
+{"type":"end","path":"poem","matched_lines":1,"matches":1}
+"#,
+            print(&config, true, &[3])
+        );
+        assert_eq!("", print(&config, false, &[]));
+    }
+
+    #[test]
+    fn prints_fuzzy_distances() {
+        let config = Config {
+            line_number: true,
+            fuzzy: Some(1),
+            ..Config::default()
+        };
+        assert_eq!(
+            "poem:3:~1:line 3\npoem:4:~0:line 4\n",
+            print(&config, false, &[3, 4])
+        );
+
+        let config = Config {
+            json: true,
+            ..config
+        };
+        assert!(print(&config, false, &[3]).contains(r#""text":"line"}],"distance":1}"#));
+    }
+}
@@ -0,0 +1,128 @@
+//! The `--json` output format: JSON Lines, one object per line.
+//!
+//! Every object has a `type` and the other fields listed here, always in
+//! this order. The format is stable: fields are never removed or given a
+//! new meaning, though new fields and types may be added, so consumers
+//! should ignore what they do not know.
+//!
+//! ```text
+//! {"type":"begin","path":"src/lib.rs"}
+//! {"type":"match","path":"src/lib.rs","line_number":3,"offset":52,"line":"Trust rust","submatches":[{"start":1,"end":5,"text":"rust"},{"start":6,"end":10,"text":"rust"}]}
+//! {"type":"context","path":"src/lib.rs","line_number":4,"offset":63,"line":"Pick three.","submatches":[]}
+//! {"type":"end","path":"src/lib.rs","matched_lines":1,"matches":2}
+//! {"type":"summary","files_searched":12,"files_with_matches":1,"matched_lines":1,"matches":2}
+//! ```
+//!
+//! - `begin` and `end` surround the records of each file with at least one
+//!   selected line; files without one produce no records.
+//! - `match` is a selected line and `context` a line printed around one
+//!   because of `-A`, `-B` or `-C`. `line_number` counts from 1 and
+//!   `offset` is the byte offset of the start of the line in the file.
+//! - With `-U`, a record may span several lines. `line` then holds them
+//!   all, joined by their newlines, and `line_number` is followed by
+//!   `end_line_number`, the number of the last of them.
+//! - `line` is the line without its line terminator, with invalid UTF-8
+//!   replaced by U+FFFD. The `start` and `end` of each submatch are byte
+//!   offsets into `line`. With `-v` the selected lines have no submatches.
+//! - With `--fuzzy`, `match` records end with a `distance` field: the
+//!   fewest edits with which the query occurs in the line.
+//! - `summary` comes last, once for the whole search.
+//! - The path of standard input is `(standard input)`.
+
+use std::io::{self, Write};
+
+use super::{Line, Stats, Summary};
+use crate::literal;
+
+pub fn begin<W: Write>(out: &mut W, path: &str) -> io::Result<()> {
+    out.write_all(br#"{"type":"begin","path":"#)?;
+    string(out, path)?;
+    out.write_all(b"}\n")
+}
+
+/// A `match` or `context` record.
+pub fn line<W: Write>(out: &mut W, kind: &str, path: &str, line: &Line) -> io::Result<()> {
+    write!(out, r#"{{"type":"{}","path":"#, kind)?;
+    string(out, path)?;
+    write!(out, r#","line_number":{}"#, line.number)?;
+    let newlines = literal::count(b'\n', line.text.as_bytes());
+    if newlines > 0 {
+        write!(out, r#","end_line_number":{}"#, line.number + newlines)?;
+    }
+    write!(out, r#","offset":{},"line":"#, line.offset)?;
+    string(out, line.text)?;
+    out.write_all(br#","submatches":["#)?;
+    for (i, range) in line.matches.iter().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        write!(
+            out,
+            r#"{{"start":{},"end":{},"text":"#,
+            range.start, range.end
+        )?;
+        string(out, &line.text[range.clone()])?;
+        out.write_all(b"}")?;
+    }
+    out.write_all(b"]")?;
+    if let Some(distance) = line.distance {
+        write!(out, r#","distance":{}"#, distance)?;
+    }
+    out.write_all(b"}\n")
+}
+
+pub fn end<W: Write>(out: &mut W, path: &str, stats: &Stats) -> io::Result<()> {
+    out.write_all(br#"{"type":"end","path":"#)?;
+    string(out, path)?;
+    writeln!(
+        out,
+        r#","matched_lines":{},"matches":{}}}"#,
+        stats.matched_lines, stats.matches
+    )
+}
+
+pub fn summary<W: Write>(out: &mut W, summary: &Summary) -> io::Result<()> {
+    writeln!(
+        out,
+        r#"{{"type":"summary","files_searched":{},"files_with_matches":{},"matched_lines":{},"matches":{}}}"#,
+        summary.files_searched, summary.files_with_matches, summary.matched_lines, summary.matches
+    )
+}
+
+/// Write `s` as a JSON string, quotes included.
+fn string<W: Write>(out: &mut W, s: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        let escaped = match c {
+            '"' => "\\\"",
+            '\\' => "\\\\",
+            '\n' => "\\n",
+            '\r' => "\\r",
+            '\t' => "\\t",
+            '\u{0}'..='\u{1f}' => "",
+            _ => continue,
+        };
+        out.write_all(&s.as_bytes()[start..i])?;
+        if escaped.is_empty() {
+            write!(out, "\\u{:04x}", c as u32)?;
+        } else {
+            out.write_all(escaped.as_bytes())?;
+        }
+        start = i + c.len_utf8();
+    }
+    out.write_all(&s.as_bytes()[start..])?;
+    out.write_all(b"\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_strings() {
+        let mut out = Vec::new();
+        string(&mut out, "a\"b\\c\td\n\u{1}é").unwrap();
+        assert_eq!(r#""a\"b\\c\td\n\u0001é""#, String::from_utf8(out).unwrap());
+    }
+}
@@ -0,0 +1,1072 @@
+//! A small backtracking-free regular expression engine.
+//!
+//! Patterns are parsed into a syntax tree, compiled to a program for a Pike
+//! VM and run over the input in a single pass, so matching time is linear in
+//! the length of the input no matter how the pattern is written.
+//!
+//! Supported syntax:
+//!
+//! * literals, `.`, escapes such as `\.` `\n` `\t` `\x41` `\x{263a}`
+//! * classes `[abc]` `[^a-z]` `[[:alpha:]]` and `\d` `\w` `\s` `\D` `\W` `\S`
+//! * anchors `^` `$` `\A` `\z` and word boundaries `\b` `\B`
+//! * alternation `a|b` and groups `(a)` `(?:a)`
+//! * repetition `*` `+` `?` `{n}` `{n,}` `{n,m}` and their lazy forms (`*?`)
+//! * flags `(?i)` `(?m)` `(?s)` and scoped flags `(?i:a)`
+
+mod literals;
+
+use std::error;
+use std::fmt;
+use std::ops::Range;
+
+use crate::casefold;
+
+/// Largest count accepted in a `{n,m}` repetition.
+const MAX_REPEAT: u32 = 1000;
+
+/// Largest number of instructions a pattern may compile to. Repetitions
+/// copy what they repeat, so nested counts multiply.
+const MAX_PROGRAM_SIZE: usize = 1_000_000;
+
+/// Deepest nesting of groups accepted, as parsing recurses into each.
+const MAX_NESTING: usize = 250;
+
+#[derive(Debug, PartialEq)]
+pub struct Error {
+    message: &'static str,
+    position: usize,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid regex: {} at position {}",
+            self.message, self.position
+        )
+    }
+}
+
+impl error::Error for Error {}
+
+/// Escape the special characters of `text` so that it matches literally.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\.+*?()|[]{}^$#&-~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[derive(Clone, Copy, Default)]
+struct Flags {
+    case_insensitive: bool,
+    multi_line: bool,
+    dot_all: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Assertion {
+    StartText,
+    EndText,
+    StartLine,
+    EndLine,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Perl {
+    Digit,
+    Word,
+    Space,
+}
+
+#[derive(Clone, Debug)]
+enum ClassItem {
+    Range(char, char),
+    Perl(Perl, bool),
+}
+
+#[derive(Clone, Debug)]
+struct Class {
+    items: Vec<ClassItem>,
+    negated: bool,
+    case_insensitive: bool,
+}
+
+impl Class {
+    fn perl(perl: Perl, negated: bool) -> Class {
+        Class {
+            items: vec![ClassItem::Perl(perl, negated)],
+            negated: false,
+            case_insensitive: false,
+        }
+    }
+
+    fn matches(&self, c: char) -> bool {
+        let found = self.contains(c)
+            || (self.case_insensitive && case_variants(c).any(|v| self.contains(v)));
+        found != self.negated
+    }
+
+    fn contains(&self, c: char) -> bool {
+        self.items.iter().any(|item| match *item {
+            ClassItem::Range(start, end) => start <= c && c <= end,
+            ClassItem::Perl(perl, negated) => perl_matches(perl, c) != negated,
+        })
+    }
+}
+
+fn perl_matches(perl: Perl, c: char) -> bool {
+    match perl {
+        Perl::Digit => c.is_ascii_digit(),
+        Perl::Word => is_word_char(c),
+        Perl::Space => c.is_whitespace(),
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The characters `c` matches when case is ignored, other than itself: its
+/// single-character lower and upper case forms and its case folding.
+fn case_variants(c: char) -> impl Iterator<Item = char> {
+    let single = |mut chars: Box<dyn Iterator<Item = char>>| match (chars.next(), chars.next()) {
+        (Some(variant), None) if variant != c => Some(variant),
+        _ => None,
+    };
+
+    let lower = single(Box::new(c.to_lowercase()));
+    let upper = single(Box::new(c.to_uppercase()));
+    let folded = Some(casefold::simple_fold(c)).filter(|&folded| folded != c);
+    lower.into_iter().chain(upper).chain(folded)
+}
+
+fn chars_match(pattern: char, c: char, case_insensitive: bool) -> bool {
+    pattern == c || (case_insensitive && casefold::simple_fold(pattern) == casefold::simple_fold(c))
+}
+
+#[derive(Debug)]
+enum Node {
+    Empty,
+    Literal(char, bool),
+    Any(bool),
+    Class(Class),
+    Assert(Assertion),
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    groups: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &'static str) -> Error {
+        Error {
+            message,
+            position: self.pos,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_alternation(&mut self, flags: &mut Flags) -> Result<Node, Error> {
+        if self.depth == MAX_NESTING {
+            return Err(self.error("groups nested too deeply"));
+        }
+        self.depth += 1;
+        let mut branches = vec![self.parse_concat(flags)?];
+        while self.eat('|') {
+            branches.push(self.parse_concat(flags)?);
+        }
+        self.depth -= 1;
+
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            Node::Alternate(branches)
+        })
+    }
+
+    fn parse_concat(&mut self, flags: &mut Flags) -> Result<Node, Error> {
+        let mut items = Vec::new();
+
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom(flags)?;
+            items.push(self.parse_repeat(atom)?);
+        }
+
+        Ok(match items.len() {
+            0 => Node::Empty,
+            1 => items.pop().unwrap(),
+            _ => Node::Concat(items),
+        })
+    }
+
+    fn parse_atom(&mut self, flags: &mut Flags) -> Result<Node, Error> {
+        let c = self.next().unwrap();
+
+        Ok(match c {
+            '(' => return self.parse_group(flags),
+            '[' => Node::Class(self.parse_class(flags)?),
+            '.' => Node::Any(flags.dot_all),
+            '^' if flags.multi_line => Node::Assert(Assertion::StartLine),
+            '^' => Node::Assert(Assertion::StartText),
+            '$' if flags.multi_line => Node::Assert(Assertion::EndLine),
+            '$' => Node::Assert(Assertion::EndText),
+            '\\' => self.parse_escape(flags)?,
+            '*' | '+' | '?' => return Err(self.error("repetition operator missing expression")),
+            c => Node::Literal(c, flags.case_insensitive),
+        })
+    }
+
+    fn parse_group(&mut self, flags: &mut Flags) -> Result<Node, Error> {
+        let mut inner = *flags;
+        let mut index = None;
+
+        if self.eat('?') {
+            let mut enable = true;
+            loop {
+                match self.next() {
+                    Some('i') => inner.case_insensitive = enable,
+                    Some('m') => inner.multi_line = enable,
+                    Some('s') => inner.dot_all = enable,
+                    Some('-') if enable => enable = false,
+                    Some(':') => break,
+                    Some(')') => {
+                        // `(?i)` changes the flags for the rest of the group.
+                        *flags = inner;
+                        return Ok(Node::Empty);
+                    }
+                    Some(_) => return Err(self.error("unknown group flag")),
+                    None => return Err(self.error("unclosed group")),
+                }
+            }
+        } else {
+            self.groups += 1;
+            index = Some(self.groups);
+        }
+
+        let node = self.parse_alternation(&mut inner)?;
+        if !self.eat(')') {
+            return Err(self.error("unclosed group"));
+        }
+
+        Ok(Node::Group(Box::new(node), index))
+    }
+
+    fn parse_repeat(&mut self, atom: Node) -> Result<Node, Error> {
+        let start = self.pos;
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => match self.parse_counts()? {
+                Some(counts) => counts,
+                None => return Ok(atom),
+            },
+            _ => return Ok(atom),
+        };
+        if self.pos == start {
+            self.pos += 1;
+        }
+
+        if matches!(atom, Node::Empty | Node::Assert(_)) {
+            return Err(Error {
+                message: "repetition operator missing expression",
+                position: start,
+            });
+        }
+
+        let greedy = !self.eat('?');
+        let node = Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+            greedy,
+        };
+        if program_size(&node) > MAX_PROGRAM_SIZE {
+            return Err(Error {
+                message: "repetition makes the regex too large",
+                position: start,
+            });
+        }
+        Ok(node)
+    }
+
+    /// Parse `{n}`, `{n,}` or `{n,m}`. Anything else is left alone so that
+    /// the brace is taken literally.
+    fn parse_counts(&mut self) -> Result<Option<(u32, Option<u32>)>, Error> {
+        let start = self.pos;
+        self.pos += 1;
+
+        let min = self.parse_number();
+        let max = if self.eat(',') {
+            self.parse_number()
+        } else {
+            min
+        };
+
+        match (min, self.eat('}')) {
+            (Some(min), true) => {
+                if min > MAX_REPEAT || max.is_some_and(|max| max > MAX_REPEAT) {
+                    return Err(self.error("repetition count too large"));
+                }
+                if max.is_some_and(|max| max < min) {
+                    return Err(self.error("invalid repetition range"));
+                }
+                Ok(Some((min, max)))
+            }
+            _ => {
+                self.pos = start;
+                Ok(None)
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Option<u32> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        if digits.is_empty() {
+            None
+        } else {
+            // Overlong counts are reported as too large by the caller.
+            Some(digits.parse().unwrap_or(u32::MAX))
+        }
+    }
+
+    fn parse_escape(&mut self, flags: &Flags) -> Result<Node, Error> {
+        let c = self
+            .next()
+            .ok_or_else(|| self.error("trailing backslash"))?;
+
+        Ok(match c {
+            'd' => Node::Class(Class::perl(Perl::Digit, false)),
+            'D' => Node::Class(Class::perl(Perl::Digit, true)),
+            'w' => Node::Class(Class::perl(Perl::Word, false)),
+            'W' => Node::Class(Class::perl(Perl::Word, true)),
+            's' => Node::Class(Class::perl(Perl::Space, false)),
+            'S' => Node::Class(Class::perl(Perl::Space, true)),
+            'b' => Node::Assert(Assertion::WordBoundary),
+            'B' => Node::Assert(Assertion::NotWordBoundary),
+            'A' => Node::Assert(Assertion::StartText),
+            'z' => Node::Assert(Assertion::EndText),
+            _ => Node::Literal(self.escaped_char(c)?, flags.case_insensitive),
+        })
+    }
+
+    /// The character an escape such as `\n` or `\x41` stands for.
+    fn escaped_char(&mut self, c: char) -> Result<char, Error> {
+        Ok(match c {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            'f' => '\x0c',
+            'v' => '\x0b',
+            '0' => '\0',
+            'x' => self.parse_hex()?,
+            c if c.is_ascii_alphanumeric() => return Err(self.error("unknown escape")),
+            c => c,
+        })
+    }
+
+    fn parse_hex(&mut self) -> Result<char, Error> {
+        let digits: String = if self.eat('{') {
+            let start = self.pos;
+            while self.peek().is_some_and(|c| c != '}') {
+                self.pos += 1;
+            }
+            let digits = self.chars[start..self.pos].iter().collect();
+            if !self.eat('}') {
+                return Err(self.error("unclosed hex escape"));
+            }
+            digits
+        } else {
+            (0..2).filter_map(|_| self.next()).collect()
+        };
+
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error("invalid hex escape"))
+    }
+
+    fn parse_class(&mut self, flags: &Flags) -> Result<Class, Error> {
+        let mut class = Class {
+            items: Vec::new(),
+            negated: self.eat('^'),
+            case_insensitive: flags.case_insensitive,
+        };
+
+        let mut first = true;
+        loop {
+            let c = self.next().ok_or_else(|| self.error("unclosed class"))?;
+            let start = match c {
+                ']' if !first => break,
+                '[' if self.peek() == Some(':') => {
+                    class.items.extend(self.parse_posix_class()?);
+                    first = false;
+                    continue;
+                }
+                '\\' => {
+                    let c = self
+                        .next()
+                        .ok_or_else(|| self.error("trailing backslash"))?;
+                    match c {
+                        'd' | 'D' | 'w' | 'W' | 's' | 'S' => {
+                            let perl = match c.to_ascii_lowercase() {
+                                'd' => Perl::Digit,
+                                'w' => Perl::Word,
+                                _ => Perl::Space,
+                            };
+                            class
+                                .items
+                                .push(ClassItem::Perl(perl, c.is_ascii_uppercase()));
+                            first = false;
+                            continue;
+                        }
+                        c => self.escaped_char(c)?,
+                    }
+                }
+                c => c,
+            };
+            first = false;
+
+            let end = if self.peek() == Some('-') && self.chars.get(self.pos + 1) != Some(&']') {
+                self.pos += 1;
+                match self.next() {
+                    Some('\\') => {
+                        let c = self
+                            .next()
+                            .ok_or_else(|| self.error("trailing backslash"))?;
+                        self.escaped_char(c)?
+                    }
+                    Some(c) => c,
+                    None => return Err(self.error("unclosed class")),
+                }
+            } else {
+                start
+            };
+
+            if end < start {
+                return Err(self.error("invalid class range"));
+            }
+            class.items.push(ClassItem::Range(start, end));
+        }
+
+        Ok(class)
+    }
+
+    fn parse_posix_class(&mut self) -> Result<Vec<ClassItem>, Error> {
+        let start = self.pos + 1;
+        let end = (start..self.chars.len())
+            .find(|&i| self.chars[i] == ':' && self.chars.get(i + 1) == Some(&']'))
+            .ok_or_else(|| self.error("unclosed class"))?;
+        let name: String = self.chars[start..end].iter().collect();
+        self.pos = end + 2;
+
+        let range = ClassItem::Range;
+        Ok(match name.as_str() {
+            "alpha" => vec![range('a', 'z'), range('A', 'Z')],
+            "digit" => vec![range('0', '9')],
+            "alnum" => vec![range('a', 'z'), range('A', 'Z'), range('0', '9')],
+            "upper" => vec![range('A', 'Z')],
+            "lower" => vec![range('a', 'z')],
+            "space" => vec![range('\t', '\r'), range(' ', ' ')],
+            "blank" => vec![range('\t', '\t'), range(' ', ' ')],
+            "punct" => vec![
+                range('!', '/'),
+                range(':', '@'),
+                range('[', '`'),
+                range('{', '~'),
+            ],
+            "xdigit" => vec![range('0', '9'), range('a', 'f'), range('A', 'F')],
+            "word" => vec![ClassItem::Perl(Perl::Word, false)],
+            _ => return Err(self.error("unknown class name")),
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Inst {
+    Char(char, bool),
+    Any(bool),
+    Class(Class),
+    Assert(Assertion),
+    Split(usize, usize),
+    Jump(usize),
+    Save(usize),
+    Match,
+}
+
+/// The number of instructions `compile` turns `node` into.
+fn program_size(node: &Node) -> usize {
+    match node {
+        Node::Empty => 0,
+        Node::Literal(..) | Node::Any(_) | Node::Class(_) | Node::Assert(_) => 1,
+        Node::Group(node, None) => program_size(node),
+        Node::Group(node, Some(_)) => program_size(node).saturating_add(2),
+        Node::Concat(nodes) => nodes
+            .iter()
+            .fold(0, |size, node| size.saturating_add(program_size(node))),
+        Node::Alternate(nodes) => nodes.iter().fold(2 * (nodes.len() - 1), |size, node| {
+            size.saturating_add(program_size(node))
+        }),
+        Node::Repeat { node, min, max, .. } => {
+            let size = program_size(node);
+            let optional = match max {
+                None => size.saturating_add(2),
+                Some(max) => ((max - min) as usize).saturating_mul(size.saturating_add(1)),
+            };
+            (*min as usize)
+                .saturating_mul(size)
+                .saturating_add(optional)
+        }
+    }
+}
+
+fn compile(node: &Node, program: &mut Vec<Inst>) {
+    match node {
+        Node::Empty => {}
+        Node::Literal(c, case_insensitive) => program.push(Inst::Char(*c, *case_insensitive)),
+        Node::Any(dot_all) => program.push(Inst::Any(*dot_all)),
+        Node::Class(class) => program.push(Inst::Class(class.clone())),
+        Node::Assert(assertion) => program.push(Inst::Assert(*assertion)),
+        Node::Group(node, None) => compile(node, program),
+        Node::Group(node, Some(index)) => {
+            program.push(Inst::Save(index * 2));
+            compile(node, program);
+            program.push(Inst::Save(index * 2 + 1));
+        }
+        Node::Concat(nodes) => {
+            for node in nodes {
+                compile(node, program);
+            }
+        }
+        Node::Alternate(nodes) => {
+            let mut jumps = Vec::new();
+            for (i, node) in nodes.iter().enumerate() {
+                if i + 1 < nodes.len() {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(node, program);
+                    jumps.push(program.len());
+                    program.push(Inst::Jump(0));
+                    program[split] = Inst::Split(split + 1, program.len());
+                } else {
+                    compile(node, program);
+                }
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat {
+            node,
+            min,
+            max,
+            greedy,
+        } => {
+            for _ in 0..*min {
+                compile(node, program);
+            }
+
+            let split = |program: &mut Vec<Inst>, at: usize, body: usize, exit: usize| {
+                program[at] = if *greedy {
+                    Inst::Split(body, exit)
+                } else {
+                    Inst::Split(exit, body)
+                };
+            };
+
+            match max {
+                None => {
+                    let start = program.len();
+                    program.push(Inst::Split(0, 0));
+                    compile(node, program);
+                    program.push(Inst::Jump(start));
+                    let end = program.len();
+                    split(program, start, start + 1, end);
+                }
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(0, 0));
+                        compile(node, program);
+                    }
+                    let end = program.len();
+                    for at in splits {
+                        split(program, at, at + 1, end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A compiled regular expression.
+#[derive(Debug)]
+pub struct Regex {
+    program: Vec<Inst>,
+    slots: usize,
+    required: literals::Required,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, Error> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            groups: 0,
+            depth: 0,
+        };
+
+        let node = parser.parse_alternation(&mut Flags::default())?;
+        if parser.pos < parser.chars.len() {
+            return Err(parser.error("unmatched closing parenthesis"));
+        }
+        if program_size(&node) > MAX_PROGRAM_SIZE {
+            return Err(Error {
+                message: "regex too large",
+                position: 0,
+            });
+        }
+
+        let mut program = vec![Inst::Save(0)];
+        compile(&node, &mut program);
+        program.push(Inst::Save(1));
+        program.push(Inst::Match);
+
+        Ok(Regex {
+            program,
+            slots: (parser.groups + 1) * 2,
+            required: literals::required(&node),
+        })
+    }
+
+    /// Strings that every match contains: all of those in at least one of
+    /// the lists. A list without strings means there is nothing to go on.
+    pub fn required(&self) -> &[Vec<String>] {
+        &self.required
+    }
+
+    /// The number of capture groups, counting the whole match as group 0.
+    pub fn captures_len(&self) -> usize {
+        self.slots / 2
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find(text).is_some()
+    }
+
+    pub fn find(&self, text: &str) -> Option<Range<usize>> {
+        self.find_at(text, 0)
+    }
+
+    /// Find the leftmost match starting at or after byte offset `start`.
+    ///
+    /// Unlike searching `&text[start..]`, anchors and word boundaries still
+    /// see the text before `start`.
+    pub fn find_at(&self, text: &str, start: usize) -> Option<Range<usize>> {
+        self.captures_at(text, start)?.get(0)
+    }
+
+    pub fn find_iter<'r, 't>(&'r self, text: &'t str) -> impl Iterator<Item = Range<usize>> + 'r
+    where
+        't: 'r,
+    {
+        self.captures_iter(text)
+            .filter_map(|captures| captures.get(0))
+    }
+
+    pub fn captures<'t>(&self, text: &'t str) -> Option<Captures<'t>> {
+        self.captures_at(text, 0)
+    }
+
+    pub fn captures_at<'t>(&self, text: &'t str, start: usize) -> Option<Captures<'t>> {
+        let slots = self.pike(text, start)?;
+        Some(Captures { text, slots })
+    }
+
+    /// Iterate over successive non-overlapping matches.
+    pub fn captures_iter<'r, 't>(&'r self, text: &'t str) -> CapturesIter<'r, 't> {
+        CapturesIter {
+            regex: self,
+            text,
+            pos: 0,
+            last_end: None,
+        }
+    }
+
+    fn pike(&self, text: &str, start: usize) -> Option<Vec<Option<usize>>> {
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        let mut matched = None;
+        let mut pos = start;
+
+        loop {
+            if matched.is_none() {
+                let mut slots = vec![None; self.slots];
+                self.add_thread(&mut current, text, 0, pos, &mut slots);
+            }
+            if current.list.is_empty() && matched.is_some() {
+                break;
+            }
+
+            let c = text[pos..].chars().next();
+            let next_pos = pos + c.map_or(0, char::len_utf8);
+
+            for (pc, slots) in current.list.drain(..) {
+                let step = match &self.program[pc] {
+                    Inst::Match => {
+                        matched = Some(slots);
+                        // Threads after this one have lower priority.
+                        break;
+                    }
+                    Inst::Char(p, case_insensitive) => {
+                        c.is_some_and(|c| chars_match(*p, c, *case_insensitive))
+                    }
+                    Inst::Any(dot_all) => c.is_some_and(|c| *dot_all || c != '\n'),
+                    Inst::Class(class) => c.is_some_and(|c| class.matches(c)),
+                    _ => false,
+                };
+                if step {
+                    let mut slots = slots;
+                    self.add_thread(&mut next, text, pc + 1, next_pos, &mut slots);
+                }
+            }
+
+            if c.is_none() {
+                break;
+            }
+            pos = next_pos;
+            current.clear();
+            std::mem::swap(&mut current, &mut next);
+        }
+
+        matched
+    }
+
+    /// Add the thread at `pc` to `threads`, following jumps, splits, saves
+    /// and assertions to the instructions that consume a character. An
+    /// explicit stack is used rather than recursion, as a long run of
+    /// splits would otherwise overflow the call stack.
+    fn add_thread(
+        &self,
+        threads: &mut Threads,
+        text: &str,
+        pc: usize,
+        pos: usize,
+        slots: &mut [Option<usize>],
+    ) {
+        threads.stack.push(Step::Visit(pc));
+
+        while let Some(step) = threads.stack.pop() {
+            let pc = match step {
+                Step::Visit(pc) => pc,
+                Step::Restore(slot, old) => {
+                    slots[slot] = old;
+                    continue;
+                }
+            };
+            if threads.seen[pc] {
+                continue;
+            }
+            threads.seen[pc] = true;
+
+            match self.program[pc] {
+                Inst::Jump(target) => threads.stack.push(Step::Visit(target)),
+                Inst::Split(first, second) => {
+                    threads.stack.push(Step::Visit(second));
+                    threads.stack.push(Step::Visit(first));
+                }
+                Inst::Save(slot) => {
+                    let old = slots[slot].replace(pos);
+                    threads.stack.push(Step::Restore(slot, old));
+                    threads.stack.push(Step::Visit(pc + 1));
+                }
+                Inst::Assert(assertion) => {
+                    if assert(assertion, text, pos) {
+                        threads.stack.push(Step::Visit(pc + 1));
+                    }
+                }
+                _ => threads.list.push((pc, slots.to_vec())),
+            }
+        }
+    }
+}
+
+/// Work left for `Regex::add_thread`: an instruction to visit, or a slot to
+/// put back once everything after a `Save` has been visited.
+enum Step {
+    Visit(usize),
+    Restore(usize, Option<usize>),
+}
+
+struct Threads {
+    list: Vec<(usize, Vec<Option<usize>>)>,
+    seen: Vec<bool>,
+    stack: Vec<Step>,
+}
+
+impl Threads {
+    fn new(len: usize) -> Threads {
+        Threads {
+            list: Vec::new(),
+            seen: vec![false; len],
+            stack: Vec::new(),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        self.seen.iter_mut().for_each(|seen| *seen = false);
+    }
+}
+
+fn assert(assertion: Assertion, text: &str, pos: usize) -> bool {
+    let before = text[..pos].chars().next_back();
+    let after = text[pos..].chars().next();
+
+    match assertion {
+        Assertion::StartText => pos == 0,
+        Assertion::EndText => pos == text.len(),
+        Assertion::StartLine => before.is_none_or(|c| c == '\n'),
+        Assertion::EndLine => after.is_none_or(|c| c == '\n'),
+        Assertion::WordBoundary | Assertion::NotWordBoundary => {
+            let boundary = before.is_some_and(is_word_char) != after.is_some_and(is_word_char);
+            boundary == (assertion == Assertion::WordBoundary)
+        }
+    }
+}
+
+/// The spans of the groups of one match.
+#[derive(Debug)]
+pub struct Captures<'t> {
+    text: &'t str,
+    slots: Vec<Option<usize>>,
+}
+
+impl<'t> Captures<'t> {
+    /// The byte range of group `i`, if it took part in the match.
+    pub fn get(&self, i: usize) -> Option<Range<usize>> {
+        match (self.slots.get(i * 2)?, self.slots.get(i * 2 + 1)?) {
+            (Some(start), Some(end)) => Some(*start..*end),
+            _ => None,
+        }
+    }
+
+    pub fn text(&self, i: usize) -> Option<&'t str> {
+        self.get(i).map(|range| &self.text[range])
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+}
+
+pub struct CapturesIter<'r, 't> {
+    regex: &'r Regex,
+    text: &'t str,
+    pos: usize,
+    last_end: Option<usize>,
+}
+
+impl<'r, 't> Iterator for CapturesIter<'r, 't> {
+    type Item = Captures<'t>;
+
+    fn next(&mut self) -> Option<Captures<'t>> {
+        loop {
+            if self.pos > self.text.len() {
+                return None;
+            }
+
+            let captures = self.regex.captures_at(self.text, self.pos)?;
+            let range = captures.get(0).unwrap();
+
+            if range.is_empty() {
+                // Step past an empty match so the next search makes progress.
+                self.pos = range.end
+                    + self.text[range.end..]
+                        .chars()
+                        .next()
+                        .map_or(1, char::len_utf8);
+                // An empty match right after the previous match is skipped.
+                if Some(range.end) == self.last_end {
+                    continue;
+                }
+            } else {
+                self.pos = range.end;
+            }
+
+            self.last_end = Some(range.end);
+            return Some(captures);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find<'t>(pattern: &str, text: &'t str) -> Option<&'t str> {
+        Regex::new(pattern).unwrap().find(text).map(|m| &text[m])
+    }
+
+    #[test]
+    fn literals_and_classes() {
+        assert_eq!(Some("fast"), find("fa.t", "safe, fast, productive."));
+        assert_eq!(Some("2024"), find(r"\d+", "year 2024!"));
+        assert_eq!(Some("B52"), find("[A-Z][0-9]+", "the B52s"));
+        assert_eq!(Some("x"), find("[^a-w]", "abcx"));
+        assert_eq!(Some("a-b"), find("[[:alpha:]][-]b", "a-b"));
+        assert_eq!(Some("."), find(r"\.", "a.b"));
+        assert_eq!(None, find("[abc]", "xyz"));
+    }
+
+    #[test]
+    fn anchors_and_boundaries() {
+        assert_eq!(Some("Rust"), find("^Rust", "Rust:"));
+        assert_eq!(None, find("^rust", "Trust me."));
+        assert_eq!(Some("me."), find(r"me\.$", "Trust me."));
+        assert_eq!(Some("rust"), find(r"\brust\b", "trust rust"));
+        assert_eq!(Some("b"), find("(?m)^b", "a\nb"));
+    }
+
+    #[test]
+    fn alternation_and_repetition() {
+        assert_eq!(Some("three"), find("one|three", "Pick three."));
+        assert_eq!(Some("aaa"), find("a{2,3}", "aaaa"));
+        assert_eq!(Some("aa"), find("a{2}", "aaaa"));
+        assert_eq!(Some("<a>"), find("<.+?>", "<a><b>"));
+        assert_eq!(Some("<a><b>"), find("<.+>", "<a><b>"));
+        assert_eq!(Some("colour"), find("colou?r", "colour"));
+        assert_eq!(Some("a{x"), find("a{x", "a{x"));
+    }
+
+    #[test]
+    fn capture_groups() {
+        let re = Regex::new(r"(\w+)@(\w+)(\.com)?").unwrap();
+        let captures = re.captures("mail ferris@rust now").unwrap();
+
+        assert_eq!(4, re.captures_len());
+        assert_eq!(Some("ferris@rust"), captures.text(0));
+        assert_eq!(Some("ferris"), captures.text(1));
+        assert_eq!(Some("rust"), captures.text(2));
+        assert_eq!(None, captures.text(3));
+    }
+
+    #[test]
+    fn case_insensitive_flag() {
+        assert_eq!(Some("RuSt"), find("(?i)rust", "RuSt"));
+        assert_eq!(Some("Tr"), find("(?i)[a-z]r", "Tr"));
+        assert_eq!(None, find("(?i:r)UST", "Rust"));
+        assert_eq!(Some("ΟΔΥΣΣΕΥΣ"), find("(?i)οδυσσευς", "ΟΔΥΣΣΕΥΣ"));
+        assert_eq!(Some("\u{212a}"), find("(?i)[a-z]", "\u{212a}"));
+    }
+
+    #[test]
+    fn iterates_non_overlapping_matches() {
+        let re = Regex::new("a*").unwrap();
+        let matches: Vec<_> = re.find_iter("baaac").collect();
+        assert_eq!(vec![0..0, 1..4, 5..5], matches);
+
+        let re = Regex::new(r"\d").unwrap();
+        assert_eq!(3, re.find_iter("1 2 3").count());
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        assert!(Regex::new("(a").is_err());
+        assert!(Regex::new("a)").is_err());
+        assert!(Regex::new("[a").is_err());
+        assert!(Regex::new("*a").is_err());
+        assert!(Regex::new("[z-a]").is_err());
+        assert!(Regex::new(r"\q").is_err());
+        assert!(Regex::new("a{5000}").is_err());
+    }
+
+    #[test]
+    fn rejects_nested_repetitions_that_compile_too_large() {
+        let error = Regex::new("((a{1000}){1000}){1000}").unwrap_err();
+        assert_eq!("repetition makes the regex too large", error.message);
+        assert!(Regex::new(&"(?:a{1000})".repeat(1001)).is_err());
+        assert!(Regex::new("((a{10}){10}){10}").is_ok());
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let deep = format!("{}a{}", "(".repeat(100_000), ")".repeat(100_000));
+        assert_eq!(
+            "groups nested too deeply",
+            Regex::new(&deep).unwrap_err().message
+        );
+        let nested = format!("{}a{}", "(?:".repeat(200), ")".repeat(200));
+        assert_eq!(Some("a"), find(&nested, "cat"));
+    }
+
+    #[test]
+    fn long_alternations_do_not_overflow_the_stack() {
+        let words: Vec<String> = (0..100_000).map(|i| format!("w{}x", i)).collect();
+        let re = Regex::new(&words.join("|")).unwrap();
+        assert!(re.is_match("w99999x"));
+    }
+
+    #[test]
+    fn escapes_special_characters() {
+        let text = "a.b*(c)[d]{2}|$^\\";
+        assert_eq!(Some(text), find(&escape(text), text));
+    }
+
+    #[test]
+    fn pathological_pattern_is_linear() {
+        let text = "a".repeat(50);
+        let pattern = format!("{}{}", "a?".repeat(50), "a".repeat(50));
+        assert!(Regex::new(&pattern).unwrap().is_match(&text));
+    }
+}
@@ -0,0 +1,182 @@
+//! The strings that every match of a pattern contains, which tell a search
+//! that input lacking them cannot match without running the pattern on it.
+//!
+//! This is worked out from the syntax tree: a concatenation needs what each
+//! of its parts needs, an alternation what one of its branches needs, and
+//! runs of literal characters are joined into strings. What a repetition
+//! that may match nothing, a class or `.` needs is unknown.
+
+use super::Node;
+use crate::casefold;
+
+/// How many strings a node may match for them all to be kept, and how many
+/// alternatives `Required` may grow to, before giving up on knowing more.
+const MAX_EXACT: usize = 16;
+const MAX_CLAUSES: usize = 64;
+
+/// A match contains every string of at least one of the lists. A list
+/// without strings means nothing is known.
+pub type Required = Vec<Vec<String>>;
+
+struct Info {
+    /// Every string the node can match, if there are only a few.
+    exact: Option<Vec<String>>,
+    /// What the node needs besides.
+    required: Required,
+}
+
+pub fn required(node: &Node) -> Required {
+    complete(analyze(node))
+}
+
+fn analyze(node: &Node) -> Info {
+    match node {
+        Node::Empty | Node::Assert(_) => exactly(String::new()),
+        Node::Literal(c, case_insensitive) => {
+            if !case_insensitive || casefold::is_ascii_only(*c) {
+                exactly(c.to_string())
+            } else {
+                unknown()
+            }
+        }
+        Node::Any(_) | Node::Class(_) => unknown(),
+        Node::Group(node, _) => analyze(node),
+        Node::Concat(nodes) => {
+            let mut exact = Some(vec![String::new()]);
+            let mut required = anything();
+
+            for node in nodes {
+                let info = analyze(node);
+                required = and(required, info.required);
+                exact = match (exact, info.exact) {
+                    (Some(before), Some(next)) if before.len() * next.len() <= MAX_EXACT => Some(
+                        before
+                            .iter()
+                            .flat_map(|a| next.iter().map(move |b| format!("{}{}", a, b)))
+                            .collect(),
+                    ),
+                    (before, next) => {
+                        if let Some(before) = before {
+                            required = and(required, any_of(before));
+                        }
+                        next
+                    }
+                };
+            }
+
+            Info { exact, required }
+        }
+        Node::Alternate(nodes) => {
+            let infos: Vec<Info> = nodes.iter().map(analyze).collect();
+            let exact: Option<Vec<String>> = infos
+                .iter()
+                .map(|info| info.exact.clone())
+                .collect::<Option<Vec<_>>>()
+                .map(|exact| exact.concat())
+                .filter(|exact| exact.len() <= MAX_EXACT);
+            if let Some(exact) = exact {
+                return Info {
+                    exact: Some(exact),
+                    required: anything(),
+                };
+            }
+
+            let mut required = infos.into_iter().map(complete);
+            let first = required.next().unwrap_or_else(anything);
+            Info {
+                exact: None,
+                required: required.fold(first, or),
+            }
+        }
+        Node::Repeat { node, min, .. } => {
+            if *min == 0 {
+                unknown()
+            } else {
+                Info {
+                    exact: None,
+                    required: complete(analyze(node)),
+                }
+            }
+        }
+    }
+}
+
+fn exactly(string: String) -> Info {
+    Info {
+        exact: Some(vec![string]),
+        required: anything(),
+    }
+}
+
+fn unknown() -> Info {
+    Info {
+        exact: None,
+        required: anything(),
+    }
+}
+
+/// Everything a node needs, its exact strings included.
+fn complete(info: Info) -> Required {
+    match info.exact {
+        Some(exact) => and(info.required, any_of(exact)),
+        None => info.required,
+    }
+}
+
+fn anything() -> Required {
+    vec![Vec::new()]
+}
+
+fn any_of(strings: Vec<String>) -> Required {
+    if strings.iter().any(String::is_empty) {
+        anything()
+    } else {
+        strings.into_iter().map(|string| vec![string]).collect()
+    }
+}
+
+fn and(a: Required, b: Required) -> Required {
+    if a.len() * b.len() > MAX_CLAUSES {
+        // Needing less than is known is never wrong.
+        return if a.len() <= b.len() { a } else { b };
+    }
+
+    a.iter()
+        .flat_map(|x| b.iter().map(move |y| [&x[..], &y[..]].concat()))
+        .collect()
+}
+
+fn or(a: Required, b: Required) -> Required {
+    if a.iter().chain(&b).any(Vec::is_empty) || a.len() + b.len() > MAX_CLAUSES {
+        return anything();
+    }
+    [a, b].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::regex::Regex;
+
+    fn required(pattern: &str) -> Vec<Vec<String>> {
+        Regex::new(pattern).unwrap().required().to_vec()
+    }
+
+    #[test]
+    fn finds_strings_every_match_contains() {
+        assert_eq!(vec![vec!["hello world"]], required("hello world"));
+        assert_eq!(vec![vec!["foo", "bar"]], required(r"foo\d+bar"));
+        assert_eq!(vec![vec!["grey"], vec!["gray"]], required("gr(e|a)y"));
+        assert_eq!(
+            vec![vec!["one", "end"], vec!["two", "end"]],
+            required(r"(one.*|two\w)end")
+        );
+        assert_eq!(vec![vec!["abc"]], required(r"\babc(d)?"));
+        assert_eq!(vec![vec!["x", "yz"]], required("x+yz"));
+
+        assert_eq!(vec![Vec::<String>::new()], required("a|b*"));
+        assert_eq!(vec![Vec::<String>::new()], required(r"\w+"));
+        // Ignoring case, `k` also matches the Kelvin sign.
+        assert_eq!(vec![vec!["eep"]], required("(?i)keep"));
+        assert_eq!(vec![vec!["déjà"]], required("déjà"));
+    }
+}
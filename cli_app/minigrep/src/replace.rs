@@ -0,0 +1,348 @@
+//! Replacing matches, for `--replace`, `--in-place` and `--dry-run`.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::mem;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str;
+
+use crate::archive::Format;
+use crate::matcher::Matcher;
+use crate::Config;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Group(usize),
+}
+
+/// Replacement text, in which `$1` or `${1}` stands for what group 1 of the
+/// pattern matched, `$0` for the whole match and `$$` for a dollar sign.
+///
+/// Groups that did not take part in the match, or that the pattern does not
+/// have, are replaced with nothing. A `$` followed by anything else is kept
+/// as it is.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Replacement {
+    parts: Vec<Part>,
+}
+
+impl Replacement {
+    pub fn new(text: &str) -> Replacement {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = text;
+
+        while let Some(at) = rest.find('$') {
+            literal.push_str(&rest[..at]);
+            rest = &rest[at + 1..];
+
+            if let Some(after) = rest.strip_prefix('$') {
+                literal.push('$');
+                rest = after;
+                continue;
+            }
+
+            let (digits, after) = match rest.strip_prefix('{') {
+                Some(braced) => braced.split_once('}').unwrap_or(("", rest)),
+                None => {
+                    let end = rest
+                        .find(|c: char| !c.is_ascii_digit())
+                        .unwrap_or(rest.len());
+                    rest.split_at(end)
+                }
+            };
+            match digits.parse() {
+                Ok(group) if digits.bytes().all(|b| b.is_ascii_digit()) => {
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Group(group));
+                    rest = after;
+                }
+                _ => literal.push('$'),
+            }
+        }
+
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Replacement { parts }
+    }
+
+    /// Replace every match in `line`, returning the new line and the byte
+    /// ranges of the replacements in it.
+    pub fn replace_all(&self, matcher: &Matcher, line: &str) -> (String, Vec<Range<usize>>) {
+        let mut replaced = String::new();
+        let mut ranges = Vec::new();
+        let mut last = 0;
+        let mut start = 0;
+
+        while start <= line.len() {
+            let groups = match matcher.captures_at(line, start) {
+                Some(groups) => groups,
+                None => break,
+            };
+            let range = groups[0].clone().unwrap();
+
+            replaced.push_str(&line[last..range.start]);
+            let begin = replaced.len();
+            for part in &self.parts {
+                match part {
+                    Part::Literal(text) => replaced.push_str(text),
+                    Part::Group(i) => {
+                        if let Some(Some(group)) = groups.get(*i) {
+                            replaced.push_str(&line[group.clone()]);
+                        }
+                    }
+                }
+            }
+            ranges.push(begin..replaced.len());
+
+            last = range.end;
+            start = if range.is_empty() {
+                range.end + line[range.end..].chars().next().map_or(1, char::len_utf8)
+            } else {
+                range.end
+            };
+        }
+
+        replaced.push_str(&line[last..]);
+        (replaced, ranges)
+    }
+}
+
+/// Apply `--replace` to every matching line of the file at `path`.
+///
+/// The new contents go to a temporary file next to the original, which is
+/// then renamed over it, so the file is never left half written. With
+/// `--dry-run` nothing is written and the changes are returned as a unified
+/// diff instead. Returns `None` when nothing was changed. Binary and
+/// compressed files, and lines that are not valid UTF-8, are left alone.
+pub fn rewrite(config: &Config, matcher: &Matcher, path: &Path) -> io::Result<Option<Vec<u8>>> {
+    let with_path = |e: io::Error| io::Error::new(e.kind(), format!("{}: {}", path.display(), e));
+    let replacement = config.replace.as_ref().expect("rewrite needs --replace");
+
+    let original = fs::read(path).map_err(with_path)?;
+    if original.contains(&0) || Format::detect(&original).is_some() {
+        return Ok(None);
+    }
+
+    let mut contents = Vec::with_capacity(original.len());
+    let mut diff = Vec::new();
+    // Lines added by replacements containing newlines, which shift the line
+    // numbers of the new file.
+    let mut added = 0;
+
+    for (index, line) in original.split_inclusive(|&b| b == b'\n').enumerate() {
+        let text = line.strip_suffix(b"\n").unwrap_or(line);
+        let text = text.strip_suffix(b"\r").unwrap_or(text);
+        let ending = &line[text.len()..];
+
+        let new = match str::from_utf8(text) {
+            Ok(text) if matcher.is_match(text) => {
+                Some(replacement.replace_all(matcher, text).0).filter(|new| new != text)
+            }
+            _ => None,
+        };
+        let Some(new) = new else {
+            contents.extend_from_slice(line);
+            continue;
+        };
+
+        contents.extend_from_slice(new.as_bytes());
+        contents.extend_from_slice(ending);
+
+        if diff.is_empty() {
+            writeln!(diff, "--- {}", path.display())?;
+            writeln!(diff, "+++ {}", path.display())?;
+        }
+        let new_lines = new.split('\n').count();
+        writeln!(
+            diff,
+            "@@ -{},1 +{},{} @@",
+            index + 1,
+            index + 1 + added,
+            new_lines
+        )?;
+        diff.push(b'-');
+        diff.extend_from_slice(text);
+        diff.push(b'\n');
+        for new_line in new.split('\n') {
+            writeln!(diff, "+{}", new_line)?;
+        }
+        added += new_lines - 1;
+    }
+
+    if diff.is_empty() {
+        Ok(None)
+    } else if config.dry_run {
+        Ok(Some(diff))
+    } else {
+        write_atomically(path, &contents).map_err(with_path)?;
+        Ok(Some(Vec::new()))
+    }
+}
+
+/// How many temporary names to try before giving up, in case earlier runs
+/// that did not finish left some behind.
+const TEMP_ATTEMPTS: usize = 100;
+
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    // Through a symlink, the file it points to is rewritten, not the link.
+    let path = fs::canonicalize(path)?;
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let (temp, mut file) = create_temp(dir, &name)?;
+
+    let result = file
+        .write_all(contents)
+        .and_then(|()| file.set_permissions(fs::metadata(&path)?.permissions()))
+        .and_then(|()| file.sync_all())
+        .and_then(|()| fs::rename(&temp, &path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Create a new file in `dir` to write the new contents of `name` to.
+fn create_temp(dir: &Path, name: &str) -> io::Result<(PathBuf, File)> {
+    let mut attempt = 0;
+    loop {
+        // Hidden, so that a search running at the same time skips it.
+        let temp = dir.join(format!(".{}.minigrep-{}-{}", name, process::id(), attempt));
+        match File::create_new(&temp) {
+            Ok(file) => return Ok((temp, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < TEMP_ATTEMPTS => {
+                attempt += 1
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn matcher(pattern: &str, regex: bool) -> Matcher {
+        Matcher::new(&Config {
+            patterns: vec![pattern.to_string()],
+            regex,
+            ..Config::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn expands_group_references() {
+        let regex = matcher(r"(\w+)@(\w+)", true);
+        let replace = |text, line| Replacement::new(text).replace_all(&regex, line);
+
+        assert_eq!(
+            (
+                "to bob at example, $ or ann at test".to_string(),
+                vec![3..17, 24..35]
+            ),
+            replace("$1 at ${2}", "to bob@example, $ or ann@test")
+        );
+        assert_eq!("$1 example", replace("$$1 $2", "bob@example").0);
+        assert_eq!("bobx", replace("${1}x", "bob@example").0);
+        assert_eq!("$x ${1 $", replace("$x ${1 $", "bob@example").0);
+        assert_eq!("[] [bob@example]", replace("[$9] [$0]", "bob@example").0);
+
+        let literal = matcher("rust", false);
+        assert_eq!(
+            ("Tcrab crab".to_string(), vec![1..5, 6..10]),
+            Replacement::new("crab").replace_all(&literal, "Trust rust")
+        );
+    }
+
+    #[test]
+    fn rewrites_files_in_place() {
+        let dir = env::temp_dir().join(format!("minigrep-replace-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notes.txt");
+        fs::write(&path, b"let x = 1;\r\nlet y = x;\n\xff x\nx").unwrap();
+
+        let mut config = Config {
+            patterns: vec![r"\bx\b".to_string()],
+            regex: true,
+            replace: Some(Replacement::new("count\ntotal")),
+            dry_run: true,
+            ..Config::default()
+        };
+        let matcher = Matcher::new(&config).unwrap();
+
+        let diff = rewrite(&config, &matcher, &path).unwrap().unwrap();
+        let name = path.display();
+        assert_eq!(
+            format!(
+                "--- {name}\n+++ {name}\n\
+                 @@ -1,1 +1,2 @@\n-let x = 1;\n+let count\n+total = 1;\n\
+                 @@ -2,1 +3,2 @@\n-let y = x;\n+let y = count\n+total;\n\
+                 @@ -4,1 +6,2 @@\n-x\n+count\n+total\n"
+            ),
+            String::from_utf8(diff).unwrap()
+        );
+        assert_eq!(
+            b"let x = 1;\r\nlet y = x;\n\xff x\nx",
+            &fs::read(&path).unwrap()[..]
+        );
+
+        config.dry_run = false;
+        config.replace = Some(Replacement::new("n"));
+        assert_eq!(Some(Vec::new()), rewrite(&config, &matcher, &path).unwrap());
+        assert_eq!(None, rewrite(&config, &matcher, &path).unwrap());
+        assert_eq!(
+            b"let n = 1;\r\nlet y = n;\n\xff x\nn",
+            &fs::read(&path).unwrap()[..]
+        );
+        assert_eq!(1, fs::read_dir(&dir).unwrap().count());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_through_symlinks_and_past_stale_temp_files() {
+        let dir = env::temp_dir().join(format!("minigrep-replace-link-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("target.txt");
+        fs::write(&path, "old\n").unwrap();
+        let stale = dir.join(format!(".target.txt.minigrep-{}-0", process::id()));
+        fs::write(&stale, "left behind").unwrap();
+
+        let link = dir.join("link.txt");
+        std::os::unix::fs::symlink("target.txt", &link).unwrap();
+
+        let config = Config {
+            patterns: vec!["old".to_string()],
+            replace: Some(Replacement::new("new")),
+            ..Config::default()
+        };
+        let matcher = Matcher::new(&config).unwrap();
+        assert_eq!(Some(Vec::new()), rewrite(&config, &matcher, &link).unwrap());
+
+        assert_eq!("new\n", fs::read_to_string(&path).unwrap());
+        assert!(
+            fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+                || !cfg!(unix)
+        );
+        assert_eq!("left behind", fs::read_to_string(&stale).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
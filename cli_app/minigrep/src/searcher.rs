@@ -0,0 +1,387 @@
+//! The library interface: build a `Searcher` with `SearcherBuilder`, then
+//! search text, readers or files with it, handing each selected line to a
+//! `Sink` as a `Match`.
+//!
+//! ```
+//! use minigrep::searcher::{Case, SearcherBuilder};
+//!
+//! let searcher = SearcherBuilder::new()
+//!     .case(Case::Insensitive)
+//!     .build(&["rust"])
+//!     .unwrap();
+//! let matches = searcher.matches("Rust:\nsafe, fast, productive.\nTrust me.");
+//! assert_eq!(vec![1, 3], matches.iter().map(|m| m.number).collect::<Vec<_>>());
+//! assert_eq!(vec![1..5], matches[1].submatches);
+//! ```
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::matcher::Matcher;
+use crate::printer::Printer;
+use crate::regex;
+use crate::{search_lines, Config};
+
+/// How the patterns treat case.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Case {
+    Sensitive,
+    Insensitive,
+    /// Insensitive unless a pattern has an uppercase letter.
+    Smart,
+}
+
+/// A selected line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Match<'a> {
+    /// Counting from 1.
+    pub number: usize,
+    /// Byte range of the line in the input, without its line terminator.
+    pub range: Range<usize>,
+    pub line: &'a str,
+    /// Byte ranges of the matches within `line`, which are none when the
+    /// line was selected for not matching.
+    pub submatches: Vec<Range<usize>>,
+}
+
+/// Receives the selected lines of a search.
+pub trait Sink {
+    /// Take a selected line, returning whether to go on searching.
+    fn matched(&mut self, found: &Match) -> io::Result<bool>;
+}
+
+impl<F: FnMut(&Match) -> io::Result<bool>> Sink for F {
+    fn matched(&mut self, found: &Match) -> io::Result<bool> {
+        self(found)
+    }
+}
+
+/// Prints the lines as the command line does.
+impl<W: Write> Sink for Printer<'_, W> {
+    fn matched(&mut self, found: &Match) -> io::Result<bool> {
+        let submatches = if self.wants_matches() {
+            &found.submatches[..]
+        } else {
+            &[]
+        };
+        self.selected(
+            found.number,
+            found.range.start,
+            found.line,
+            submatches,
+            None,
+        )?;
+        Ok(true)
+    }
+}
+
+pub struct SearcherBuilder {
+    case: Case,
+    regex: bool,
+    invert: bool,
+    max_count: Option<usize>,
+}
+
+impl SearcherBuilder {
+    /// A builder for searching case-sensitively for literal text.
+    pub fn new() -> SearcherBuilder {
+        SearcherBuilder {
+            case: Case::Sensitive,
+            regex: false,
+            invert: false,
+            max_count: None,
+        }
+    }
+
+    pub fn case(mut self, case: Case) -> SearcherBuilder {
+        self.case = case;
+        self
+    }
+
+    /// Treat the patterns as regular expressions rather than literal text.
+    pub fn regex(mut self, yes: bool) -> SearcherBuilder {
+        self.regex = yes;
+        self
+    }
+
+    /// Select the lines that do not match.
+    pub fn invert(mut self, yes: bool) -> SearcherBuilder {
+        self.invert = yes;
+        self
+    }
+
+    /// Stop after this many selected lines.
+    pub fn max_count(mut self, max: Option<usize>) -> SearcherBuilder {
+        self.max_count = max;
+        self
+    }
+
+    /// Build a searcher selecting the lines that match any of `patterns`.
+    pub fn build<S: AsRef<str>>(&self, patterns: &[S]) -> Result<Searcher, regex::Error> {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.as_ref().to_string()).collect();
+        let case_sensitive = match self.case {
+            Case::Sensitive => true,
+            Case::Insensitive => false,
+            Case::Smart => patterns.iter().any(|p| p.chars().any(char::is_uppercase)),
+        };
+        let config = Config {
+            patterns,
+            case_sensitive,
+            regex: self.regex,
+            ..Config::default()
+        };
+        Ok(Searcher {
+            matcher: Matcher::new(&config)?,
+            invert: self.invert,
+            max_count: self.max_count,
+        })
+    }
+}
+
+impl Default for SearcherBuilder {
+    fn default() -> SearcherBuilder {
+        SearcherBuilder::new()
+    }
+}
+
+pub struct Searcher {
+    matcher: Matcher,
+    invert: bool,
+    max_count: Option<usize>,
+}
+
+impl Searcher {
+    /// The selected lines of `contents`.
+    pub fn matches<'a>(&self, contents: &'a str) -> Vec<Match<'a>> {
+        let mut matches = Vec::new();
+        self.each_match(contents, |found| {
+            matches.push(found);
+            true
+        });
+        matches
+    }
+
+    /// Search `contents`, returning the number of selected lines.
+    pub fn search_str<S: Sink>(&self, contents: &str, sink: &mut S) -> io::Result<usize> {
+        let mut result = Ok(());
+        let selected = self.each_match(contents, |found| match sink.matched(&found) {
+            Ok(more) => more,
+            Err(e) => {
+                result = Err(e);
+                false
+            }
+        });
+        result.map(|()| selected)
+    }
+
+    /// Search `reader` a line at a time, returning the number of selected
+    /// lines. Text that is not UTF-8 is searched with replacement
+    /// characters in its place.
+    pub fn search_reader<R: BufRead, S: Sink>(
+        &self,
+        mut reader: R,
+        sink: &mut S,
+    ) -> io::Result<usize> {
+        let max = self.max_count.unwrap_or(usize::MAX);
+        let mut selected = 0;
+        let mut buffer = Vec::new();
+        let mut number = 0;
+        let mut offset = 0;
+
+        while selected < max {
+            buffer.clear();
+            let len = reader.read_until(b'\n', &mut buffer)?;
+            if len == 0 {
+                break;
+            }
+            number += 1;
+            let start = offset;
+            offset += len;
+
+            if buffer.ends_with(b"\n") {
+                buffer.pop();
+                if buffer.ends_with(b"\r") {
+                    buffer.pop();
+                }
+            }
+            let line = String::from_utf8_lossy(&buffer);
+            let submatches = self.matcher.find_iter(&line);
+            if submatches.is_empty() != self.invert {
+                continue;
+            }
+
+            selected += 1;
+            let found = Match {
+                number,
+                range: start..start + buffer.len(),
+                line: &line,
+                submatches,
+            };
+            if !sink.matched(&found)? {
+                break;
+            }
+        }
+
+        Ok(selected)
+    }
+
+    /// Search the file at `path`, returning the number of selected lines.
+    pub fn search_path<P: AsRef<Path>, S: Sink>(&self, path: P, sink: &mut S) -> io::Result<usize> {
+        self.search_reader(BufReader::new(File::open(path)?), sink)
+    }
+
+    /// Call `f` with each selected line of `contents` until it returns
+    /// false, returning the number of lines it was called with.
+    fn each_match<'a>(&self, contents: &'a str, mut f: impl FnMut(Match<'a>) -> bool) -> usize {
+        let max = self.max_count.unwrap_or(usize::MAX);
+        let mut selected = 0;
+
+        if !self.invert {
+            for found in search_lines(&self.matcher, contents) {
+                if selected == max {
+                    break;
+                }
+                selected += 1;
+                let found = Match {
+                    number: found.number,
+                    range: found.offset..found.offset + found.line.len(),
+                    line: found.line,
+                    submatches: found.ranges,
+                };
+                if !f(found) {
+                    break;
+                }
+            }
+            return selected;
+        }
+
+        let mut offset = 0;
+        for (index, line) in contents.split_inclusive('\n').enumerate() {
+            if selected == max {
+                break;
+            }
+            let start = offset;
+            offset += line.len();
+            let line = line.strip_suffix('\n').unwrap_or(line);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if self.matcher.is_match(line) {
+                continue;
+            }
+
+            selected += 1;
+            let found = Match {
+                number: index + 1,
+                range: start..start + line.len(),
+                line,
+                submatches: Vec::new(),
+            };
+            if !f(found) {
+                break;
+            }
+        }
+        selected
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+
+    fn numbers(matches: &[Match]) -> Vec<usize> {
+        matches.iter().map(|found| found.number).collect()
+    }
+
+    #[test]
+    fn searches_text_and_readers_alike() {
+        let searcher = SearcherBuilder::new()
+            .case(Case::Smart)
+            .regex(true)
+            .build(&["r(u)st"])
+            .unwrap();
+        let matches = searcher.matches(TEXT);
+        assert_eq!(
+            vec![
+                Match {
+                    number: 1,
+                    range: 0..5,
+                    line: "Rust:",
+                    submatches: vec![0..4],
+                },
+                Match {
+                    number: 4,
+                    range: 42..51,
+                    line: "Trust me.",
+                    submatches: vec![1..5],
+                },
+            ],
+            matches
+        );
+
+        let mut read = Vec::new();
+        let selected = searcher
+            .search_reader(TEXT.as_bytes(), &mut |found: &Match| {
+                read.push((found.number, found.range.clone(), found.submatches.clone()));
+                Ok(true)
+            })
+            .unwrap();
+        assert_eq!(2, selected);
+        let expected: Vec<_> = matches
+            .iter()
+            .map(|found| (found.number, found.range.clone(), found.submatches.clone()))
+            .collect();
+        assert_eq!(expected, read);
+
+        let smart = SearcherBuilder::new().case(Case::Smart);
+        assert_eq!(
+            vec![1],
+            numbers(&smart.build(&["Rust"]).unwrap().matches(TEXT))
+        );
+    }
+
+    #[test]
+    fn inverts_and_stops_at_the_max_count() {
+        let searcher = SearcherBuilder::new()
+            .invert(true)
+            .max_count(Some(1))
+            .build(&["st"])
+            .unwrap();
+        assert_eq!(vec![3], numbers(&searcher.matches(TEXT)));
+        assert_eq!(
+            1,
+            searcher
+                .search_str(TEXT, &mut |_: &Match| Ok(true))
+                .unwrap()
+        );
+
+        let searcher = SearcherBuilder::new()
+            .max_count(Some(2))
+            .build(&["t"])
+            .unwrap();
+        let mut lines = Vec::new();
+        searcher
+            .search_reader(TEXT.as_bytes(), &mut |found: &Match| {
+                lines.push(found.line.to_string());
+                Ok(true)
+            })
+            .unwrap();
+        assert_eq!(vec!["Rust:", "safe, fast, productive."], lines);
+
+        let mut stopped = 0;
+        let selected = searcher
+            .search_str(TEXT, &mut |_: &Match| {
+                stopped += 1;
+                Ok(false)
+            })
+            .unwrap();
+        assert_eq!((1, 1), (stopped, selected));
+    }
+}
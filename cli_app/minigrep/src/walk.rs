@@ -0,0 +1,192 @@
+mod ignore;
+
+pub use ignore::Ignore;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::vec;
+
+struct Dir {
+    entries: vec::IntoIter<(PathBuf, bool)>,
+    /// The ignore files that apply to the entries, with the directory each
+    /// was found in, from the outermost directory inwards.
+    ignores: Vec<Arc<(PathBuf, Ignore)>>,
+}
+
+/// Iterates over the files below a list of paths.
+///
+/// Files named directly, and `-` for standard input, are always yielded.
+/// Directories are walked
+/// recursively in name order, skipping hidden entries, symbolic links and
+/// anything matched by a `.gitignore` or `.ignore` file on the way down.
+pub struct Walk {
+    roots: vec::IntoIter<PathBuf>,
+    stack: Vec<Dir>,
+}
+
+impl Walk {
+    pub fn new<P: AsRef<Path>>(paths: &[P]) -> Walk {
+        Walk {
+            roots: paths
+                .iter()
+                .map(|path| path.as_ref().to_path_buf())
+                .collect::<Vec<_>>()
+                .into_iter(),
+            stack: Vec::new(),
+        }
+    }
+
+    fn open(&mut self, dir: PathBuf, mut ignores: Vec<Arc<(PathBuf, Ignore)>>) -> io::Result<()> {
+        let ignore = Ignore::from_dir(&dir);
+        if !ignore.is_empty() {
+            ignores.push(Arc::new((dir.clone(), ignore)));
+        }
+
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&dir).map_err(|e| with_path(&dir, e))? {
+            let entry = entry.map_err(|e| with_path(&dir, e))?;
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            let file_type = entry.file_type().map_err(|e| with_path(&entry.path(), e))?;
+            if file_type.is_dir() || file_type.is_file() {
+                entries.push((entry.path(), file_type.is_dir()));
+            }
+        }
+        entries.sort();
+
+        self.stack.push(Dir {
+            entries: entries.into_iter(),
+            ignores,
+        });
+        Ok(())
+    }
+}
+
+impl Iterator for Walk {
+    type Item = io::Result<PathBuf>;
+
+    fn next(&mut self) -> Option<io::Result<PathBuf>> {
+        loop {
+            if let Some(dir) = self.stack.last_mut() {
+                let (path, is_dir) = match dir.entries.next() {
+                    Some(entry) => entry,
+                    None => {
+                        self.stack.pop();
+                        continue;
+                    }
+                };
+
+                if is_ignored(&dir.ignores, &path, is_dir) {
+                    continue;
+                }
+                if !is_dir {
+                    return Some(Ok(path));
+                }
+
+                let ignores = dir.ignores.clone();
+                if let Err(e) = self.open(path, ignores) {
+                    return Some(Err(e));
+                }
+                continue;
+            }
+
+            let root = self.roots.next()?;
+            if root == Path::new("-") {
+                return Some(Ok(root));
+            }
+            match fs::metadata(&root) {
+                Ok(metadata) if metadata.is_dir() => {
+                    if let Err(e) = self.open(root, Vec::new()) {
+                        return Some(Err(e));
+                    }
+                }
+                Ok(_) => return Some(Ok(root)),
+                Err(e) => return Some(Err(with_path(&root, e))),
+            }
+        }
+    }
+}
+
+/// Check the ignore files from the innermost directory outwards; the first
+/// one with an opinion on `path` decides.
+fn is_ignored(ignores: &[Arc<(PathBuf, Ignore)>], path: &Path, is_dir: bool) -> bool {
+    ignores
+        .iter()
+        .rev()
+        .find_map(|ignore| {
+            let (base, ignore) = &**ignore;
+            let relative = path.strip_prefix(base).ok()?;
+            let relative: Vec<_> = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect();
+            ignore.matched(&relative.join("/"), is_dir)
+        })
+        .unwrap_or(false)
+}
+
+fn with_path(path: &Path, error: io::Error) -> io::Error {
+    io::Error::new(error.kind(), format!("{}: {}", path.display(), error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn walks_tree_honouring_ignore_files() {
+        let root = env::temp_dir().join(format!("minigrep-walk-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["src/nested", "target/debug", ".git", "logs"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "README.md",
+            "src/lib.rs",
+            "src/nested/mod.rs",
+            "src/nested/generated.rs",
+            "target/debug/out.txt",
+            ".git/config",
+            ".hidden",
+            "logs/app.log",
+            "logs/keep.log",
+        ] {
+            fs::write(root.join(file), "text").unwrap();
+        }
+        fs::write(root.join(".gitignore"), "target/\n*.log\n").unwrap();
+        fs::write(root.join("logs/.ignore"), "!keep.log\n").unwrap();
+        fs::write(root.join("src/.ignore"), "nested/generated.rs\n").unwrap();
+
+        let files: Vec<_> = Walk::new(&[&root])
+            .map(|path| {
+                let path = path.unwrap();
+                let relative = path.strip_prefix(&root).unwrap();
+                relative.to_string_lossy().replace('\\', "/")
+            })
+            .collect();
+
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            vec![
+                "README.md",
+                "logs/keep.log",
+                "src/lib.rs",
+                "src/nested/mod.rs"
+            ],
+            files
+        );
+    }
+
+    #[test]
+    fn reports_missing_paths() {
+        let error = Walk::new(&["does/not/exist"]).next().unwrap().unwrap_err();
+        assert!(error.to_string().starts_with("does/not/exist: "));
+    }
+}
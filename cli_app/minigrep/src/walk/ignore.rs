@@ -0,0 +1,196 @@
+use std::fs;
+use std::path::Path;
+
+use crate::regex::Regex;
+
+struct Rule {
+    regex: Regex,
+    negated: bool,
+    dir_only: bool,
+    /// Patterns without a slash match the file name at any depth; the others
+    /// match the whole path relative to the ignore file.
+    basename: bool,
+}
+
+/// The patterns of the `.gitignore` and `.ignore` files of one directory.
+#[derive(Default)]
+pub struct Ignore {
+    rules: Vec<Rule>,
+}
+
+impl Ignore {
+    /// Read the ignore files in `dir`. Rules from `.ignore` come after those
+    /// from `.gitignore`, so they win when both match.
+    pub fn from_dir(dir: &Path) -> Ignore {
+        let mut ignore = Ignore::default();
+        for name in [".gitignore", ".ignore"] {
+            if let Ok(text) = fs::read_to_string(dir.join(name)) {
+                ignore.add(&text);
+            }
+        }
+        ignore
+    }
+
+    pub fn parse(text: &str) -> Ignore {
+        let mut ignore = Ignore::default();
+        ignore.add(text);
+        ignore
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    fn add(&mut self, text: &str) {
+        for line in text.lines() {
+            let mut line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let negated = line.starts_with('!');
+            if negated {
+                line = &line[1..];
+            }
+
+            let dir_only = line.ends_with('/');
+            let line = line.trim_end_matches('/');
+            let basename = !line.contains('/');
+            let line = line.trim_start_matches('/');
+            if line.is_empty() {
+                continue;
+            }
+
+            // Patterns that do not compile are skipped, as git does.
+            if let Ok(regex) = Regex::new(&glob_to_regex(line)) {
+                self.rules.push(Rule {
+                    regex,
+                    negated,
+                    dir_only,
+                    basename,
+                });
+            }
+        }
+    }
+
+    /// Whether `relative`, a path below the directory of the ignore files
+    /// using `/` as separator, is ignored (`Some(true)`), explicitly
+    /// whitelisted with `!` (`Some(false)`) or not mentioned at all.
+    ///
+    /// As in git, the last matching pattern decides.
+    pub fn matched(&self, relative: &str, is_dir: bool) -> Option<bool> {
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+
+        self.rules
+            .iter()
+            .rev()
+            .filter(|rule| is_dir || !rule.dir_only)
+            .find(|rule| {
+                rule.regex
+                    .is_match(if rule.basename { name } else { relative })
+            })
+            .map(|rule| !rule.negated)
+    }
+}
+
+/// Translate a gitignore glob into an anchored regular expression.
+fn glob_to_regex(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut out = String::from("^");
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let at_start = i == 0 || chars[i - 1] == '/';
+                if at_start && chars.get(i + 2) == Some(&'/') {
+                    // `**/` matches zero or more directories.
+                    out.push_str("(?:.*/)?");
+                    i += 3;
+                } else {
+                    out.push_str(".*");
+                    i += 2;
+                }
+                continue;
+            }
+            '*' => out.push_str("[^/]*"),
+            '?' => out.push_str("[^/]"),
+            '[' => match chars[i + 1..].iter().skip(1).position(|&c| c == ']') {
+                Some(offset) => {
+                    let end = i + 2 + offset;
+                    out.push('[');
+                    let mut class = &chars[i + 1..end];
+                    if let Some(('!', rest)) = class.split_first() {
+                        out.push('^');
+                        class = rest;
+                    }
+                    for &c in class {
+                        if c == '\\' || c == '[' {
+                            out.push('\\');
+                        }
+                        out.push(c);
+                    }
+                    out.push(']');
+                    i = end;
+                }
+                None => out.push_str("\\["),
+            },
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                push_literal(&mut out, chars[i]);
+            }
+            c => push_literal(&mut out, c),
+        }
+        i += 1;
+    }
+
+    out.push('$');
+    out
+}
+
+fn push_literal(out: &mut String, c: char) {
+    if "\\.+*?()|[]{}^$".contains(c) {
+        out.push('\\');
+    }
+    out.push(c);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_gitignore_patterns() {
+        let ignore = Ignore::parse(
+            "\
+# build output
+target/
+*.log
+!keep.log
+/root.txt
+docs/**/*.md
+file[0-9].txt
+",
+        );
+
+        assert_eq!(Some(true), ignore.matched("target", true));
+        assert_eq!(None, ignore.matched("target", false));
+        assert_eq!(Some(true), ignore.matched("src/debug.log", false));
+        assert_eq!(Some(false), ignore.matched("keep.log", false));
+        assert_eq!(Some(true), ignore.matched("root.txt", false));
+        assert_eq!(None, ignore.matched("src/root.txt", false));
+        assert_eq!(Some(true), ignore.matched("docs/a/b/c.md", false));
+        assert_eq!(Some(true), ignore.matched("docs/c.md", false));
+        assert_eq!(Some(true), ignore.matched("file7.txt", false));
+        assert_eq!(None, ignore.matched("fileX.txt", false));
+        assert_eq!(None, ignore.matched("src/main.rs", false));
+    }
+
+    #[test]
+    fn translates_globs() {
+        assert_eq!("^[^/]*\\.rs$", glob_to_regex("*.rs"));
+        assert_eq!("^(?:.*/)?foo$", glob_to_regex("**/foo"));
+        assert_eq!("^a/.*$", glob_to_regex("a/**"));
+        assert_eq!("^[^ab]$", glob_to_regex("[!ab]"));
+    }
+}
@@ -0,0 +1,321 @@
+//! `--watch`: keep searching files as they change, like `tail -f | grep`
+//! across a whole tree.
+//!
+//! Every file is searched once, and after that only the lines added to it
+//! since. A file that shrank or was replaced is searched again from the
+//! start. Only complete lines are searched; a line still being written is
+//! left until its newline arrives.
+//!
+//! On Linux inotify says when something may have changed; elsewhere, or if
+//! inotify is unavailable, the files are checked every second. Either way
+//! the paths are walked again to find what changed, so new files and
+//! directories are picked up too.
+
+#[cfg(target_os = "linux")]
+mod inotify;
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::archive::Format;
+use crate::matcher::Matcher;
+use crate::printer::Printer;
+use crate::walk::Walk;
+use crate::{print_match, Config};
+
+/// How often to look for changes without inotify, and with it, in case a
+/// change was missed, such as one in a directory created and written to
+/// before it could be watched.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const SAFETY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long to wait after a change for more, so that a burst of writes is
+/// searched together.
+const SETTLE: Duration = Duration::from_millis(20);
+
+pub fn watch(config: &Config, matcher: &Matcher, color: bool) -> Result<(), Box<dyn Error>> {
+    let with_filename =
+        config.paths.len() > 1 || config.paths.iter().any(|path| Path::new(path).is_dir());
+    let mut follower = Follower::new(config, matcher, color, with_filename);
+    let mut watcher = Watcher::new();
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut first = true;
+
+    loop {
+        let mut seen = HashSet::new();
+        for path in Walk::new(&config.paths) {
+            let path = match path {
+                Ok(path) => path,
+                Err(e) => {
+                    if !config.no_messages {
+                        eprintln!("minigrep: {}", e);
+                    }
+                    continue;
+                }
+            };
+            if let Some(dir) = path.parent() {
+                watcher.watch(dir);
+            }
+            if let Err(e) = follower.follow(&path, first, &mut out) {
+                if !config.no_messages {
+                    eprintln!("minigrep: {}: {}", path.display(), e);
+                }
+            }
+            seen.insert(path);
+        }
+        for path in &config.paths {
+            watcher.watch(Path::new(path));
+        }
+        follower.files.retain(|path, _| seen.contains(path));
+
+        out.flush()?;
+        first = false;
+        watcher.wait();
+    }
+}
+
+/// Where searching a file got to.
+struct Followed {
+    len: u64,
+    modified: Option<SystemTime>,
+    id: u64,
+    /// The end of the last complete line searched, and how many lines that
+    /// was.
+    offset: u64,
+    lines: usize,
+    /// Binary and compressed files are not followed.
+    skip: bool,
+}
+
+struct Follower<'a> {
+    config: &'a Config,
+    matcher: &'a Matcher,
+    color: bool,
+    with_filename: bool,
+    files: HashMap<PathBuf, Followed>,
+}
+
+impl<'a> Follower<'a> {
+    fn new(config: &'a Config, matcher: &'a Matcher, color: bool, with_filename: bool) -> Self {
+        Follower {
+            config,
+            matcher,
+            color,
+            with_filename,
+            files: HashMap::new(),
+        }
+    }
+
+    /// Search what was added to the file at `path` since it was last
+    /// searched. With `partial`, a last line without a newline is searched
+    /// too, though it will be again once it is complete.
+    fn follow<W: Write>(&mut self, path: &Path, partial: bool, out: &mut W) -> io::Result<()> {
+        let metadata = fs::metadata(path)?;
+        let followed = self.files.entry(path.to_path_buf()).or_insert(Followed {
+            len: 0,
+            modified: None,
+            id: file_id(&metadata),
+            offset: 0,
+            lines: 0,
+            skip: false,
+        });
+
+        let modified = metadata.modified().ok();
+        let rewritten = followed.id != file_id(&metadata)
+            || metadata.len() < followed.offset
+            || (metadata.len() == followed.len && modified != followed.modified);
+        if rewritten {
+            followed.offset = 0;
+            followed.lines = 0;
+            followed.skip = false;
+        } else if metadata.len() == followed.len && followed.modified.is_some() {
+            return Ok(());
+        }
+        followed.len = metadata.len();
+        followed.modified = modified;
+        followed.id = file_id(&metadata);
+        if followed.skip {
+            return Ok(());
+        }
+
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(followed.offset))?;
+        let mut reader = BufReader::new(file);
+        if followed.offset == 0 {
+            let head = reader.fill_buf()?;
+            if head.contains(&0) || Format::detect(head).is_some() {
+                followed.skip = true;
+                return Ok(());
+            }
+        }
+
+        let label = (self.with_filename || self.config.json).then(|| path.display().to_string());
+        let mut printer = Printer::new(self.config, label, self.color, &mut *out);
+        let mut buffer = Vec::new();
+
+        loop {
+            buffer.clear();
+            let len = reader.read_until(b'\n', &mut buffer)?;
+            let complete = buffer.ends_with(b"\n");
+            if len == 0 || (!complete && !partial) {
+                break;
+            }
+
+            let number = followed.lines + 1;
+            let offset = followed.offset as usize;
+            if complete {
+                followed.lines += 1;
+                followed.offset += len as u64;
+                buffer.pop();
+                if buffer.ends_with(b"\r") {
+                    buffer.pop();
+                }
+            }
+            let line = String::from_utf8_lossy(&buffer);
+
+            let config = self.config;
+            if self.matcher.is_match(&line) == config.invert_match {
+                printer.unselected(number, offset, &line)?;
+            } else if config.invert_match {
+                printer.selected(number, offset, &line, &[], None)?;
+            } else {
+                print_match(
+                    &mut printer,
+                    config,
+                    self.matcher,
+                    number,
+                    offset,
+                    &line,
+                    None,
+                )?;
+            }
+        }
+
+        printer.finish()?;
+        Ok(())
+    }
+}
+
+/// Which file a path is, to tell when it was replaced by another.
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.ino()
+}
+
+#[cfg(not(unix))]
+fn file_id(_: &Metadata) -> u64 {
+    0
+}
+
+/// Waits for something to change in the directories it was told of.
+struct Watcher {
+    #[cfg(target_os = "linux")]
+    inotify: Option<inotify::Inotify>,
+    watched: HashSet<PathBuf>,
+}
+
+impl Watcher {
+    fn new() -> Watcher {
+        Watcher {
+            #[cfg(target_os = "linux")]
+            inotify: inotify::Inotify::new().ok(),
+            watched: HashSet::new(),
+        }
+    }
+
+    /// Wake up for changes in `dir`, or to `dir` itself if it is a file.
+    fn watch(&mut self, dir: &Path) {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        if self.watched.contains(dir) {
+            return;
+        }
+        self.watched.insert(dir.to_path_buf());
+
+        #[cfg(target_os = "linux")]
+        if let Some(inotify) = &self.inotify {
+            // Past the limit on watches, fall back to polling.
+            if inotify.add(dir).is_err() {
+                self.inotify = None;
+            }
+        }
+    }
+
+    fn wait(&mut self) {
+        #[cfg(target_os = "linux")]
+        if let Some(inotify) = &self.inotify {
+            match inotify.wait(SAFETY_INTERVAL) {
+                Ok(true) => thread::sleep(SETTLE),
+                Ok(false) => {}
+                Err(_) => self.inotify = None,
+            }
+            if let Some(inotify) = &self.inotify {
+                // Whatever happened while settling is about to be seen.
+                let _ = inotify.drain();
+            }
+            return;
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    #[test]
+    fn follows_lines_as_they_are_added() {
+        let dir = env::temp_dir().join(format!("minigrep-watch-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("log.txt");
+
+        let config = Config {
+            patterns: vec!["error".to_string()],
+            line_number: true,
+            ..Config::default()
+        };
+        let matcher = Matcher::new(&config).unwrap();
+        let mut follower = Follower::new(&config, &matcher, false, false);
+
+        let binary = dir.join("data.bin");
+        fs::write(&binary, b"error\0binary\n").unwrap();
+        let mut out = Vec::new();
+        follower.follow(&binary, true, &mut out).unwrap();
+        assert!(out.is_empty());
+
+        let mut follow = |partial| {
+            let mut out = Vec::new();
+            follower.follow(&path, partial, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        fs::write(&path, "error one\nfine\nerror, unfinished").unwrap();
+        assert_eq!("1:error one\n3:error, unfinished\n", follow(true));
+        assert_eq!("", follow(false));
+
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b" now\nerror four\nerror fi").unwrap();
+        assert_eq!("3:error, unfinished now\n4:error four\n", follow(false));
+        file.write_all(b"ve\n").unwrap();
+        assert_eq!("5:error five\n", follow(false));
+
+        fs::write(&path, "error again\n").unwrap();
+        assert_eq!("1:error again\n", follow(false));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+//! Just enough of Linux's inotify to be woken up when something in a set of
+//! directories changes. Which change it was does not matter, since the
+//! files are checked again either way.
+
+use std::ffi::{c_char, c_int, c_short, c_ulong, CString};
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+
+const IN_NONBLOCK: c_int = 0o4000;
+const IN_CLOEXEC: c_int = 0o2000000;
+
+const IN_MODIFY: u32 = 0x2;
+const IN_ATTRIB: u32 = 0x4;
+const IN_CLOSE_WRITE: u32 = 0x8;
+const IN_MOVED_FROM: u32 = 0x40;
+const IN_MOVED_TO: u32 = 0x80;
+const IN_CREATE: u32 = 0x100;
+const IN_DELETE: u32 = 0x200;
+const CHANGES: u32 =
+    IN_MODIFY | IN_ATTRIB | IN_CLOSE_WRITE | IN_MOVED_FROM | IN_MOVED_TO | IN_CREATE | IN_DELETE;
+
+const POLLIN: c_short = 1;
+
+#[repr(C)]
+struct PollFd {
+    fd: c_int,
+    events: c_short,
+    revents: c_short,
+}
+
+extern "C" {
+    fn inotify_init1(flags: c_int) -> c_int;
+    fn inotify_add_watch(fd: c_int, pathname: *const c_char, mask: u32) -> c_int;
+    fn poll(fds: *mut PollFd, nfds: c_ulong, timeout: c_int) -> c_int;
+}
+
+pub struct Inotify {
+    file: File,
+}
+
+impl Inotify {
+    pub fn new() -> io::Result<Inotify> {
+        // SAFETY: no pointers are passed, and a new descriptor is owned by
+        // nothing else.
+        let fd = unsafe { inotify_init1(IN_NONBLOCK | IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` was just opened and is closed only by the `File`.
+        let file = unsafe { File::from_raw_fd(fd) };
+        Ok(Inotify { file })
+    }
+
+    /// Watch `path` for changes, to the files in it if it is a directory.
+    pub fn add(&self, path: &Path) -> io::Result<()> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        // SAFETY: `path` is a NUL-terminated string that outlives the call.
+        let wd = unsafe { inotify_add_watch(self.file.as_raw_fd(), path.as_ptr(), CHANGES) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Wait up to `timeout` for a change, returning whether there was one.
+    pub fn wait(&self, timeout: Duration) -> io::Result<bool> {
+        let mut fds = PollFd {
+            fd: self.file.as_raw_fd(),
+            events: POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.as_millis().min(c_int::MAX as u128) as c_int;
+        // SAFETY: `fds` is a single valid `pollfd` for the call.
+        let ready = unsafe { poll(&mut fds, 1, timeout) };
+        match ready {
+            n if n < 0 => {
+                let e = io::Error::last_os_error();
+                match e.kind() {
+                    io::ErrorKind::Interrupted => Ok(true),
+                    _ => Err(e),
+                }
+            }
+            0 => Ok(false),
+            _ => Ok(true),
+        }
+    }
+
+    /// Throw away the events so far.
+    pub fn drain(&self) -> io::Result<()> {
+        let mut buffer = [0; 4096];
+        loop {
+            match (&self.file).read(&mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
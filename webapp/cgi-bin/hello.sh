@@ -0,0 +1,13 @@
+#!/bin/sh
+printf 'Content-Type: text/plain\r\n\r\n'
+printf 'Hello from %s %s%s\n' "$REQUEST_METHOD" "$SCRIPT_NAME" "$PATH_INFO"
+
+if [ -n "$QUERY_STRING" ]; then
+    printf 'Query: %s\n' "$QUERY_STRING"
+fi
+
+if [ -n "$CONTENT_LENGTH" ]; then
+    printf 'Body: '
+    cat
+    printf '\n'
+fi
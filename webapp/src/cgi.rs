@@ -0,0 +1,551 @@
+use std::{
+    io::{self, prelude::*},
+    path::PathBuf,
+    process::{Child, ChildStdout, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::http::{Handler, Request, Response};
+use crate::router::split_prefix;
+
+/// How long to wait for the rest of a script's output once it has exited
+/// and its process group has been killed.
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+/// The default limit on a script's output, headers included.
+pub const MAX_OUTPUT: u64 = 50 * 1024 * 1024;
+
+/// Runs an external program for every request, following CGI/1.1
+/// (RFC 3875).
+///
+/// The request is described to the program through environment variables
+/// and its body is written to the program's stdin. The program answers on
+/// stdout with a block of CGI headers, a blank line and the response body.
+/// Programs that run longer than the timeout are killed together with any
+/// children they spawned, and the client gets a `504 Gateway Timeout`.
+/// Children left running in the background are killed when the program
+/// exits, so they cannot keep its output open. Programs that write more
+/// than the output limit are killed as well, and the client gets a
+/// `502 Bad Gateway`.
+pub struct CgiHandler {
+    script_name: String,
+    program: PathBuf,
+    args: Vec<String>,
+    current_dir: Option<PathBuf>,
+    timeout: Duration,
+    max_output: u64,
+}
+
+impl CgiHandler {
+    /// Create a handler that runs `program` for requests below `script_name`.
+    ///
+    /// `script_name` should be the path the handler is mounted at; anything
+    /// after it in the request path is passed on as `PATH_INFO`.
+    pub fn new(script_name: &str, program: impl Into<PathBuf>) -> CgiHandler {
+        CgiHandler {
+            script_name: script_name.trim_end_matches('/').to_string(),
+            program: program.into(),
+            args: Vec::new(),
+            current_dir: None,
+            timeout: Duration::from_secs(30),
+            max_output: MAX_OUTPUT,
+        }
+    }
+
+    pub fn arg(mut self, arg: &str) -> CgiHandler {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> CgiHandler {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> CgiHandler {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Limit the number of bytes the program may write to stdout.
+    pub fn max_output(mut self, max_output: u64) -> CgiHandler {
+        self.max_output = max_output;
+        self
+    }
+
+    /// Build the CGI meta-variables for `request`.
+    pub fn environment(&self, request: &Request) -> Vec<(String, String)> {
+        let path_info = split_prefix(&request.path, &self.script_name).unwrap_or("");
+
+        let host = request.header("Host").unwrap_or("localhost");
+        let (server_name, server_port) = match host.rsplit_once(':') {
+            Some((name, port)) => (name, port),
+            None => (host, "80"),
+        };
+
+        let mut env = vec![
+            ("GATEWAY_INTERFACE", "CGI/1.1".to_string()),
+            ("SERVER_SOFTWARE", "webapp".to_string()),
+            ("SERVER_PROTOCOL", request.version.clone()),
+            ("SERVER_NAME", server_name.to_string()),
+            ("SERVER_PORT", server_port.to_string()),
+            ("REQUEST_METHOD", request.method.clone()),
+            ("SCRIPT_NAME", self.script_name.clone()),
+            ("PATH_INFO", path_info.to_string()),
+            ("QUERY_STRING", request.query.clone()),
+        ];
+
+        if let Some(addr) = request.remote_addr {
+            env.push(("REMOTE_ADDR", addr.ip().to_string()));
+            env.push(("REMOTE_PORT", addr.port().to_string()));
+        }
+
+        if !request.body.is_empty() || request.header("Content-Length").is_some() {
+            env.push(("CONTENT_LENGTH", request.body.len().to_string()));
+        }
+        if let Some(content_type) = request.header("Content-Type") {
+            env.push(("CONTENT_TYPE", content_type.to_string()));
+        }
+
+        let mut env: Vec<(String, String)> = env
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+
+        for (name, value) in &request.headers {
+            // Content-Type and Content-Length already have their own
+            // variables, and HTTP_PROXY would let a client inject a proxy
+            // setting into the script ("httpoxy").
+            if name.eq_ignore_ascii_case("Content-Type")
+                || name.eq_ignore_ascii_case("Content-Length")
+                || name.eq_ignore_ascii_case("Proxy")
+            {
+                continue;
+            }
+
+            let var = format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_"));
+            match env.iter_mut().find(|(existing, _)| *existing == var) {
+                Some((_, existing)) => {
+                    existing.push_str(", ");
+                    existing.push_str(value);
+                }
+                None => env.push((var, value.clone())),
+            }
+        }
+
+        env
+    }
+
+    fn run(&self, request: &Request) -> Result<Response, Response> {
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .env_clear()
+            .envs(self.environment(request))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit());
+
+        if let Ok(path) = std::env::var("PATH") {
+            command.env("PATH", path);
+        }
+        if let Some(dir) = &self.current_dir {
+            command.current_dir(dir);
+        }
+
+        // Put the script in its own process group so a timeout can take
+        // down anything it forked as well.
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+
+        let mut child = command.spawn().map_err(|e| {
+            eprintln!("CGI: failed to start {}: {}", self.program.display(), e);
+            Response::new(500)
+        })?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        // Something outside the process group may still hold stdout open, so
+        // it is read on a thread of its own that is told to stop once the
+        // output is no longer waited for.
+        let (sender, receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let max_output = self.max_output;
+        thread::spawn({
+            let stop = Arc::clone(&stop);
+            move || {
+                let _ = sender.send(read_output(stdout, max_output, &stop));
+            }
+        });
+
+        let mut output = None;
+        let finished = thread::scope(|scope| {
+            scope.spawn(move || {
+                // The script is free to ignore its input, so a broken pipe
+                // here is not an error.
+                if let Ok(mut body) = request.body.reader() {
+                    let _ = io::copy(&mut body, &mut stdin);
+                }
+            });
+
+            // A script that writes too much is stopped without waiting for
+            // it to exit.
+            let finished = wait_timeout(&mut child, self.timeout, || {
+                if output.is_none() {
+                    output = receiver.try_recv().ok();
+                }
+                matches!(output, Some(Err(_)))
+            });
+            // Even a script that exited may have left a child in the
+            // background holding stdout open.
+            kill(&mut child);
+            finished
+        });
+
+        let output = match output {
+            Some(output) => Ok(output),
+            None if finished => receiver.recv_timeout(OUTPUT_GRACE),
+            None => Err(mpsc::RecvTimeoutError::Timeout),
+        };
+        stop.store(true, Ordering::Relaxed);
+
+        if !finished {
+            eprintln!(
+                "CGI: {} timed out after {:?}",
+                self.program.display(),
+                self.timeout
+            );
+            return Err(Response::new(504));
+        }
+
+        let output = output.map_err(|_| {
+            eprintln!(
+                "CGI: {} left its output open after exiting",
+                self.program.display()
+            );
+            Response::new(502)
+        })?;
+        let output = output.map_err(|e| {
+            if e.kind() == io::ErrorKind::FileTooLarge {
+                eprintln!(
+                    "CGI: {} wrote more than {} bytes",
+                    self.program.display(),
+                    self.max_output
+                );
+            }
+            Response::new(502)
+        })?;
+        parse_output(&output).ok_or_else(|| {
+            eprintln!("CGI: {} sent a malformed response", self.program.display());
+            Response::new(502)
+        })
+    }
+}
+
+impl Handler for CgiHandler {
+    fn handle(&self, request: &Request) -> Response {
+        match self.run(request) {
+            Ok(response) => response,
+            Err(response) => response,
+        }
+    }
+}
+
+/// Parse the output of a CGI script into a response.
+///
+/// Returns `None` when the header block is missing or malformed.
+pub fn parse_output(output: &[u8]) -> Option<Response> {
+    let (head, body) = split_head(output)?;
+    let head = std::str::from_utf8(head).ok()?;
+
+    let mut response = Response::new(200);
+    let mut status = None;
+
+    for line in head.lines() {
+        let (name, value) = line.split_once(':')?;
+        let (name, value) = (name.trim(), value.trim());
+
+        if name.eq_ignore_ascii_case("Status") {
+            let code = value.split_whitespace().next()?;
+            status = Some(code.parse().ok()?);
+        } else {
+            response = response.with_header(name, value);
+        }
+    }
+
+    response.status = match status {
+        Some(status) => status,
+        None if response.header("Location").is_some() => 302,
+        None => 200,
+    };
+
+    if response.header("Content-Type").is_none() && response.header("Location").is_none() {
+        return None;
+    }
+
+    Some(response.with_body(body))
+}
+
+fn split_head(output: &[u8]) -> Option<(&[u8], &[u8])> {
+    for i in 0..output.len() {
+        if output[i..].starts_with(b"\r\n\r\n") {
+            return Some((&output[..i], &output[i + 4..]));
+        }
+        if output[i..].starts_with(b"\n\n") {
+            return Some((&output[..i], &output[i + 2..]));
+        }
+    }
+
+    None
+}
+
+/// Wait for the script to exit, or for `give_up` to say it need not be
+/// waited for any longer. Returns false on timeout.
+fn wait_timeout(child: &mut Child, timeout: Duration, mut give_up: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        if give_up() {
+            return true;
+        }
+        match child.try_wait() {
+            Ok(Some(_)) => return true,
+            Ok(None) if Instant::now() >= deadline => return false,
+            Ok(None) => thread::sleep(Duration::from_millis(10)),
+            Err(_) => return false,
+        }
+    }
+}
+
+/// Read the script's output until it is closed, goes over `max_output`
+/// bytes, or `stop` is set.
+fn read_output(stdout: ChildStdout, max_output: u64, stop: &AtomicBool) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut buffer = [0; 8192];
+    let mut reader = stdout.take(max_output + 1);
+
+    loop {
+        if stop.load(Ordering::Relaxed) {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        if !readable(reader.get_ref(), Duration::from_millis(10))? {
+            continue;
+        }
+        match reader.read(&mut buffer) {
+            Ok(0) if output.len() as u64 > max_output => {
+                return Err(io::ErrorKind::FileTooLarge.into())
+            }
+            Ok(0) => return Ok(output),
+            Ok(n) => output.extend_from_slice(&buffer[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Wait up to `timeout` for something to read on stdout, or for it to be
+/// closed.
+#[cfg(unix)]
+fn readable(stdout: &ChildStdout, timeout: Duration) -> io::Result<bool> {
+    use std::ffi::{c_int, c_short};
+    use std::os::fd::AsRawFd;
+
+    #[cfg(target_os = "linux")]
+    type Nfds = std::ffi::c_ulong;
+    #[cfg(not(target_os = "linux"))]
+    type Nfds = std::ffi::c_uint;
+
+    #[repr(C)]
+    struct PollFd {
+        fd: c_int,
+        events: c_short,
+        revents: c_short,
+    }
+
+    extern "C" {
+        fn poll(fds: *mut PollFd, nfds: Nfds, timeout: c_int) -> c_int;
+    }
+    const POLLIN: c_short = 1;
+
+    let mut fds = PollFd {
+        fd: stdout.as_raw_fd(),
+        events: POLLIN,
+        revents: 0,
+    };
+    // SAFETY: `fds` is a single valid `pollfd` for the call.
+    match unsafe { poll(&mut fds, 1, timeout.as_millis() as c_int) } {
+        n if n < 0 => {
+            let e = io::Error::last_os_error();
+            match e.kind() {
+                io::ErrorKind::Interrupted => Ok(false),
+                _ => Err(e),
+            }
+        }
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+/// Without `poll`, reads block until the pipe is closed.
+#[cfg(not(unix))]
+fn readable(_: &ChildStdout, _: Duration) -> io::Result<bool> {
+    Ok(true)
+}
+
+/// Kill the script and everything in its process group, then reap it so it
+/// does not linger as a zombie. The script may already have exited.
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    {
+        extern "C" {
+            fn kill(pid: i32, sig: i32) -> i32;
+        }
+        const SIGKILL: i32 = 9;
+
+        // A negative pid signals the whole process group.
+        unsafe {
+            kill(-(child.id() as i32), SIGKILL);
+        }
+    }
+
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Body;
+
+    fn request(method: &str, path: &str, body: &str) -> Request {
+        let mut request = Request::new(method, &format!("{}?name=ferris", path));
+        request.headers = vec![
+            ("Host".to_string(), "localhost:7878".to_string()),
+            ("X-Token".to_string(), "abc".to_string()),
+        ];
+        request.body = Body::from(body.as_bytes().to_vec());
+        request
+    }
+
+    #[test]
+    fn builds_environment() {
+        let handler = CgiHandler::new("/cgi-bin/hello", "hello.sh");
+        let env = handler.environment(&request("GET", "/cgi-bin/hello/extra", ""));
+        let var = |name: &str| {
+            env.iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        assert_eq!(Some("GET"), var("REQUEST_METHOD"));
+        assert_eq!(Some("/cgi-bin/hello"), var("SCRIPT_NAME"));
+        assert_eq!(Some("/extra"), var("PATH_INFO"));
+        assert_eq!(Some("name=ferris"), var("QUERY_STRING"));
+        assert_eq!(Some("7878"), var("SERVER_PORT"));
+        assert_eq!(Some("abc"), var("HTTP_X_TOKEN"));
+        assert_eq!(None, var("CONTENT_LENGTH"));
+    }
+
+    #[test]
+    fn parses_status_header() {
+        let response =
+            parse_output(b"Status: 404 Not Found\nContent-Type: text/plain\n\nmissing").unwrap();
+
+        assert_eq!(404, response.status);
+        assert_eq!(Some("text/plain"), response.header("Content-Type"));
+        assert_eq!(b"missing", &response.body[..]);
+    }
+
+    #[test]
+    fn rejects_output_without_headers() {
+        assert!(parse_output(b"just some text").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_script_with_body_on_stdin() {
+        let handler = CgiHandler::new("/echo", "sh")
+            .arg("-c")
+            .arg("printf 'Content-Type: text/plain\\n\\n'; printf '%s:' \"$REQUEST_METHOD\"; cat");
+        let response = handler.handle(&request("POST", "/echo", "hello"));
+
+        assert_eq!(200, response.status);
+        assert_eq!(b"POST:hello", &response.body[..]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn kills_script_on_timeout() {
+        let handler = CgiHandler::new("/slow", "sh")
+            .arg("-c")
+            .arg("sleep 10 & sleep 10")
+            .timeout(Duration::from_millis(200));
+
+        let started = Instant::now();
+        let response = handler.handle(&request("GET", "/slow", ""));
+
+        assert_eq!(504, response.status);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn kills_background_children_holding_stdout() {
+        let handler = CgiHandler::new("/detach", "sh")
+            .arg("-c")
+            .arg("printf 'Content-Type: text/plain\\n\\nhi'; sleep 1000 &")
+            .timeout(Duration::from_secs(10));
+
+        let started = Instant::now();
+        let response = handler.handle(&request("GET", "/detach", ""));
+
+        assert_eq!(200, response.status);
+        assert_eq!(b"hi", &response.body[..]);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn kills_script_that_writes_too_much() {
+        let handler = CgiHandler::new("/flood", "sh")
+            .arg("-c")
+            .arg("printf 'Content-Type: text/plain\\n\\n'; yes")
+            .max_output(64 * 1024);
+
+        let started = Instant::now();
+        let response = handler.handle(&request("GET", "/flood", ""));
+
+        assert_eq!(502, response.status);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let handler = CgiHandler::new("/fits", "sh")
+            .arg("-c")
+            .arg("printf 'Content-Type: text/plain\\n\\n1234'")
+            .max_output(30);
+        assert_eq!(200, handler.handle(&request("GET", "/fits", "")).status);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stops_reading_output_that_stays_open() {
+        let mut child = Command::new("sleep")
+            .arg("10")
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        let started = Instant::now();
+        let error = read_output(stdout, MAX_OUTPUT, &AtomicBool::new(true)).unwrap_err();
+        assert_eq!(io::ErrorKind::TimedOut, error.kind());
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        kill(&mut child);
+    }
+}
@@ -0,0 +1,155 @@
+use std::{fmt, time::Duration};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    /// Browsers only accept `SameSite=None` on `Secure` cookies.
+    None,
+}
+
+/// A cookie to send to the client with `Set-Cookie`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    pub max_age: Option<Duration>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Cookie {
+        Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            max_age: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// A cookie that tells the client to delete `name` right away.
+    pub fn removal(name: &str) -> Cookie {
+        Cookie::new(name, "").max_age(Duration::ZERO)
+    }
+
+    pub fn path(mut self, path: &str) -> Cookie {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> Cookie {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Cookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+/// Formats the cookie as the value of a `Set-Cookie` header.
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => write!(f, "; SameSite=Strict")?,
+            Some(SameSite::Lax) => write!(f, "; SameSite=Lax")?,
+            Some(SameSite::None) => write!(f, "; SameSite=None")?,
+            None => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse the value of a `Cookie` request header into name/value pairs.
+///
+/// Pairs without a `=` are skipped and surrounding double quotes are
+/// removed from values.
+pub fn parse_cookies(header: &str) -> Vec<(String, String)> {
+    header
+        .split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| {
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            (name.trim().to_string(), value.to_string())
+        })
+        .filter(|(name, _)| !name.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cookie_header() {
+        assert_eq!(
+            vec![
+                ("id".to_string(), "abc".to_string()),
+                ("theme".to_string(), "dark mode".to_string()),
+            ],
+            parse_cookies("id=abc; broken; theme=\"dark mode\"")
+        );
+    }
+
+    #[test]
+    fn formats_set_cookie() {
+        let cookie = Cookie::new("id", "abc")
+            .path("/")
+            .max_age(Duration::from_secs(3600))
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Lax);
+
+        assert_eq!(
+            "id=abc; Path=/; Max-Age=3600; Secure; HttpOnly; SameSite=Lax",
+            cookie.to_string()
+        );
+        assert_eq!("id=; Max-Age=0", Cookie::removal("id").to_string());
+    }
+}
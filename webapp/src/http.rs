@@ -0,0 +1,404 @@
+use std::io::{self, prelude::*};
+use std::net::SocketAddr;
+
+use crate::cookie::{self, Cookie};
+use crate::session::Session;
+use crate::temp::TempFile;
+
+/// Request bodies larger than this are spooled to a temp file instead of
+/// being held in memory.
+pub const BODY_MEMORY_LIMIT: u64 = 64 * 1024;
+
+/// Requests with a larger `Content-Length` are refused before any of the
+/// body is read. This matches the total size multipart uploads allow.
+pub const MAX_BODY_SIZE: u64 = 50 * 1024 * 1024;
+
+/// The longest request or header line, and the most header lines, accepted.
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Body,
+    pub remote_addr: Option<SocketAddr>,
+    pub session: Session,
+}
+
+impl Request {
+    /// Create a request for `target`, which may include a query string.
+    pub fn new(method: &str, target: &str) -> Request {
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, query),
+            None => (target, ""),
+        };
+
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: query.to_string(),
+            version: "HTTP/1.1".to_string(),
+            headers: Vec::new(),
+            body: Body::empty(),
+            remote_addr: None,
+            session: Session::new(),
+        }
+    }
+
+    /// Read a single HTTP/1.x request from `reader`.
+    ///
+    /// The body is read according to the `Content-Length` header; requests
+    /// without one are treated as having an empty body. Bodies larger than
+    /// `BODY_MEMORY_LIMIT` are spooled to disk, and a `Content-Length` over
+    /// `MAX_BODY_SIZE` fails with `ErrorKind::FileTooLarge` without reading
+    /// the body at all.
+    pub fn parse<R: BufRead>(reader: &mut R) -> io::Result<Request> {
+        let line = read_line(reader)?;
+
+        let mut parts = line.split_whitespace();
+        let (method, target, version) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version)) => (method, target, version),
+            _ => return Err(invalid("malformed request line")),
+        };
+
+        let mut request = Request::new(method, target);
+        request.version = version.to_string();
+
+        loop {
+            let line = read_line(reader)?;
+            if line.is_empty() {
+                return Err(invalid("connection closed before end of headers"));
+            }
+
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                break;
+            }
+            if request.headers.len() == MAX_HEADERS {
+                return Err(invalid("too many header lines"));
+            }
+
+            match line.split_once(':') {
+                Some((name, value)) => request
+                    .headers
+                    .push((name.trim().to_string(), value.trim().to_string())),
+                None => return Err(invalid("malformed header line")),
+            }
+        }
+
+        if let Some(length) = request.header("Content-Length") {
+            let length: u64 = length
+                .parse()
+                .map_err(|_| invalid("invalid Content-Length"))?;
+            if length > MAX_BODY_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::FileTooLarge,
+                    "request body too large",
+                ));
+            }
+            request.body = Body::read_from(reader, length, BODY_MEMORY_LIMIT)?;
+        }
+
+        Ok(request)
+    }
+
+    /// Look up a header by name, ignoring ASCII case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// All cookies sent with the request, from every `Cookie` header.
+    pub fn cookies(&self) -> Vec<(String, String)> {
+        self.headers
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case("Cookie"))
+            .flat_map(|(_, value)| cookie::parse_cookies(value))
+            .collect()
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.cookies()
+            .into_iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+}
+
+enum BodyData {
+    Memory(Vec<u8>),
+    File(TempFile, u64),
+}
+
+/// The body of a request, either in memory or spooled to a temp file.
+pub struct Body {
+    data: BodyData,
+}
+
+impl Body {
+    pub fn empty() -> Body {
+        Body::from(Vec::new())
+    }
+
+    /// Read exactly `length` bytes from `reader`, keeping them in memory if
+    /// there are at most `memory_limit` of them.
+    pub fn read_from<R: Read>(reader: &mut R, length: u64, memory_limit: u64) -> io::Result<Body> {
+        let mut reader = reader.take(length);
+
+        let data = if length <= memory_limit {
+            let mut bytes = Vec::with_capacity(length as usize);
+            reader.read_to_end(&mut bytes)?;
+            BodyData::Memory(bytes)
+        } else {
+            let mut temp = TempFile::new()?;
+            io::copy(&mut reader, temp.file())?;
+            BodyData::File(temp, length)
+        };
+
+        if reader.limit() > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed before end of body",
+            ));
+        }
+
+        Ok(Body { data })
+    }
+
+    pub fn len(&self) -> u64 {
+        match &self.data {
+            BodyData::Memory(bytes) => bytes.len() as u64,
+            BodyData::File(_, len) => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Stream the body from the start.
+    pub fn reader(&self) -> io::Result<Box<dyn Read + '_>> {
+        match &self.data {
+            BodyData::Memory(bytes) => Ok(Box::new(&bytes[..])),
+            BodyData::File(temp, _) => Ok(Box::new(temp.reopen()?)),
+        }
+    }
+
+    /// Read the whole body into memory.
+    pub fn to_vec(&self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.reader()?.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Body {
+        Body {
+            data: BodyData::Memory(bytes),
+        }
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Add a `Set-Cookie` header for `cookie`.
+    pub fn with_cookie(self, cookie: &Cookie) -> Response {
+        self.with_header("Set-Cookie", &cookie.to_string())
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Serialize the response as HTTP/1.1, adding a `Content-Length` header.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\n",
+            self.status,
+            reason_phrase(self.status)
+        );
+
+        for (name, value) in &self.headers {
+            if !name.eq_ignore_ascii_case("Content-Length") {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", self.body.len()));
+
+        writer.write_all(head.as_bytes())?;
+        writer.write_all(&self.body)?;
+        writer.flush()
+    }
+}
+
+/// Something that can turn a request into a response.
+///
+/// Handlers are shared between the worker threads of the `ThreadPool`, so
+/// they must be `Send + Sync`. Plain closures work as handlers too.
+pub trait Handler: Send + Sync {
+    fn handle(&self, request: &Request) -> Response;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&Request) -> Response + Send + Sync,
+{
+    fn handle(&self, request: &Request) -> Response {
+        self(request)
+    }
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Unknown",
+    }
+}
+
+/// Read one line of at most `MAX_LINE_LENGTH` bytes, which is empty only at
+/// the end of the input.
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    reader.take(MAX_LINE_LENGTH).read_line(&mut line)?;
+    if line.len() as u64 == MAX_LINE_LENGTH && !line.ends_with('\n') {
+        return Err(invalid("request line or header too long"));
+    }
+    Ok(line)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_request_with_body() {
+        let raw = "POST /form?a=1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello";
+        let request = Request::parse(&mut raw.as_bytes()).unwrap();
+
+        assert_eq!("POST", request.method);
+        assert_eq!("/form", request.path);
+        assert_eq!("a=1", request.query);
+        assert_eq!(Some("localhost"), request.header("host"));
+        assert_eq!(b"hello", &request.body.to_vec().unwrap()[..]);
+    }
+
+    #[test]
+    fn refuses_oversized_bodies_before_reading_them() {
+        let raw = format!(
+            "POST /upload HTTP/1.1\r\nContent-Length: {}\r\n\r\nhello",
+            MAX_BODY_SIZE + 1
+        );
+        let mut reader = raw.as_bytes();
+        let error = Request::parse(&mut reader).err().unwrap();
+
+        assert_eq!(io::ErrorKind::FileTooLarge, error.kind());
+        assert_eq!(b"hello", reader);
+    }
+
+    #[test]
+    fn limits_header_lines() {
+        let long = format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", "a".repeat(10_000));
+        assert!(Request::parse(&mut long.as_bytes()).is_err());
+
+        let many = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X: 1\r\n".repeat(MAX_HEADERS + 1)
+        );
+        assert!(Request::parse(&mut many.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn finds_cookies_in_all_headers() {
+        let mut request = Request::new("GET", "/");
+        request
+            .headers
+            .push(("Cookie".to_string(), "a=1; b=2".to_string()));
+        request
+            .headers
+            .push(("cookie".to_string(), "c=3".to_string()));
+
+        assert_eq!(Some("2".to_string()), request.cookie("b"));
+        assert_eq!(Some("3".to_string()), request.cookie("c"));
+        assert_eq!(None, request.cookie("d"));
+    }
+
+    #[test]
+    fn spools_large_bodies() {
+        let data = vec![b'x'; 100];
+        let body = Body::read_from(&mut &data[..], 100, 10).unwrap();
+
+        assert_eq!(100, body.len());
+        assert_eq!(data, body.to_vec().unwrap());
+    }
+
+    #[test]
+    fn rejects_truncated_body() {
+        assert!(Body::read_from(&mut &b"short"[..], 10, 100).is_err());
+    }
+
+    #[test]
+    fn writes_content_length() {
+        let mut out = Vec::new();
+        Response::new(404)
+            .with_body("gone")
+            .write_to(&mut out)
+            .unwrap();
+
+        assert_eq!(
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 4\r\n\r\ngone",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
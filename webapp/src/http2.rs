@@ -0,0 +1,1154 @@
+//! HTTP/2 over cleartext TCP, started with prior knowledge (RFC 9113,
+//! section 3.3).
+//!
+//! The connection's reader thread parses frames and decodes headers. Every
+//! request that completes is queued for a few scoped handler threads, so
+//! slow handlers do not hold up other streams on the same connection, and
+//! a client opening many streams cannot start a thread for each.
+//! Responses are written back through a shared writer, with DATA frames
+//! held back until both the connection and the stream flow-control windows
+//! have room. Request bodies are buffered whole, and the client's windows
+//! are only opened again while the buffered bodies stay under a limit.
+
+pub mod frame;
+pub mod hpack;
+mod huffman;
+
+use std::{
+    collections::HashMap,
+    io::{self, prelude::*},
+    net::SocketAddr,
+    sync::{mpsc, Condvar, Mutex},
+    thread,
+};
+
+use crate::http::{Body, Handler, Request, Response};
+use frame::Frame;
+
+/// What a client sends first when it knows the server speaks HTTP/2.
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const MAX_CONCURRENT_STREAMS: usize = 100;
+/// The most handlers run at once for one connection; further requests wait
+/// for one of them to finish.
+const MAX_HANDLER_THREADS: usize = 4;
+const HEADER_TABLE_SIZE: usize = 4096;
+const DEFAULT_WINDOW_SIZE: i64 = 65_535;
+const MAX_WINDOW_SIZE: i64 = (1 << 31) - 1;
+const MAX_HEADER_BLOCK_SIZE: usize = 64 * 1024;
+/// The largest header list a request may decode to, as advertised in
+/// SETTINGS_MAX_HEADER_LIST_SIZE.
+const MAX_HEADER_LIST_SIZE: usize = 64 * 1024;
+const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+/// The most request body data buffered at once across all streams of a
+/// connection. Past it, the connection's receive window stays shut until
+/// requests are handed to their handlers.
+const MAX_BUFFERED_BODIES: usize = 32 * 1024 * 1024;
+
+/// Headers that only make sense on an HTTP/1.1 connection.
+const CONNECTION_HEADERS: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+enum Fault {
+    Io(io::Error),
+    Connection(u32, &'static str),
+}
+
+impl From<io::Error> for Fault {
+    fn from(e: io::Error) -> Fault {
+        Fault::Io(e)
+    }
+}
+
+struct FlowState {
+    connection_window: i64,
+    initial_window: i64,
+    max_frame_size: usize,
+    /// Send windows of open streams that have not finished their response.
+    streams: HashMap<u32, i64>,
+    closed: bool,
+}
+
+struct Shared<W> {
+    writer: Mutex<(W, hpack::Encoder)>,
+    flow: Mutex<FlowState>,
+    flow_changed: Condvar,
+}
+
+impl<W: Write> Shared<W> {
+    fn new(writer: W) -> Shared<W> {
+        Shared {
+            writer: Mutex::new((writer, hpack::Encoder::new())),
+            flow: Mutex::new(FlowState {
+                connection_window: DEFAULT_WINDOW_SIZE,
+                initial_window: DEFAULT_WINDOW_SIZE,
+                max_frame_size: frame::DEFAULT_MAX_FRAME_SIZE,
+                streams: HashMap::new(),
+                closed: false,
+            }),
+            flow_changed: Condvar::new(),
+        }
+    }
+
+    fn send(&self, frame: Frame) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        frame.write_to(&mut writer.0)?;
+        writer.0.flush()
+    }
+
+    fn close(&self) {
+        self.flow.lock().unwrap().closed = true;
+        self.flow_changed.notify_all();
+    }
+
+    /// Whether the response on a stream is still to be sent.
+    fn is_answering(&self, stream_id: u32) -> bool {
+        self.flow.lock().unwrap().streams.contains_key(&stream_id)
+    }
+
+    fn forget_stream(&self, stream_id: u32) {
+        self.flow.lock().unwrap().streams.remove(&stream_id);
+        self.flow_changed.notify_all();
+    }
+
+    fn send_response(&self, stream_id: u32, response: Response) {
+        if let Err(e) = self.try_send_response(stream_id, response) {
+            eprintln!("HTTP/2: failed to send response: {}", e);
+            self.close();
+        }
+
+        self.forget_stream(stream_id);
+    }
+
+    fn try_send_response(&self, stream_id: u32, response: Response) -> io::Result<()> {
+        let mut headers = vec![(":status".to_string(), response.status.to_string())];
+        for (name, value) in &response.headers {
+            let name = name.to_ascii_lowercase();
+            if name != "content-length" && !CONNECTION_HEADERS.contains(&name.as_str()) {
+                headers.push((name, value.clone()));
+            }
+        }
+        headers.push((
+            "content-length".to_string(),
+            response.body.len().to_string(),
+        ));
+
+        let max_frame_size = self.flow.lock().unwrap().max_frame_size;
+        {
+            let mut writer = self.writer.lock().unwrap();
+            let (out, encoder) = &mut *writer;
+
+            // The header block must go out in one piece, so the writer stays
+            // locked until the last CONTINUATION frame.
+            let block = encoder.encode(&headers);
+            let chunks: Vec<&[u8]> = block.chunks(max_frame_size).collect();
+
+            for (i, chunk) in chunks.iter().enumerate() {
+                let mut flags = 0;
+                if i == chunks.len() - 1 {
+                    flags |= frame::END_HEADERS;
+                }
+
+                let kind = if i == 0 {
+                    if response.body.is_empty() {
+                        flags |= frame::END_STREAM;
+                    }
+                    frame::HEADERS
+                } else {
+                    frame::CONTINUATION
+                };
+
+                Frame::new(kind, flags, stream_id, chunk.to_vec()).write_to(out)?;
+            }
+            out.flush()?;
+        }
+
+        let mut remaining = &response.body[..];
+        while !remaining.is_empty() {
+            let len = match self.reserve(stream_id, remaining.len())? {
+                Some(len) => len,
+                None => return Ok(()),
+            };
+
+            let (chunk, rest) = remaining.split_at(len);
+            remaining = rest;
+
+            let flags = if remaining.is_empty() {
+                frame::END_STREAM
+            } else {
+                0
+            };
+            self.send(Frame::new(frame::DATA, flags, stream_id, chunk.to_vec()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Wait until up to `wanted` bytes may be sent on `stream_id` and take
+    /// them out of the flow-control windows.
+    ///
+    /// Returns `None` if the peer reset the stream in the meantime.
+    fn reserve(&self, stream_id: u32, wanted: usize) -> io::Result<Option<usize>> {
+        let mut flow = self.flow.lock().unwrap();
+
+        loop {
+            let stream_window = match flow.streams.get(&stream_id) {
+                Some(window) => *window,
+                None => return Ok(None),
+            };
+
+            if flow.connection_window > 0 && stream_window > 0 {
+                let len = wanted
+                    .min(flow.max_frame_size)
+                    .min(flow.connection_window as usize)
+                    .min(stream_window as usize);
+
+                flow.connection_window -= len as i64;
+                *flow.streams.get_mut(&stream_id).unwrap() -= len as i64;
+                return Ok(Some(len));
+            }
+
+            // Once the reader is gone no window update can arrive.
+            if flow.closed {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "connection closed",
+                ));
+            }
+
+            flow = self.flow_changed.wait(flow).unwrap();
+        }
+    }
+}
+
+struct PendingRequest {
+    request: Request,
+    body: Vec<u8>,
+    /// How much more the client may send on the stream.
+    window: i64,
+}
+
+/// The reader side of a connection.
+struct Connection<'a, W> {
+    shared: &'a Shared<W>,
+    decoder: hpack::Decoder,
+    remote_addr: Option<SocketAddr>,
+    /// Requests whose headers arrived but whose body is still coming.
+    pending: HashMap<u32, PendingRequest>,
+    /// A header block waiting for CONTINUATION frames.
+    header_block: Option<(u32, Vec<u8>, bool)>,
+    last_stream_id: u32,
+    /// How much more the client may send on the connection.
+    receive_window: i64,
+    /// The size of the bodies in `pending`.
+    buffered: usize,
+}
+
+impl<'a, W: Write> Connection<'a, W> {
+    /// Process one frame, returning a request if it completed one.
+    fn handle_frame(&mut self, frame: Frame) -> Result<Option<(u32, Request)>, Fault> {
+        if let Some((stream_id, _, _)) = &self.header_block {
+            if frame.kind != frame::CONTINUATION || frame.stream_id != *stream_id {
+                return Err(Fault::Connection(
+                    frame::PROTOCOL_ERROR,
+                    "expected CONTINUATION",
+                ));
+            }
+        }
+
+        match frame.kind {
+            frame::DATA => self.on_data(frame),
+            frame::HEADERS => self.on_headers(frame),
+            frame::CONTINUATION => self.on_continuation(frame),
+            frame::PRIORITY => {
+                if frame.stream_id == 0 {
+                    return Err(Fault::Connection(
+                        frame::PROTOCOL_ERROR,
+                        "PRIORITY on stream 0",
+                    ));
+                }
+                if frame.payload.len() != 5 {
+                    self.reset(frame.stream_id, frame::FRAME_SIZE_ERROR)?;
+                }
+                Ok(None)
+            }
+            frame::RST_STREAM => {
+                if frame.stream_id == 0 || frame.stream_id > self.last_stream_id {
+                    return Err(Fault::Connection(
+                        frame::PROTOCOL_ERROR,
+                        "RST_STREAM on idle stream",
+                    ));
+                }
+                if frame.payload.len() != 4 {
+                    return Err(Fault::Connection(
+                        frame::FRAME_SIZE_ERROR,
+                        "bad RST_STREAM length",
+                    ));
+                }
+                self.take_pending(frame.stream_id);
+                self.shared.forget_stream(frame.stream_id);
+                self.open_connection_window()?;
+                Ok(None)
+            }
+            frame::SETTINGS => self.on_settings(frame),
+            frame::PUSH_PROMISE => Err(Fault::Connection(
+                frame::PROTOCOL_ERROR,
+                "clients cannot push",
+            )),
+            frame::PING => {
+                if frame.stream_id != 0 {
+                    return Err(Fault::Connection(frame::PROTOCOL_ERROR, "PING on a stream"));
+                }
+                if frame.payload.len() != 8 {
+                    return Err(Fault::Connection(
+                        frame::FRAME_SIZE_ERROR,
+                        "bad PING length",
+                    ));
+                }
+                if !frame.has_flag(frame::ACK) {
+                    self.shared
+                        .send(Frame::new(frame::PING, frame::ACK, 0, frame.payload))?;
+                }
+                Ok(None)
+            }
+            frame::GOAWAY => {
+                if frame.stream_id != 0 {
+                    return Err(Fault::Connection(
+                        frame::PROTOCOL_ERROR,
+                        "GOAWAY on a stream",
+                    ));
+                }
+                Ok(None)
+            }
+            frame::WINDOW_UPDATE => self.on_window_update(frame),
+            // Unknown frame types must be ignored.
+            _ => Ok(None),
+        }
+    }
+
+    fn on_data(&mut self, frame: Frame) -> Result<Option<(u32, Request)>, Fault> {
+        let stream_id = frame.stream_id;
+        if stream_id == 0 {
+            return Err(Fault::Connection(frame::PROTOCOL_ERROR, "DATA on stream 0"));
+        }
+
+        // Padding counts towards flow control too.
+        let len = frame.payload.len() as i64;
+        if len > self.receive_window {
+            return Err(Fault::Connection(
+                frame::FLOW_CONTROL_ERROR,
+                "connection window exceeded",
+            ));
+        }
+        self.receive_window -= len;
+
+        let data = frame
+            .unpadded()
+            .ok_or(Fault::Connection(frame::PROTOCOL_ERROR, "bad padding"))?;
+
+        let pending = match self.pending.get_mut(&stream_id) {
+            Some(pending) => pending,
+            None if stream_id > self.last_stream_id => {
+                return Err(Fault::Connection(
+                    frame::PROTOCOL_ERROR,
+                    "DATA on idle stream",
+                ))
+            }
+            // The request already went to its handler. Its response is
+            // still sent, so the data is dropped without resetting the
+            // stream.
+            None if self.shared.is_answering(stream_id) => {
+                self.open_connection_window()?;
+                return Ok(None);
+            }
+            None => {
+                self.reset(stream_id, frame::STREAM_CLOSED)?;
+                return Ok(None);
+            }
+        };
+
+        if len > pending.window {
+            self.reset(stream_id, frame::FLOW_CONTROL_ERROR)?;
+            return Ok(None);
+        }
+        pending.window -= len;
+
+        if pending.body.len() + data.len() > MAX_BODY_SIZE {
+            // Answer right away and tell the client to stop sending.
+            self.take_pending(stream_id);
+            self.shared.send_response(stream_id, Response::new(413));
+            self.shared
+                .send(Frame::rst_stream(stream_id, frame::NO_ERROR))?;
+            self.open_connection_window()?;
+            return Ok(None);
+        }
+
+        pending.body.extend_from_slice(data);
+        self.buffered += data.len();
+
+        if frame.has_flag(frame::END_STREAM) {
+            let request = self.finish(stream_id);
+            self.open_connection_window()?;
+            return Ok(request);
+        }
+
+        // The stream's own window is opened again as soon as its data is
+        // buffered; MAX_BODY_SIZE and the connection window bound the rest.
+        let increment = DEFAULT_WINDOW_SIZE - pending.window;
+        if increment > 0 {
+            pending.window = DEFAULT_WINDOW_SIZE;
+            self.shared
+                .send(Frame::window_update(stream_id, increment as u32))?;
+        }
+        self.open_connection_window()?;
+
+        Ok(None)
+    }
+
+    fn on_headers(&mut self, frame: Frame) -> Result<Option<(u32, Request)>, Fault> {
+        if frame.stream_id == 0 {
+            return Err(Fault::Connection(
+                frame::PROTOCOL_ERROR,
+                "HEADERS on stream 0",
+            ));
+        }
+
+        let mut block = frame
+            .unpadded()
+            .ok_or(Fault::Connection(frame::PROTOCOL_ERROR, "bad padding"))?;
+
+        if frame.has_flag(frame::PRIORITY_FLAG) {
+            if block.len() < 5 {
+                return Err(Fault::Connection(
+                    frame::FRAME_SIZE_ERROR,
+                    "HEADERS too short",
+                ));
+            }
+            block = &block[5..];
+        }
+
+        let end_stream = frame.has_flag(frame::END_STREAM);
+        if frame.has_flag(frame::END_HEADERS) {
+            self.on_header_block(frame.stream_id, block, end_stream)
+        } else {
+            self.header_block = Some((frame.stream_id, block.to_vec(), end_stream));
+            Ok(None)
+        }
+    }
+
+    fn on_continuation(&mut self, frame: Frame) -> Result<Option<(u32, Request)>, Fault> {
+        let (stream_id, mut block, end_stream) = self.header_block.take().ok_or(
+            Fault::Connection(frame::PROTOCOL_ERROR, "unexpected CONTINUATION"),
+        )?;
+
+        block.extend_from_slice(&frame.payload);
+        if block.len() > MAX_HEADER_BLOCK_SIZE {
+            return Err(Fault::Connection(
+                frame::PROTOCOL_ERROR,
+                "header block too large",
+            ));
+        }
+
+        if frame.has_flag(frame::END_HEADERS) {
+            self.on_header_block(stream_id, &block, end_stream)
+        } else {
+            self.header_block = Some((stream_id, block, end_stream));
+            Ok(None)
+        }
+    }
+
+    fn on_header_block(
+        &mut self,
+        stream_id: u32,
+        block: &[u8],
+        end_stream: bool,
+    ) -> Result<Option<(u32, Request)>, Fault> {
+        // Always decode, even for streams we refuse, to keep the HPACK
+        // state in sync with the client.
+        let headers = self
+            .decoder
+            .decode(block)
+            .map_err(|_| Fault::Connection(frame::COMPRESSION_ERROR, "invalid header block"))?;
+
+        if self.pending.contains_key(&stream_id) {
+            // Trailers. They must end the stream; their contents are not
+            // passed on to the handler.
+            if !end_stream {
+                self.reset(stream_id, frame::PROTOCOL_ERROR)?;
+                return Ok(None);
+            }
+            let request = self.finish(stream_id);
+            self.open_connection_window()?;
+            return Ok(request);
+        }
+
+        if stream_id.is_multiple_of(2) {
+            return Err(Fault::Connection(
+                frame::PROTOCOL_ERROR,
+                "even stream id from client",
+            ));
+        }
+        if stream_id <= self.last_stream_id {
+            return Err(Fault::Connection(
+                frame::STREAM_CLOSED,
+                "HEADERS on closed stream",
+            ));
+        }
+        self.last_stream_id = stream_id;
+
+        let request = match build_request(headers, self.remote_addr) {
+            Some(request) => request,
+            None => {
+                self.reset(stream_id, frame::PROTOCOL_ERROR)?;
+                return Ok(None);
+            }
+        };
+
+        {
+            let mut flow = self.shared.flow.lock().unwrap();
+            if flow.streams.len() >= MAX_CONCURRENT_STREAMS {
+                drop(flow);
+                self.reset(stream_id, frame::REFUSED_STREAM)?;
+                return Ok(None);
+            }
+
+            let window = flow.initial_window;
+            flow.streams.insert(stream_id, window);
+        }
+
+        self.pending.insert(
+            stream_id,
+            PendingRequest {
+                request,
+                body: Vec::new(),
+                window: DEFAULT_WINDOW_SIZE,
+            },
+        );
+
+        if end_stream {
+            Ok(self.finish(stream_id))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn on_settings(&mut self, frame: Frame) -> Result<Option<(u32, Request)>, Fault> {
+        if frame.stream_id != 0 {
+            return Err(Fault::Connection(
+                frame::PROTOCOL_ERROR,
+                "SETTINGS on a stream",
+            ));
+        }
+
+        if frame.has_flag(frame::ACK) {
+            if !frame.payload.is_empty() {
+                return Err(Fault::Connection(
+                    frame::FRAME_SIZE_ERROR,
+                    "SETTINGS ACK with payload",
+                ));
+            }
+            return Ok(None);
+        }
+
+        if !frame.payload.len().is_multiple_of(6) {
+            return Err(Fault::Connection(
+                frame::FRAME_SIZE_ERROR,
+                "bad SETTINGS length",
+            ));
+        }
+
+        {
+            let mut flow = self.shared.flow.lock().unwrap();
+
+            for setting in frame.payload.chunks(6) {
+                let id = u16::from_be_bytes([setting[0], setting[1]]);
+                let value = frame::read_u32(&setting[2..]);
+
+                match id {
+                    frame::SETTINGS_ENABLE_PUSH if value > 1 => {
+                        return Err(Fault::Connection(frame::PROTOCOL_ERROR, "bad ENABLE_PUSH"));
+                    }
+                    frame::SETTINGS_INITIAL_WINDOW_SIZE => {
+                        let value = value as i64;
+                        if value > MAX_WINDOW_SIZE {
+                            return Err(Fault::Connection(
+                                frame::FLOW_CONTROL_ERROR,
+                                "window too large",
+                            ));
+                        }
+
+                        // The change applies to the windows of all open
+                        // streams, which may even become negative.
+                        let delta = value - flow.initial_window;
+                        for window in flow.streams.values_mut() {
+                            *window += delta;
+                            if *window > MAX_WINDOW_SIZE {
+                                return Err(Fault::Connection(
+                                    frame::FLOW_CONTROL_ERROR,
+                                    "window too large",
+                                ));
+                            }
+                        }
+                        flow.initial_window = value;
+                    }
+                    frame::SETTINGS_MAX_FRAME_SIZE => {
+                        if !(16_384..=16_777_215).contains(&value) {
+                            return Err(Fault::Connection(
+                                frame::PROTOCOL_ERROR,
+                                "bad MAX_FRAME_SIZE",
+                            ));
+                        }
+                        flow.max_frame_size = value as usize;
+                    }
+                    // Our encoder never uses the dynamic table, so the
+                    // peer's table size does not matter.
+                    _ => {}
+                }
+            }
+        }
+        self.shared.flow_changed.notify_all();
+
+        self.shared
+            .send(Frame::new(frame::SETTINGS, frame::ACK, 0, Vec::new()))?;
+        Ok(None)
+    }
+
+    fn on_window_update(&mut self, frame: Frame) -> Result<Option<(u32, Request)>, Fault> {
+        if frame.payload.len() != 4 {
+            return Err(Fault::Connection(
+                frame::FRAME_SIZE_ERROR,
+                "bad WINDOW_UPDATE length",
+            ));
+        }
+
+        let increment = (frame::read_u32(&frame.payload) & 0x7fff_ffff) as i64;
+
+        if frame.stream_id == 0 {
+            if increment == 0 {
+                return Err(Fault::Connection(
+                    frame::PROTOCOL_ERROR,
+                    "zero WINDOW_UPDATE",
+                ));
+            }
+
+            let mut flow = self.shared.flow.lock().unwrap();
+            flow.connection_window += increment;
+            if flow.connection_window > MAX_WINDOW_SIZE {
+                return Err(Fault::Connection(
+                    frame::FLOW_CONTROL_ERROR,
+                    "window too large",
+                ));
+            }
+        } else if frame.stream_id > self.last_stream_id {
+            return Err(Fault::Connection(
+                frame::PROTOCOL_ERROR,
+                "WINDOW_UPDATE on idle stream",
+            ));
+        } else {
+            let overflow = {
+                let mut flow = self.shared.flow.lock().unwrap();
+                match flow.streams.get_mut(&frame.stream_id) {
+                    Some(window) => {
+                        *window += increment;
+                        increment == 0 || *window > MAX_WINDOW_SIZE
+                    }
+                    None => false,
+                }
+            };
+
+            if overflow {
+                self.reset(frame.stream_id, frame::FLOW_CONTROL_ERROR)?;
+            }
+        }
+
+        self.shared.flow_changed.notify_all();
+        Ok(None)
+    }
+
+    fn finish(&mut self, stream_id: u32) -> Option<(u32, Request)> {
+        let pending = self.take_pending(stream_id)?;
+        let mut request = pending.request;
+        request.body = Body::from(pending.body);
+
+        Some((stream_id, request))
+    }
+
+    fn take_pending(&mut self, stream_id: u32) -> Option<PendingRequest> {
+        let pending = self.pending.remove(&stream_id)?;
+        self.buffered -= pending.body.len();
+        Some(pending)
+    }
+
+    /// Let the client send up to the default window size again on the
+    /// connection, but only as far as the bodies buffered leave room for.
+    /// Bodies stop counting once their request goes to the handler.
+    fn open_connection_window(&mut self) -> io::Result<()> {
+        let room = MAX_BUFFERED_BODIES.saturating_sub(self.buffered) as i64;
+        let target = DEFAULT_WINDOW_SIZE.min(room);
+        if self.receive_window < target {
+            let increment = target - self.receive_window;
+            self.receive_window = target;
+            self.shared
+                .send(Frame::window_update(0, increment as u32))?;
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self, stream_id: u32, error_code: u32) -> io::Result<()> {
+        self.take_pending(stream_id);
+        self.shared.forget_stream(stream_id);
+        self.shared.send(Frame::rst_stream(stream_id, error_code))?;
+        self.open_connection_window()
+    }
+}
+
+/// Turn a decoded header list into a request, or `None` if it is malformed.
+fn build_request(
+    headers: Vec<(String, String)>,
+    remote_addr: Option<SocketAddr>,
+) -> Option<Request> {
+    let mut method = None;
+    let mut target = None;
+    let mut scheme = None;
+    let mut authority = None;
+    let mut regular = Vec::new();
+    let mut cookies = Vec::new();
+
+    for (name, value) in headers {
+        if let Some(pseudo) = name.strip_prefix(':') {
+            // Pseudo-headers must come before regular ones.
+            if !regular.is_empty() || !cookies.is_empty() {
+                return None;
+            }
+
+            let slot = match pseudo {
+                "method" => &mut method,
+                "path" => &mut target,
+                "scheme" => &mut scheme,
+                "authority" => &mut authority,
+                _ => return None,
+            };
+            if slot.replace(value).is_some() {
+                return None;
+            }
+        } else if name.bytes().any(|b| b.is_ascii_uppercase())
+            || CONNECTION_HEADERS.contains(&name.as_str())
+            || (name == "te" && value != "trailers")
+        {
+            return None;
+        } else if name == "cookie" {
+            // HTTP/2 allows the cookie header to be split into several
+            // fields; handlers expect a single one.
+            cookies.push(value);
+        } else {
+            regular.push((name, value));
+        }
+    }
+
+    let method = method?;
+    let target = target.filter(|target| !target.is_empty())?;
+    scheme?;
+
+    if !cookies.is_empty() {
+        regular.push(("cookie".to_string(), cookies.join("; ")));
+    }
+    if let Some(authority) = authority {
+        if !regular.iter().any(|(name, _)| name == "host") {
+            regular.insert(0, ("host".to_string(), authority));
+        }
+    }
+
+    let mut request = Request::new(&method, &target);
+    request.version = "HTTP/2.0".to_string();
+    request.headers = regular;
+    request.remote_addr = remote_addr;
+
+    Some(request)
+}
+
+/// Serve an HTTP/2 connection, starting with the client preface.
+///
+/// Returns once the client closes the connection or a connection error
+/// occurs, after all in-flight requests have been answered.
+pub fn serve<R, W>(
+    mut reader: R,
+    writer: W,
+    handler: &dyn Handler,
+    remote_addr: Option<SocketAddr>,
+) -> io::Result<()>
+where
+    R: Read,
+    W: Write + Send,
+{
+    let mut preface = [0; 24];
+    reader.read_exact(&mut preface)?;
+    if preface != PREFACE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "missing HTTP/2 preface",
+        ));
+    }
+
+    let shared = Shared::new(writer);
+
+    shared.send(Frame::settings(&[
+        (
+            frame::SETTINGS_MAX_CONCURRENT_STREAMS,
+            MAX_CONCURRENT_STREAMS as u32,
+        ),
+        (frame::SETTINGS_HEADER_TABLE_SIZE, HEADER_TABLE_SIZE as u32),
+        (
+            frame::SETTINGS_MAX_HEADER_LIST_SIZE,
+            MAX_HEADER_LIST_SIZE as u32,
+        ),
+    ]))?;
+
+    let mut connection = Connection {
+        shared: &shared,
+        decoder: hpack::Decoder::new(HEADER_TABLE_SIZE).max_list_size(MAX_HEADER_LIST_SIZE),
+        remote_addr,
+        pending: HashMap::new(),
+        header_block: None,
+        last_stream_id: 0,
+        receive_window: DEFAULT_WINDOW_SIZE,
+        buffered: 0,
+    };
+
+    let (queue, requests) = mpsc::channel::<(u32, Request)>();
+    let requests = Mutex::new(requests);
+
+    thread::scope(|scope| {
+        let mut handler_threads = 0;
+
+        let result = loop {
+            let frame = match Frame::read_from(&mut reader, frame::DEFAULT_MAX_FRAME_SIZE) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    break Err(Fault::Connection(
+                        frame::FRAME_SIZE_ERROR,
+                        "frame too large",
+                    ))
+                }
+                Err(e) => break Err(Fault::Io(e)),
+            };
+
+            match connection.handle_frame(frame) {
+                Ok(Some(request)) => {
+                    // Threads are started as requests arrive, up to the
+                    // limit, and then live as long as the connection.
+                    if handler_threads < MAX_HANDLER_THREADS {
+                        handler_threads += 1;
+                        let (shared, requests) = (&shared, &requests);
+                        scope.spawn(move || loop {
+                            let next = requests.lock().unwrap().recv();
+                            let Ok((stream_id, request)) = next else {
+                                break;
+                            };
+                            let response = handler.handle(&request);
+                            shared.send_response(stream_id, response);
+                        });
+                    }
+                    // The handler threads only stop once `queue` is dropped.
+                    queue.send(request).unwrap();
+                }
+                Ok(None) => {}
+                Err(fault) => break Err(fault),
+            }
+        };
+
+        let result = match result {
+            Ok(()) => Ok(()),
+            Err(Fault::Io(e)) => Err(e),
+            Err(Fault::Connection(code, reason)) => {
+                let _ = shared.send(Frame::goaway(connection.last_stream_id, code));
+                Err(io::Error::new(io::ErrorKind::InvalidData, reason))
+            }
+        };
+
+        // Nothing more will arrive, so responses that run out of window can
+        // never complete.
+        shared.close();
+        drop(queue);
+        result
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(mut bytes: &[u8]) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        while let Some(frame) = Frame::read_from(&mut bytes, 1 << 24).unwrap() {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    fn request_headers(path: &str) -> Vec<u8> {
+        let headers: Vec<(String, String)> = [
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":authority", "localhost"),
+            (":path", path),
+        ]
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+
+        hpack::Encoder::new().encode(&headers)
+    }
+
+    #[test]
+    fn serves_multiplexed_requests() {
+        let mut input = PREFACE.to_vec();
+        Frame::settings(&[]).write_to(&mut input).unwrap();
+        for (stream_id, path) in [(1, "/one"), (3, "/two?x=1")] {
+            Frame::new(
+                frame::HEADERS,
+                frame::END_HEADERS | frame::END_STREAM,
+                stream_id,
+                request_headers(path),
+            )
+            .write_to(&mut input)
+            .unwrap();
+        }
+
+        let handler = |request: &Request| {
+            Response::new(200).with_body(format!(
+                "{} {} {}",
+                request.path,
+                request.query,
+                request.header("Host").unwrap_or("")
+            ))
+        };
+
+        let mut output = Vec::new();
+        serve(&input[..], &mut output, &handler, None).unwrap();
+
+        let frames = frames(&output);
+        assert_eq!(frame::SETTINGS, frames[0].kind);
+        assert!(frames
+            .iter()
+            .any(|f| f.kind == frame::SETTINGS && f.has_flag(frame::ACK)));
+
+        let mut decoder = hpack::Decoder::new(4096);
+        for (stream_id, body) in [(1, "/one  localhost"), (3, "/two x=1 localhost")] {
+            let headers = frames
+                .iter()
+                .find(|f| f.kind == frame::HEADERS && f.stream_id == stream_id)
+                .unwrap();
+            let decoded = decoder.decode(&headers.payload).unwrap();
+            assert_eq!((":status".to_string(), "200".to_string()), decoded[0]);
+
+            let data = frames
+                .iter()
+                .find(|f| f.kind == frame::DATA && f.stream_id == stream_id)
+                .unwrap();
+            assert!(data.has_flag(frame::END_STREAM));
+            assert_eq!(body.as_bytes(), &data.payload[..]);
+        }
+    }
+
+    #[test]
+    fn respects_flow_control_windows() {
+        let mut input = PREFACE.to_vec();
+        Frame::new(
+            frame::HEADERS,
+            frame::END_HEADERS | frame::END_STREAM,
+            1,
+            request_headers("/big"),
+        )
+        .write_to(&mut input)
+        .unwrap();
+        Frame::window_update(0, 50_000)
+            .write_to(&mut input)
+            .unwrap();
+        Frame::window_update(1, 50_000)
+            .write_to(&mut input)
+            .unwrap();
+
+        let handler = |_: &Request| Response::new(200).with_body(vec![b'x'; 100_000]);
+        let mut output = Vec::new();
+        serve(&input[..], &mut output, &handler, None).unwrap();
+
+        let data: Vec<Frame> = frames(&output)
+            .into_iter()
+            .filter(|f| f.kind == frame::DATA)
+            .collect();
+
+        assert!(data
+            .iter()
+            .all(|f| f.payload.len() <= frame::DEFAULT_MAX_FRAME_SIZE));
+        assert_eq!(100_000, data.iter().map(|f| f.payload.len()).sum::<usize>());
+        assert!(data.last().unwrap().has_flag(frame::END_STREAM));
+    }
+
+    #[test]
+    fn limits_concurrent_handlers() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        let mut input = PREFACE.to_vec();
+        for stream_id in (1..40).step_by(2) {
+            Frame::new(
+                frame::HEADERS,
+                frame::END_HEADERS | frame::END_STREAM,
+                stream_id,
+                request_headers("/"),
+            )
+            .write_to(&mut input)
+            .unwrap();
+        }
+
+        let (running, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        let handler = |_: &Request| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(5));
+            running.fetch_sub(1, Ordering::SeqCst);
+            Response::new(204)
+        };
+        let mut output = Vec::new();
+        serve(&input[..], &mut output, &handler, None).unwrap();
+
+        let answered = frames(&output)
+            .iter()
+            .filter(|f| f.kind == frame::HEADERS)
+            .count();
+        assert_eq!(20, answered);
+        assert!(most.load(Ordering::SeqCst) <= MAX_HANDLER_THREADS);
+    }
+
+    #[test]
+    fn enforces_receive_windows() {
+        let shared = Shared::new(Vec::new());
+        let mut connection = Connection {
+            shared: &shared,
+            decoder: hpack::Decoder::new(HEADER_TABLE_SIZE),
+            remote_addr: None,
+            pending: HashMap::new(),
+            header_block: None,
+            last_stream_id: 0,
+            receive_window: DEFAULT_WINDOW_SIZE,
+            buffered: 0,
+        };
+        let data = |stream_id, flags, len| Frame::new(frame::DATA, flags, stream_id, vec![0; len]);
+        // The WINDOW_UPDATE frames sent since last asked, for stream 1 and
+        // for the connection.
+        let updates = |shared: &Shared<Vec<u8>>| {
+            let mut writer = shared.writer.lock().unwrap();
+            let sent = frames(&writer.0);
+            writer.0.clear();
+            let count = |stream_id| {
+                sent.iter()
+                    .filter(|f| f.kind == frame::WINDOW_UPDATE && f.stream_id == stream_id)
+                    .count()
+            };
+            (count(1), count(0))
+        };
+
+        for stream_id in [1, 3] {
+            let headers = Frame::new(
+                frame::HEADERS,
+                frame::END_HEADERS,
+                stream_id,
+                request_headers("/"),
+            );
+            assert!(connection.handle_frame(headers).ok().unwrap().is_none());
+        }
+
+        // The stream's window is opened again once its data is buffered.
+        assert!(connection.handle_frame(data(1, 0, 16_384)).is_ok());
+        assert_eq!((1, 1), updates(&shared));
+
+        // While the bodies held are at the limit, the connection's window
+        // stays shut until a request goes to its handler.
+        connection.buffered += MAX_BUFFERED_BODIES;
+        assert!(connection.handle_frame(data(1, 0, 1000)).is_ok());
+        assert_eq!((1, 0), updates(&shared));
+        connection.buffered -= MAX_BUFFERED_BODIES;
+        let finished = connection.handle_frame(data(1, frame::END_STREAM, 0));
+        assert_eq!(16_384 + 1000, finished.ok().unwrap().unwrap().1.body.len());
+        assert_eq!((0, 1), updates(&shared));
+
+        // Sending past a window is an error.
+        connection.pending.get_mut(&3).unwrap().window = 10;
+        assert!(connection.handle_frame(data(3, 0, 11)).is_ok());
+        assert!(!connection.pending.contains_key(&3));
+        connection.receive_window = 10;
+        assert!(matches!(
+            connection.handle_frame(data(3, 0, 11)),
+            Err(Fault::Connection(frame::FLOW_CONTROL_ERROR, _))
+        ));
+    }
+
+    #[test]
+    fn answers_streams_that_send_data_after_the_request() {
+        let shared = Shared::new(Vec::new());
+        let mut connection = Connection {
+            shared: &shared,
+            decoder: hpack::Decoder::new(HEADER_TABLE_SIZE),
+            remote_addr: None,
+            pending: HashMap::new(),
+            header_block: None,
+            last_stream_id: 0,
+            receive_window: DEFAULT_WINDOW_SIZE,
+            buffered: 0,
+        };
+        let headers = Frame::new(
+            frame::HEADERS,
+            frame::END_HEADERS | frame::END_STREAM,
+            1,
+            request_headers("/"),
+        );
+        let late_data = || Frame::new(frame::DATA, 0, 1, b"late".to_vec());
+        let sent = |shared: &Shared<Vec<u8>>, kind| {
+            let mut writer = shared.writer.lock().unwrap();
+            let sent = frames(&writer.0);
+            writer.0.clear();
+            sent.iter()
+                .filter(|f| f.kind == kind && f.stream_id == 1)
+                .count()
+        };
+
+        assert!(connection.handle_frame(headers).ok().unwrap().is_some());
+        assert!(connection.handle_frame(late_data()).ok().unwrap().is_none());
+        assert_eq!(0, sent(&shared, frame::RST_STREAM));
+
+        shared.send_response(1, Response::new(200).with_body("ok"));
+        assert_eq!(1, sent(&shared, frame::DATA));
+
+        // Once the response is complete, the stream is closed.
+        assert!(connection.handle_frame(late_data()).ok().unwrap().is_none());
+        assert_eq!(1, sent(&shared, frame::RST_STREAM));
+    }
+
+    #[test]
+    fn rejects_missing_pseudo_headers() {
+        let headers = vec![(":method".to_string(), "GET".to_string())];
+        assert!(build_request(headers, None).is_none());
+    }
+
+    #[test]
+    fn sends_goaway_on_protocol_error() {
+        let mut input = PREFACE.to_vec();
+        Frame::new(frame::DATA, 0, 0, b"oops".to_vec())
+            .write_to(&mut input)
+            .unwrap();
+
+        let handler = |_: &Request| Response::new(200);
+        let mut output = Vec::new();
+        assert!(serve(&input[..], &mut output, &handler, None).is_err());
+
+        let goaway = frames(&output).pop().unwrap();
+        assert_eq!(frame::GOAWAY, goaway.kind);
+        assert_eq!(frame::PROTOCOL_ERROR, frame::read_u32(&goaway.payload[4..]));
+    }
+}
@@ -0,0 +1,190 @@
+//! HTTP/2 frame layout (RFC 9113, section 4).
+
+use std::io::{self, prelude::*};
+
+pub const DATA: u8 = 0x0;
+pub const HEADERS: u8 = 0x1;
+pub const PRIORITY: u8 = 0x2;
+pub const RST_STREAM: u8 = 0x3;
+pub const SETTINGS: u8 = 0x4;
+pub const PUSH_PROMISE: u8 = 0x5;
+pub const PING: u8 = 0x6;
+pub const GOAWAY: u8 = 0x7;
+pub const WINDOW_UPDATE: u8 = 0x8;
+pub const CONTINUATION: u8 = 0x9;
+
+pub const END_STREAM: u8 = 0x1;
+pub const ACK: u8 = 0x1;
+pub const END_HEADERS: u8 = 0x4;
+pub const PADDED: u8 = 0x8;
+pub const PRIORITY_FLAG: u8 = 0x20;
+
+pub const NO_ERROR: u32 = 0x0;
+pub const PROTOCOL_ERROR: u32 = 0x1;
+pub const INTERNAL_ERROR: u32 = 0x2;
+pub const FLOW_CONTROL_ERROR: u32 = 0x3;
+pub const STREAM_CLOSED: u32 = 0x5;
+pub const FRAME_SIZE_ERROR: u32 = 0x6;
+pub const REFUSED_STREAM: u32 = 0x7;
+pub const COMPRESSION_ERROR: u32 = 0x9;
+
+pub const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+pub const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+pub const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/// The frame size every endpoint must accept.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16_384;
+
+pub struct Frame {
+    pub kind: u8,
+    pub flags: u8,
+    pub stream_id: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(kind: u8, flags: u8, stream_id: u32, payload: Vec<u8>) -> Frame {
+        Frame {
+            kind,
+            flags,
+            stream_id,
+            payload,
+        }
+    }
+
+    /// Read the next frame, or `None` if the peer closed the connection
+    /// cleanly between frames.
+    ///
+    /// Frames longer than `max_size` are an `io::ErrorKind::InvalidData`
+    /// error.
+    pub fn read_from<R: Read>(reader: &mut R, max_size: usize) -> io::Result<Option<Frame>> {
+        let mut header = [0; 9];
+
+        match reader.read(&mut header[..1]) {
+            Ok(0) => return Ok(None),
+            Ok(_) => reader.read_exact(&mut header[1..])?,
+            Err(e) => return Err(e),
+        }
+
+        let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        if length > max_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "frame exceeds maximum size",
+            ));
+        }
+
+        let mut payload = vec![0; length];
+        reader.read_exact(&mut payload)?;
+
+        Ok(Some(Frame {
+            kind: header[3],
+            flags: header[4],
+            stream_id: u32::from_be_bytes([header[5], header[6], header[7], header[8]])
+                & 0x7fff_ffff,
+            payload,
+        }))
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let length = (self.payload.len() as u32).to_be_bytes();
+        let stream_id = self.stream_id.to_be_bytes();
+
+        let header = [
+            length[1],
+            length[2],
+            length[3],
+            self.kind,
+            self.flags,
+            stream_id[0],
+            stream_id[1],
+            stream_id[2],
+            stream_id[3],
+        ];
+
+        writer.write_all(&header)?;
+        writer.write_all(&self.payload)
+    }
+
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// The payload with any padding removed, or `None` if the padding
+    /// length is invalid.
+    pub fn unpadded(&self) -> Option<&[u8]> {
+        if !self.has_flag(PADDED) {
+            return Some(&self.payload);
+        }
+
+        let (&pad, rest) = self.payload.split_first()?;
+        rest.len().checked_sub(pad as usize).map(|end| &rest[..end])
+    }
+
+    pub fn settings(settings: &[(u16, u32)]) -> Frame {
+        let mut payload = Vec::with_capacity(settings.len() * 6);
+        for (id, value) in settings {
+            payload.extend_from_slice(&id.to_be_bytes());
+            payload.extend_from_slice(&value.to_be_bytes());
+        }
+
+        Frame::new(SETTINGS, 0, 0, payload)
+    }
+
+    pub fn rst_stream(stream_id: u32, error_code: u32) -> Frame {
+        Frame::new(RST_STREAM, 0, stream_id, error_code.to_be_bytes().to_vec())
+    }
+
+    pub fn window_update(stream_id: u32, increment: u32) -> Frame {
+        Frame::new(
+            WINDOW_UPDATE,
+            0,
+            stream_id,
+            increment.to_be_bytes().to_vec(),
+        )
+    }
+
+    pub fn goaway(last_stream_id: u32, error_code: u32) -> Frame {
+        let mut payload = last_stream_id.to_be_bytes().to_vec();
+        payload.extend_from_slice(&error_code.to_be_bytes());
+
+        Frame::new(GOAWAY, 0, 0, payload)
+    }
+}
+
+/// Read a big-endian `u32` from the start of `bytes`.
+pub fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_round_trip() {
+        let mut out = Vec::new();
+        Frame::window_update(3, 1024).write_to(&mut out).unwrap();
+
+        assert_eq!(13, out.len());
+
+        let frame = Frame::read_from(&mut &out[..], DEFAULT_MAX_FRAME_SIZE)
+            .unwrap()
+            .unwrap();
+        assert_eq!(WINDOW_UPDATE, frame.kind);
+        assert_eq!(3, frame.stream_id);
+        assert_eq!(1024, read_u32(&frame.payload));
+    }
+
+    #[test]
+    fn strips_padding() {
+        let frame = Frame::new(DATA, PADDED, 1, vec![2, b'h', b'i', 0, 0]);
+        assert_eq!(Some(&b"hi"[..]), frame.unpadded());
+
+        let frame = Frame::new(DATA, PADDED, 1, vec![9, b'h', b'i']);
+        assert_eq!(None, frame.unpadded());
+    }
+}
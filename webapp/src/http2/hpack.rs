@@ -0,0 +1,453 @@
+//! HPACK header compression (RFC 7541).
+
+use std::{collections::VecDeque, error::Error, fmt};
+
+use super::huffman;
+
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Per-entry overhead counted towards the dynamic table size, and per field
+/// towards the size of a header list.
+const ENTRY_OVERHEAD: usize = 32;
+
+#[derive(Debug, PartialEq)]
+pub struct DecodeError(&'static str);
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HPACK decoding error: {}", self.0)
+    }
+}
+
+impl Error for DecodeError {}
+
+struct DynamicTable {
+    entries: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl DynamicTable {
+    fn new(max_size: usize) -> DynamicTable {
+        DynamicTable {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size,
+        }
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        let size = name.len() + value.len() + ENTRY_OVERHEAD;
+        self.size += size;
+        self.entries.push_front((name, value));
+        self.evict();
+    }
+
+    fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            match self.entries.pop_back() {
+                Some((name, value)) => self.size -= name.len() + value.len() + ENTRY_OVERHEAD,
+                None => break,
+            }
+        }
+    }
+}
+
+/// Decodes header blocks sent by the peer.
+///
+/// A connection has one decoder, and blocks must be decoded in the order
+/// they arrived since each may change the dynamic table.
+pub struct Decoder {
+    table: DynamicTable,
+    /// The table size we advertised in SETTINGS; the encoder may not go
+    /// above it.
+    max_size_limit: usize,
+    /// The largest decoded header list accepted, counted as in
+    /// SETTINGS_MAX_HEADER_LIST_SIZE.
+    max_list_size: usize,
+}
+
+impl Decoder {
+    pub fn new(max_size: usize) -> Decoder {
+        Decoder {
+            table: DynamicTable::new(max_size),
+            max_size_limit: max_size,
+            max_list_size: usize::MAX,
+        }
+    }
+
+    /// Refuse header lists larger than `size`. A small block can refer to
+    /// the same large table entry many times, so the size of the block
+    /// alone does not bound the size of what it decodes to.
+    pub fn max_list_size(mut self, size: usize) -> Decoder {
+        self.max_list_size = size;
+        self
+    }
+
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<(String, String)>, DecodeError> {
+        let mut headers = Vec::new();
+        let mut input = block;
+        let mut may_resize = true;
+        let mut list_size: usize = 0;
+
+        while let Some(&first) = input.first() {
+            let (name, value) = if first & 0x80 != 0 {
+                // Indexed header field.
+                let index = decode_integer(&mut input, 7)?;
+                self.lookup(index)?
+            } else if first & 0xc0 == 0x40 {
+                // Literal with incremental indexing.
+                let (name, value) = self.decode_literal(&mut input, 6)?;
+                self.table.insert(name.clone(), value.clone());
+                (name, value)
+            } else if first & 0xe0 == 0x20 {
+                // Dynamic table size update, only allowed at the start.
+                if !may_resize {
+                    return Err(DecodeError("late dynamic table size update"));
+                }
+                let size = decode_integer(&mut input, 5)?;
+                if size > self.max_size_limit {
+                    return Err(DecodeError("table size update above limit"));
+                }
+                self.table.set_max_size(size);
+                continue;
+            } else {
+                // Literal without indexing or never indexed.
+                self.decode_literal(&mut input, 4)?
+            };
+
+            list_size = list_size.saturating_add(name.len() + value.len() + ENTRY_OVERHEAD);
+            if list_size > self.max_list_size {
+                return Err(DecodeError("header list too large"));
+            }
+            headers.push((name, value));
+            may_resize = false;
+        }
+
+        Ok(headers)
+    }
+
+    fn lookup(&self, index: usize) -> Result<(String, String), DecodeError> {
+        if index == 0 {
+            return Err(DecodeError("index zero"));
+        }
+
+        if index <= STATIC_TABLE.len() {
+            let (name, value) = STATIC_TABLE[index - 1];
+            return Ok((name.to_string(), value.to_string()));
+        }
+
+        self.table
+            .entries
+            .get(index - STATIC_TABLE.len() - 1)
+            .cloned()
+            .ok_or(DecodeError("index out of range"))
+    }
+
+    fn decode_literal(
+        &self,
+        input: &mut &[u8],
+        prefix: u8,
+    ) -> Result<(String, String), DecodeError> {
+        let index = decode_integer(input, prefix)?;
+        let name = if index == 0 {
+            decode_string(input)?
+        } else {
+            self.lookup(index)?.0
+        };
+        let value = decode_string(input)?;
+
+        Ok((name, value))
+    }
+}
+
+/// Encodes header blocks sent to the peer.
+///
+/// The encoder never inserts into the dynamic table, so it needs no state
+/// and is unaffected by the peer's table size setting. Fields are sent as
+/// static table references where possible, otherwise as literals with
+/// Huffman coding when that is shorter.
+pub struct Encoder;
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder
+    }
+
+    pub fn encode(&mut self, headers: &[(String, String)]) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        for (name, value) in headers {
+            let exact = STATIC_TABLE
+                .iter()
+                .position(|(n, v)| n == name && v == value && !v.is_empty());
+
+            if let Some(index) = exact {
+                encode_integer(&mut out, 0x80, 7, index + 1);
+                continue;
+            }
+
+            let sensitive = name == "authorization" || name == "cookie" || name == "set-cookie";
+            let flags = if sensitive { 0x10 } else { 0x00 };
+
+            match STATIC_TABLE.iter().position(|(n, _)| n == name) {
+                Some(index) => encode_integer(&mut out, flags, 4, index + 1),
+                None => {
+                    out.push(flags);
+                    encode_string(&mut out, name.as_bytes());
+                }
+            }
+            encode_string(&mut out, value.as_bytes());
+        }
+
+        out
+    }
+}
+
+impl Default for Encoder {
+    fn default() -> Encoder {
+        Encoder::new()
+    }
+}
+
+fn decode_integer(input: &mut &[u8], prefix: u8) -> Result<usize, DecodeError> {
+    let mask = (1u16 << prefix) as usize - 1;
+    let (&first, rest) = input
+        .split_first()
+        .ok_or(DecodeError("truncated integer"))?;
+    *input = rest;
+
+    let mut value = first as usize & mask;
+    if value < mask {
+        return Ok(value);
+    }
+
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = input
+            .split_first()
+            .ok_or(DecodeError("truncated integer"))?;
+        *input = rest;
+
+        if shift > 28 {
+            return Err(DecodeError("integer overflow"));
+        }
+        value += ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn encode_integer(out: &mut Vec<u8>, flags: u8, prefix: u8, value: usize) {
+    let mask = (1usize << prefix) - 1;
+
+    if value < mask {
+        out.push(flags | value as u8);
+        return;
+    }
+
+    out.push(flags | mask as u8);
+    let mut value = value - mask;
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn decode_string(input: &mut &[u8]) -> Result<String, DecodeError> {
+    let huffman = input.first().ok_or(DecodeError("truncated string"))? & 0x80 != 0;
+    let length = decode_integer(input, 7)?;
+
+    if length > input.len() {
+        return Err(DecodeError("truncated string"));
+    }
+    let (data, rest) = input.split_at(length);
+    *input = rest;
+
+    let bytes = if huffman {
+        huffman::decode(data).ok_or(DecodeError("invalid Huffman code"))?
+    } else {
+        data.to_vec()
+    };
+
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn encode_string(out: &mut Vec<u8>, data: &[u8]) {
+    let encoded_len = huffman::encoded_len(data);
+
+    if encoded_len < data.len() {
+        encode_integer(out, 0x80, 7, encoded_len);
+        huffman::encode(data, out);
+    } else {
+        encode_integer(out, 0x00, 7, data.len());
+        out.extend_from_slice(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn pairs(headers: &[(&str, &str)]) -> Vec<(String, String)> {
+        headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_rfc_request_examples() {
+        // RFC 7541, C.4.1 and C.4.2: requests with Huffman coding.
+        let mut decoder = Decoder::new(4096);
+
+        assert_eq!(
+            Ok(pairs(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ])),
+            decoder.decode(&hex("828684418cf1e3c2e5f23a6ba0ab90f4ff"))
+        );
+
+        assert_eq!(
+            Ok(pairs(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("cache-control", "no-cache"),
+            ])),
+            decoder.decode(&hex("828684be5886a8eb10649cbf"))
+        );
+    }
+
+    #[test]
+    fn encoded_headers_round_trip() {
+        let headers = pairs(&[
+            (":status", "200"),
+            (":status", "201"),
+            ("content-type", "text/html; charset=utf-8"),
+            ("x-custom", "value"),
+            ("set-cookie", "id=1"),
+        ]);
+
+        let block = Encoder::new().encode(&headers);
+        assert_eq!(Ok(headers), Decoder::new(4096).decode(&block));
+    }
+
+    #[test]
+    fn large_integers_round_trip() {
+        let mut out = Vec::new();
+        encode_integer(&mut out, 0, 5, 1337);
+        assert_eq!(vec![0x1f, 0x9a, 0x0a], out);
+        assert_eq!(Ok(1337), decode_integer(&mut &out[..], 5));
+    }
+
+    #[test]
+    fn rejects_table_size_above_limit() {
+        assert!(Decoder::new(100).decode(&[0x3f, 0xe1, 0x1f]).is_err());
+    }
+
+    #[test]
+    fn limits_decoded_header_list_size() {
+        // One 1000-byte value added to the table, then referred to again
+        // and again with a single byte each.
+        let mut block = vec![0x40];
+        encode_string(&mut block, b"x");
+        encode_string(&mut block, &[b'a'; 1000]);
+        block.extend(std::iter::repeat_n(0xbe, 100));
+
+        let mut decoder = Decoder::new(4096).max_list_size(16 * 1024);
+        assert_eq!(
+            Err(DecodeError("header list too large")),
+            decoder.decode(&block)
+        );
+        assert_eq!(
+            11,
+            Decoder::new(4096)
+                .decode(&block[..block.len() - 90])
+                .unwrap()
+                .len()
+        );
+    }
+}
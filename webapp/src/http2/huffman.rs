@@ -0,0 +1,167 @@
+//! The static Huffman code from RFC 7541, Appendix B.
+//!
+//! The code is canonical, so it is fully described by the code length of
+//! each symbol; the codes themselves are rebuilt from the lengths.
+
+use std::sync::OnceLock;
+
+/// Code length in bits for each byte value, followed by EOS (256).
+const CODE_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 30, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, 5, 5,
+    5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, 13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, 15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6,
+    6, 5, 6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, 20, 22, 20, 20, 22, 22, 22, 23, 22,
+    23, 23, 23, 23, 23, 24, 23, 24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, 22,
+    21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, 21, 21, 22, 21, 23, 22, 23, 23, 20,
+    22, 22, 22, 23, 22, 22, 23, 26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, 19,
+    21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, 20, 24, 20, 21, 22, 21, 21, 23, 22,
+    22, 25, 25, 24, 24, 26, 23, 26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, 30,
+];
+
+const EOS: usize = 256;
+
+struct Table {
+    /// `(code, length)` for every symbol.
+    codes: Vec<(u32, u8)>,
+    /// Symbols ordered by `(length, symbol)`, as codes are assigned.
+    sorted: Vec<u16>,
+    /// First code, index into `sorted` and number of codes of each length.
+    first_code: [u32; 31],
+    first_index: [usize; 31],
+    count: [u32; 31],
+}
+
+fn table() -> &'static Table {
+    static TABLE: OnceLock<Table> = OnceLock::new();
+
+    TABLE.get_or_init(|| {
+        let mut sorted: Vec<u16> = (0..257).collect();
+        sorted.sort_by_key(|&symbol| (CODE_LENGTHS[symbol as usize], symbol));
+
+        let mut table = Table {
+            codes: vec![(0, 0); 257],
+            sorted,
+            first_code: [0; 31],
+            first_index: [0; 31],
+            count: [0; 31],
+        };
+
+        let mut code = 0u32;
+        let mut previous = 0u8;
+        for (index, &symbol) in table.sorted.iter().enumerate() {
+            let length = CODE_LENGTHS[symbol as usize];
+            if index > 0 {
+                code = (code + 1) << (length - previous);
+            }
+            if table.count[length as usize] == 0 {
+                table.first_code[length as usize] = code;
+                table.first_index[length as usize] = index;
+            }
+            table.count[length as usize] += 1;
+            table.codes[symbol as usize] = (code, length);
+            previous = length;
+        }
+
+        table
+    })
+}
+
+/// The length in bytes of `data` once Huffman encoded.
+pub fn encoded_len(data: &[u8]) -> usize {
+    let table = table();
+    let bits: usize = data
+        .iter()
+        .map(|&b| table.codes[b as usize].1 as usize)
+        .sum();
+    bits.div_ceil(8)
+}
+
+pub fn encode(data: &[u8], out: &mut Vec<u8>) {
+    let table = table();
+    let mut acc = 0u64;
+    let mut bits = 0u32;
+
+    for &b in data {
+        let (code, length) = table.codes[b as usize];
+        acc = (acc << length) | code as u64;
+        bits += length as u32;
+
+        while bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+
+    // Pad with the most significant bits of EOS, which are all ones.
+    if bits > 0 {
+        out.push(((acc << (8 - bits)) as u8) | (0xff >> bits));
+    }
+}
+
+/// Decode a Huffman encoded string, or `None` if it is invalid.
+pub fn decode(data: &[u8]) -> Option<Vec<u8>> {
+    let table = table();
+    let mut out = Vec::with_capacity(data.len() * 8 / 5);
+    let mut code = 0u32;
+    let mut length = 0usize;
+
+    for &byte in data {
+        for shift in (0..8).rev() {
+            code = (code << 1) | ((byte >> shift) & 1) as u32;
+            length += 1;
+
+            if length > 30 {
+                return None;
+            }
+
+            let offset = code.wrapping_sub(table.first_code[length]);
+            if table.count[length] > 0 && offset < table.count[length] {
+                let symbol = table.sorted[table.first_index[length] + offset as usize] as usize;
+                if symbol == EOS {
+                    return None;
+                }
+                out.push(symbol as u8);
+                code = 0;
+                length = 0;
+            }
+        }
+    }
+
+    // Whatever is left must be a short run of padding ones.
+    if length > 7 || code != (1 << length) - 1 {
+        return None;
+    }
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_rfc_example() {
+        let encoded = [
+            0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab, 0x90, 0xf4, 0xff,
+        ];
+        assert_eq!(Some(b"www.example.com".to_vec()), decode(&encoded));
+    }
+
+    #[test]
+    fn round_trips_every_byte() {
+        let data: Vec<u8> = (0..=255).collect();
+        let mut encoded = Vec::new();
+        encode(&data, &mut encoded);
+
+        assert_eq!(encoded_len(&data), encoded.len());
+        assert_eq!(Some(data), decode(&encoded));
+    }
+
+    #[test]
+    fn rejects_bad_padding() {
+        // "a" is 00011; padding it with zeros is invalid.
+        assert_eq!(None, decode(&[0b0001_1000]));
+        assert_eq!(Some(b"a".to_vec()), decode(&[0b0001_1111]));
+    }
+}
@@ -1,11 +1,53 @@
+pub mod cgi;
+pub mod cookie;
+pub mod crypto;
+pub mod http;
+pub mod http2;
+pub mod metrics;
+pub mod multipart;
+pub mod router;
+pub mod server;
+pub mod session;
+pub mod temp;
+pub mod upload;
+
 use std::{
-    sync::{mpsc, Arc, Mutex},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
     thread::{self, JoinHandle},
 };
 
 pub struct ThreadPool {
     workers: Vec<Worker>,
     sender: mpsc::Sender<Message>,
+    stats: Arc<PoolStats>,
+}
+
+/// Live counters of a `ThreadPool`, shareable with other threads.
+pub struct PoolStats {
+    size: usize,
+    queued: AtomicUsize,
+    busy: AtomicUsize,
+}
+
+impl PoolStats {
+    /// The number of worker threads.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The number of jobs waiting for a free worker.
+    pub fn queue_depth(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// The number of workers currently running a job.
+    pub fn busy_workers(&self) -> usize {
+        self.busy.load(Ordering::Relaxed)
+    }
 }
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
@@ -30,13 +72,27 @@ impl ThreadPool {
 
         let receiver = Arc::new(Mutex::new(receiver));
 
+        let stats = Arc::new(PoolStats {
+            size,
+            queued: AtomicUsize::new(0),
+            busy: AtomicUsize::new(0),
+        });
+
         let mut workers = Vec::with_capacity(size);
 
         for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+            workers.push(Worker::new(id, Arc::clone(&receiver), Arc::clone(&stats)));
         }
 
-        ThreadPool { workers, sender }
+        ThreadPool {
+            workers,
+            sender,
+            stats,
+        }
+    }
+
+    pub fn stats(&self) -> Arc<PoolStats> {
+        Arc::clone(&self.stats)
     }
 
     pub fn execute<F>(&self, f: F)
@@ -44,6 +100,7 @@ impl ThreadPool {
         F: FnOnce() + Send + 'static,
     {
         let job = Box::new(f);
+        self.stats.queued.fetch_add(1, Ordering::Relaxed);
         self.sender.send(Message::NewJob(job)).unwrap();
     }
 }
@@ -74,16 +131,23 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
+    fn new(
+        id: usize,
+        receiver: Arc<Mutex<mpsc::Receiver<Message>>>,
+        stats: Arc<PoolStats>,
+    ) -> Worker {
         let thread = thread::spawn(move || loop {
             let message = receiver.lock().unwrap().recv().unwrap();
 
-            println!("Worker {id} got a job; executing.");
-
             match message {
                 Message::NewJob(job) => {
                     println!("Worker {id} got a job; executing.");
-                    job();
+                    stats.queued.fetch_sub(1, Ordering::Relaxed);
+                    stats.busy.fetch_add(1, Ordering::Relaxed);
+                    // A panicking job must neither leave the worker counted
+                    // as busy nor take the worker down with it.
+                    let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                    stats.busy.fetch_sub(1, Ordering::Relaxed);
                 }
                 Message::Terminate => {
                     println!("Worker {id} is terminating.");
@@ -98,3 +162,20 @@ impl Worker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn survives_panicking_jobs() {
+        let pool = ThreadPool::new(1);
+        let stats = pool.stats();
+        let (sender, receiver) = mpsc::channel();
+
+        pool.execute(|| panic!("job failed"));
+        pool.execute(move || sender.send(stats.busy_workers()).unwrap());
+
+        assert_eq!(1, receiver.recv().unwrap());
+    }
+}
@@ -1,9 +1,16 @@
+use std::env;
 use std::fs;
-use std::io::prelude::*;
 use std::net::TcpListener;
-use std::net::TcpStream;
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
+use webapp::cgi::CgiHandler;
+use webapp::http::{Request, Response};
+use webapp::metrics::{Instrumented, Metrics};
+use webapp::router::Router;
+use webapp::server::handle_connection;
+use webapp::session::{MemoryStore, Sessions};
+use webapp::upload::UploadHandler;
 use webapp::ThreadPool;
 
 fn main() {
@@ -11,44 +18,59 @@ fn main() {
 
     let pool = ThreadPool::new(4);
 
+    let metrics = Arc::new(Metrics::new().with_pool(pool.stats()));
+    let metrics_path = env::var("METRICS_PATH").unwrap_or_else(|_| "/metrics".to_string());
+
+    let handler = Arc::new(Sessions::new(
+        Instrumented::new(routes(), Arc::clone(&metrics), &metrics_path),
+        MemoryStore::new(Duration::from_secs(30 * 60)),
+    ));
+
     for stream in listener.incoming().take(2) {
         let stream = stream.unwrap();
+        let handler = Arc::clone(&handler);
+        let connection = metrics.track_connection();
 
-        pool.execute(|| {
-            handle_connection(stream);
+        pool.execute(move || {
+            handle_connection(stream, &*handler);
+            drop(connection);
         });
     }
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    let mut buffer = [0; 1024];
-    stream.read(&mut buffer).unwrap();
-
-    let get = b"GET / HTTP/1.1\r\n";
+fn routes() -> Router {
+    Router::new()
+        .get("/", |_: &Request| serve_file(200, "index.html"))
+        .get("/sleep", |_: &Request| {
+            thread::sleep(Duration::from_secs(5));
+            serve_file(200, "index.html")
+        })
+        .prefix(
+            "/cgi-bin/hello",
+            CgiHandler::new("/cgi-bin/hello", "cgi-bin/hello.sh").timeout(Duration::from_secs(10)),
+        )
+        .get("/visits", |request: &Request| {
+            let visits: u32 = request
+                .session
+                .get("visits")
+                .and_then(|visits| visits.parse().ok())
+                .unwrap_or(0)
+                + 1;
+            request.session.insert("visits", &visits.to_string());
 
-    let sleep = b"GET /sleep HTTP/1.1\r\n";
-
-    let (status_line, filename) = if buffer.starts_with(get) {
-        ("HTTP/1.1 200 OK", "index.html")
-    } else if buffer.starts_with(sleep) {
-        thread::sleep(Duration::from_secs(5));
-        ("HTTP/1.1 200 OK", "index.html")
-    } else {
-        ("HTTP/1.1 404 NOT FOUND", "404.html")
-    };
+            Response::new(200)
+                .with_header("Content-Type", "text/plain; charset=utf-8")
+                .with_body(format!("You have been here {} times.\n", visits))
+        })
+        .get("/upload", |_: &Request| serve_file(200, "upload.html"))
+        .route("POST", "/upload", UploadHandler::new("uploads"))
+        .fallback(|_: &Request| serve_file(404, "404.html"))
+}
 
+fn serve_file(status: u16, filename: &str) -> Response {
     let contents = fs::read_to_string(filename).unwrap();
 
-    let res = format!(
-        "{}\r\nContent-Length: {}\r\n\r\n{}",
-        status_line,
-        contents.len(),
-        contents
-    );
-    stream.write(res.as_bytes()).unwrap();
-    stream.flush().unwrap();
-
-    if buffer.starts_with(get) {
-    } else {
-    }
+    Response::new(status)
+        .with_header("Content-Type", "text/html; charset=utf-8")
+        .with_body(contents)
 }
@@ -0,0 +1,294 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::http::{Handler, Request, Response};
+use crate::router::Router;
+use crate::PoolStats;
+
+/// Upper bounds, in seconds, of the request latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+    /// Non-cumulative counts per bucket, plus one for `+Inf`.
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+
+        self.buckets[bucket] += 1;
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    requests: BTreeMap<(String, u16), u64>,
+    latencies: BTreeMap<String, Histogram>,
+}
+
+/// Server metrics, rendered in the Prometheus text exposition format.
+///
+/// Share one `Metrics` between the accept loop, which tracks connections,
+/// and an `Instrumented` router, which records requests.
+pub struct Metrics {
+    registry: Mutex<Registry>,
+    open_connections: AtomicUsize,
+    pool: Option<Arc<PoolStats>>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            registry: Mutex::new(Registry::default()),
+            open_connections: AtomicUsize::new(0),
+            pool: None,
+        }
+    }
+
+    /// Also report the queue depth and busy workers of a `ThreadPool`.
+    pub fn with_pool(mut self, stats: Arc<PoolStats>) -> Metrics {
+        self.pool = Some(stats);
+        self
+    }
+
+    pub fn observe_request(&self, route: &str, status: u16, elapsed: Duration) {
+        let mut registry = self.registry.lock().unwrap();
+
+        *registry
+            .requests
+            .entry((route.to_string(), status))
+            .or_insert(0) += 1;
+        registry
+            .latencies
+            .entry(route.to_string())
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Count a connection as open until the returned guard is dropped.
+    pub fn track_connection(self: &Arc<Self>) -> ConnectionGuard {
+        self.open_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard {
+            metrics: Arc::clone(self),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str("# HELP http_requests_total Requests handled, by route and status.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((route, status), count) in &registry.requests {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{route=\"{}\",status=\"{}\"}} {}",
+                escape(route),
+                status,
+                count
+            );
+        }
+
+        out.push_str("# HELP http_request_duration_seconds Request latency, by route.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for (route, histogram) in &registry.latencies {
+            let route = escape(route);
+            let mut cumulative = 0;
+
+            for (i, count) in histogram.buckets.iter().enumerate() {
+                cumulative += count;
+                let bound = match LATENCY_BUCKETS.get(i) {
+                    Some(bound) => bound.to_string(),
+                    None => "+Inf".to_string(),
+                };
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                    route, bound, cumulative
+                );
+            }
+
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{route=\"{}\"}} {}",
+                route, histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{route=\"{}\"}} {}",
+                route, histogram.count
+            );
+        }
+
+        gauge(
+            &mut out,
+            "http_open_connections",
+            "Client connections currently open.",
+            self.open_connections.load(Ordering::Relaxed),
+        );
+
+        if let Some(pool) = &self.pool {
+            gauge(
+                &mut out,
+                "threadpool_workers",
+                "Worker threads in the pool.",
+                pool.size(),
+            );
+            gauge(
+                &mut out,
+                "threadpool_queue_depth",
+                "Jobs waiting for a free worker.",
+                pool.queue_depth(),
+            );
+            gauge(
+                &mut out,
+                "threadpool_busy_workers",
+                "Workers currently running a job.",
+                pool.busy_workers(),
+            );
+        }
+
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics::new()
+    }
+}
+
+pub struct ConnectionGuard {
+    metrics: Arc<Metrics>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.metrics
+            .open_connections
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A router that records every request in `Metrics` and serves the
+/// metrics themselves on a configurable path.
+pub struct Instrumented {
+    router: Router,
+    metrics: Arc<Metrics>,
+    path: String,
+}
+
+impl Instrumented {
+    pub fn new(router: Router, metrics: Arc<Metrics>, path: &str) -> Instrumented {
+        Instrumented {
+            router,
+            metrics,
+            path: path.to_string(),
+        }
+    }
+}
+
+impl Handler for Instrumented {
+    fn handle(&self, request: &Request) -> Response {
+        let started = Instant::now();
+
+        let (route, response) = if request.method == "GET" && request.path == self.path {
+            let response = Response::new(200)
+                .with_header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+                .with_body(self.metrics.render());
+            (self.path.as_str(), response)
+        } else {
+            (self.router.route_name(request), self.router.handle(request))
+        };
+
+        self.metrics
+            .observe_request(route, response.status, started.elapsed());
+        response
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: usize) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Escape a label value for the text exposition format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_counters_and_histograms() {
+        let metrics = Metrics::new();
+        metrics.observe_request("/", 200, Duration::from_millis(3));
+        metrics.observe_request("/", 200, Duration::from_millis(30));
+        metrics.observe_request("fallback", 404, Duration::from_millis(1));
+
+        let text = metrics.render();
+
+        assert!(text.contains("http_requests_total{route=\"/\",status=\"200\"} 2\n"));
+        assert!(text.contains("http_requests_total{route=\"fallback\",status=\"404\"} 1\n"));
+        assert!(text.contains("http_request_duration_seconds_bucket{route=\"/\",le=\"0.005\"} 1\n"));
+        assert!(text.contains("http_request_duration_seconds_bucket{route=\"/\",le=\"0.05\"} 2\n"));
+        assert!(text.contains("http_request_duration_seconds_bucket{route=\"/\",le=\"+Inf\"} 2\n"));
+        assert!(text.contains("http_request_duration_seconds_count{route=\"/\"} 2\n"));
+    }
+
+    #[test]
+    fn tracks_open_connections() {
+        let metrics = Arc::new(Metrics::new());
+
+        let guard = metrics.track_connection();
+        assert!(metrics.render().contains("http_open_connections 1\n"));
+
+        drop(guard);
+        assert!(metrics.render().contains("http_open_connections 0\n"));
+    }
+
+    #[test]
+    fn serves_metrics_on_configured_path() {
+        let router = Router::new().get("/", |_: &Request| Response::new(200));
+        let handler = Instrumented::new(router, Arc::new(Metrics::new()), "/stats");
+
+        let request = |path: &str| Request::new("GET", path);
+
+        handler.handle(&request("/"));
+        handler.handle(&request("/nope"));
+        let response = handler.handle(&request("/stats"));
+        let text = String::from_utf8(response.body).unwrap();
+
+        assert!(text.contains("http_requests_total{route=\"/\",status=\"200\"} 1\n"));
+        assert!(text.contains("http_requests_total{route=\"fallback\",status=\"404\"} 1\n"));
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!("a\\\"b\\\\c\\n", escape("a\"b\\c\n"));
+    }
+}
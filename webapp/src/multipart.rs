@@ -0,0 +1,471 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{self, prelude::*},
+};
+
+use crate::temp::TempFile;
+
+/// Bounds applied while parsing a `multipart/form-data` body.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// Largest size of a single part's content.
+    pub max_part_size: u64,
+    /// Largest combined size of all parts' content.
+    pub max_total_size: u64,
+    /// Largest number of parts in one body.
+    pub max_parts: usize,
+    /// Largest size of a part's header block.
+    pub max_header_size: usize,
+    /// Parts larger than this are spilled to a temp file.
+    pub memory_threshold: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_part_size: 10 * 1024 * 1024,
+            max_total_size: 50 * 1024 * 1024,
+            max_parts: 100,
+            max_header_size: 8 * 1024,
+            memory_threshold: 64 * 1024,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MultipartError {
+    Io(io::Error),
+    Malformed(&'static str),
+    PartTooLarge,
+    BodyTooLarge,
+    TooManyParts,
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MultipartError::Io(e) => write!(f, "I/O error: {}", e),
+            MultipartError::Malformed(reason) => write!(f, "malformed multipart body: {}", reason),
+            MultipartError::PartTooLarge => write!(f, "part exceeds the size limit"),
+            MultipartError::BodyTooLarge => write!(f, "body exceeds the size limit"),
+            MultipartError::TooManyParts => write!(f, "too many parts"),
+        }
+    }
+}
+
+impl Error for MultipartError {}
+
+impl From<io::Error> for MultipartError {
+    fn from(e: io::Error) -> MultipartError {
+        MultipartError::Io(e)
+    }
+}
+
+pub enum PartData {
+    Memory(Vec<u8>),
+    File(TempFile),
+}
+
+pub struct Part {
+    pub headers: Vec<(String, String)>,
+    /// The `name` parameter of the part's `Content-Disposition`.
+    pub name: Option<String>,
+    /// The `filename` parameter as sent by the client. Never use it as a
+    /// path without sanitizing it first.
+    pub filename: Option<String>,
+    pub size: u64,
+    pub data: PartData,
+}
+
+impl Part {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.header("Content-Type")
+    }
+
+    pub fn reader(&self) -> io::Result<Box<dyn Read + '_>> {
+        match &self.data {
+            PartData::Memory(bytes) => Ok(Box::new(&bytes[..])),
+            PartData::File(temp) => Ok(Box::new(temp.reopen()?)),
+        }
+    }
+}
+
+/// Extract the boundary from a `multipart/form-data` content type.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let media_type = params.next()?.trim();
+
+    if !media_type.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+
+    parse_params(&params.collect::<Vec<_>>().join(";"))
+        .into_iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value)
+        .filter(|boundary| !boundary.is_empty() && boundary.len() <= 70)
+}
+
+/// A streaming `multipart/form-data` parser.
+///
+/// Parts are read one at a time with `next_part`. Only a small window of
+/// the input is buffered; part contents above the memory threshold go
+/// straight to a temp file.
+pub struct Multipart<R> {
+    reader: R,
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    limits: Limits,
+    started: bool,
+    done: bool,
+    parts: usize,
+    total_size: u64,
+}
+
+impl<R: Read> Multipart<R> {
+    pub fn new(reader: R, boundary: &str, limits: Limits) -> Multipart<R> {
+        Multipart {
+            reader,
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            // Every delimiter is preceded by a CRLF except the very first
+            // one, so pretend the body starts with one.
+            buffer: b"\r\n".to_vec(),
+            limits,
+            started: false,
+            done: false,
+            parts: 0,
+            total_size: 0,
+        }
+    }
+
+    /// Read the next part, or `None` once the closing boundary is reached.
+    pub fn next_part(&mut self) -> Result<Option<Part>, MultipartError> {
+        if self.done {
+            return Ok(None);
+        }
+
+        if !self.started {
+            self.skip_preamble()?;
+            self.started = true;
+        }
+
+        while self.buffer.len() < 2 {
+            if !self.fill()? {
+                return Err(MultipartError::Malformed("unexpected end of body"));
+            }
+        }
+
+        if self.buffer.starts_with(b"--") {
+            self.done = true;
+            return Ok(None);
+        }
+
+        // The rest of the boundary line may only hold transport padding.
+        let line = self.read_until(b"\r\n", self.limits.max_header_size)?;
+        if !line.iter().all(|b| *b == b' ' || *b == b'\t') {
+            return Err(MultipartError::Malformed("garbage after boundary"));
+        }
+
+        self.parts += 1;
+        if self.parts > self.limits.max_parts {
+            return Err(MultipartError::TooManyParts);
+        }
+
+        let headers = self.read_headers()?;
+        let mut part = Part {
+            name: None,
+            filename: None,
+            size: 0,
+            data: PartData::Memory(Vec::new()),
+            headers,
+        };
+
+        if let Some(disposition) = part.header("Content-Disposition") {
+            for (name, value) in parse_params(disposition.split_once(';').map_or("", |(_, p)| p)) {
+                if name.eq_ignore_ascii_case("name") {
+                    part.name = Some(value);
+                } else if name.eq_ignore_ascii_case("filename") {
+                    part.filename = Some(value);
+                }
+            }
+        }
+
+        self.read_content(&mut part)?;
+        Ok(Some(part))
+    }
+
+    fn skip_preamble(&mut self) -> Result<(), MultipartError> {
+        loop {
+            if let Some(i) = find(&self.buffer, &self.delimiter) {
+                self.buffer.drain(..i + self.delimiter.len());
+                return Ok(());
+            }
+
+            let keep = self.delimiter.len() - 1;
+            if self.buffer.len() > keep {
+                self.buffer.drain(..self.buffer.len() - keep);
+            }
+
+            if !self.fill()? {
+                return Err(MultipartError::Malformed("missing opening boundary"));
+            }
+        }
+    }
+
+    fn read_headers(&mut self) -> Result<Vec<(String, String)>, MultipartError> {
+        while self.buffer.len() < 2 {
+            if !self.fill()? {
+                return Err(MultipartError::Malformed("unexpected end of body"));
+            }
+        }
+
+        if self.buffer.starts_with(b"\r\n") {
+            self.buffer.drain(..2);
+            return Ok(Vec::new());
+        }
+
+        let block = self.read_until(b"\r\n\r\n", self.limits.max_header_size)?;
+        let block =
+            String::from_utf8(block).map_err(|_| MultipartError::Malformed("non UTF-8 header"))?;
+
+        block
+            .split("\r\n")
+            .map(|line| match line.split_once(':') {
+                Some((name, value)) => Ok((name.trim().to_string(), value.trim().to_string())),
+                None => Err(MultipartError::Malformed("malformed part header")),
+            })
+            .collect()
+    }
+
+    fn read_content(&mut self, part: &mut Part) -> Result<(), MultipartError> {
+        loop {
+            let (end, consumed) = match find(&self.buffer, &self.delimiter) {
+                Some(i) => (i, i + self.delimiter.len()),
+                None => {
+                    // Hold back enough bytes to recognize a delimiter that
+                    // straddles the next read.
+                    let end = self.buffer.len().saturating_sub(self.delimiter.len() - 1);
+                    (end, end)
+                }
+            };
+
+            let found = end != consumed;
+            self.write_content(part, end)?;
+            self.buffer.drain(..consumed);
+
+            if found {
+                return Ok(());
+            }
+
+            if !self.fill()? {
+                return Err(MultipartError::Malformed("unexpected end of body"));
+            }
+        }
+    }
+
+    fn write_content(&mut self, part: &mut Part, len: usize) -> Result<(), MultipartError> {
+        let chunk = &self.buffer[..len];
+
+        part.size += len as u64;
+        self.total_size += len as u64;
+        if part.size > self.limits.max_part_size {
+            return Err(MultipartError::PartTooLarge);
+        }
+        if self.total_size > self.limits.max_total_size {
+            return Err(MultipartError::BodyTooLarge);
+        }
+
+        if let PartData::Memory(bytes) = &mut part.data {
+            if bytes.len() + chunk.len() <= self.limits.memory_threshold {
+                bytes.extend_from_slice(chunk);
+                return Ok(());
+            }
+
+            let mut temp = TempFile::new()?;
+            temp.file().write_all(bytes)?;
+            part.data = PartData::File(temp);
+        }
+
+        if let PartData::File(temp) = &mut part.data {
+            temp.file().write_all(chunk)?;
+        }
+
+        Ok(())
+    }
+
+    /// Consume input up to and including `terminator`, returning what came
+    /// before it.
+    fn read_until(&mut self, terminator: &[u8], limit: usize) -> Result<Vec<u8>, MultipartError> {
+        loop {
+            if let Some(i) = find(&self.buffer, terminator) {
+                let line = self.buffer[..i].to_vec();
+                self.buffer.drain(..i + terminator.len());
+                return Ok(line);
+            }
+
+            if self.buffer.len() > limit {
+                return Err(MultipartError::Malformed("part header too large"));
+            }
+
+            if !self.fill()? {
+                return Err(MultipartError::Malformed("unexpected end of body"));
+            }
+        }
+    }
+
+    fn fill(&mut self) -> io::Result<bool> {
+        let mut chunk = [0; 8192];
+
+        loop {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(n) => {
+                    self.buffer.extend_from_slice(&chunk[..n]);
+                    return Ok(true);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Parse `; name=value; name="quoted value"` style header parameters.
+fn parse_params(params: &str) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+    let mut chars = params.chars().peekable();
+
+    loop {
+        while matches!(chars.peek(), Some(c) if *c == ';' || c.is_whitespace()) {
+            chars.next();
+        }
+
+        let name: String = chars.by_ref().take_while(|c| *c != '=').collect();
+        if name.is_empty() {
+            return result;
+        }
+
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    c => value.push(c),
+                }
+            }
+            while matches!(chars.peek(), Some(c) if *c != ';') {
+                chars.next();
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ';') {
+                value.push(c);
+            }
+        }
+
+        result.push((name.trim().to_string(), value.trim().to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &str = "preamble\r\n\
+--XyZ\r\n\
+Content-Disposition: form-data; name=\"title\"\r\n\
+\r\n\
+Hello\r\n\
+--XyZ\r\n\
+Content-Disposition: form-data; name=\"file\"; filename=\"notes; v2.txt\"\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+line one\r\nline two\r\n\
+--XyZ--\r\n";
+
+    fn content(part: &Part) -> String {
+        let mut content = String::new();
+        part.reader().unwrap().read_to_string(&mut content).unwrap();
+        content
+    }
+
+    #[test]
+    fn extracts_boundary() {
+        assert_eq!(
+            Some("XyZ".to_string()),
+            boundary("multipart/form-data; boundary=\"XyZ\"")
+        );
+        assert_eq!(None, boundary("text/plain; boundary=XyZ"));
+    }
+
+    #[test]
+    fn parses_parts() {
+        let mut multipart = Multipart::new(BODY.as_bytes(), "XyZ", Limits::default());
+
+        let title = multipart.next_part().unwrap().unwrap();
+        assert_eq!(Some("title"), title.name.as_deref());
+        assert_eq!(None, title.filename);
+        assert_eq!("Hello", content(&title));
+
+        let file = multipart.next_part().unwrap().unwrap();
+        assert_eq!(Some("notes; v2.txt"), file.filename.as_deref());
+        assert_eq!(Some("text/plain"), file.content_type());
+        assert_eq!("line one\r\nline two", content(&file));
+
+        assert!(multipart.next_part().unwrap().is_none());
+    }
+
+    #[test]
+    fn spills_large_parts_to_disk() {
+        let limits = Limits {
+            memory_threshold: 4,
+            ..Limits::default()
+        };
+        let mut multipart = Multipart::new(BODY.as_bytes(), "XyZ", limits);
+
+        multipart.next_part().unwrap();
+        let file = multipart.next_part().unwrap().unwrap();
+
+        assert!(matches!(file.data, PartData::File(_)));
+        assert_eq!("line one\r\nline two", content(&file));
+    }
+
+    #[test]
+    fn enforces_part_size_limit() {
+        let limits = Limits {
+            max_part_size: 8,
+            ..Limits::default()
+        };
+        let mut multipart = Multipart::new(BODY.as_bytes(), "XyZ", limits);
+
+        multipart.next_part().unwrap();
+        assert!(matches!(
+            multipart.next_part(),
+            Err(MultipartError::PartTooLarge)
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_body() {
+        let truncated = &BODY[..BODY.len() - 20];
+        let mut multipart = Multipart::new(truncated.as_bytes(), "XyZ", Limits::default());
+
+        multipart.next_part().unwrap();
+        assert!(multipart.next_part().is_err());
+    }
+}
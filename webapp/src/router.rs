@@ -0,0 +1,101 @@
+use crate::http::{Handler, Request, Response};
+
+enum Pattern {
+    Exact(String, String),
+    Prefix(String),
+}
+
+/// Dispatches requests to handlers by method and path.
+///
+/// Routes are tried in the order they were added; requests that match no
+/// route go to the fallback handler.
+pub struct Router {
+    routes: Vec<(Pattern, Box<dyn Handler>)>,
+    fallback: Box<dyn Handler>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            fallback: Box::new(|_: &Request| Response::new(404)),
+        }
+    }
+
+    /// Route `GET` requests for exactly `path` to `handler`.
+    pub fn get(self, path: &str, handler: impl Handler + 'static) -> Router {
+        self.route("GET", path, handler)
+    }
+
+    pub fn route(mut self, method: &str, path: &str, handler: impl Handler + 'static) -> Router {
+        self.routes.push((
+            Pattern::Exact(method.to_string(), path.to_string()),
+            Box::new(handler),
+        ));
+        self
+    }
+
+    /// Route every request whose path is `prefix` or lies below it.
+    pub fn prefix(mut self, prefix: &str, handler: impl Handler + 'static) -> Router {
+        self.routes
+            .push((Pattern::Prefix(prefix.to_string()), Box::new(handler)));
+        self
+    }
+
+    pub fn fallback(mut self, handler: impl Handler + 'static) -> Router {
+        self.fallback = Box::new(handler);
+        self
+    }
+
+    fn find(&self, request: &Request) -> Option<(&Pattern, &dyn Handler)> {
+        self.routes
+            .iter()
+            .find(|(pattern, _)| match pattern {
+                Pattern::Exact(method, path) => *method == request.method && *path == request.path,
+                Pattern::Prefix(prefix) => split_prefix(&request.path, prefix).is_some(),
+            })
+            .map(|(pattern, handler)| (pattern, handler.as_ref()))
+    }
+
+    /// The path of the route `request` would be dispatched to, or
+    /// `"fallback"` if no route matches.
+    ///
+    /// Unlike the request path this has a small, fixed set of values, which
+    /// makes it suitable as a metrics label.
+    pub fn route_name(&self, request: &Request) -> &str {
+        match self.find(request) {
+            Some((Pattern::Exact(_, path), _)) => path,
+            Some((Pattern::Prefix(prefix), _)) => prefix,
+            None => "fallback",
+        }
+    }
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, request: &Request) -> Response {
+        match self.find(request) {
+            Some((_, handler)) => handler.handle(request),
+            None => self.fallback.handle(request),
+        }
+    }
+}
+
+/// Return the part of `path` below `prefix`, if `path` lies below it.
+///
+/// `/cgi-bin` matches `/cgi-bin` and `/cgi-bin/x` but not `/cgi-binary`.
+pub fn split_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let prefix = prefix.trim_end_matches('/');
+    let rest = path.strip_prefix(prefix)?;
+
+    if rest.is_empty() || rest.starts_with('/') {
+        Some(rest)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    io::{self, prelude::*, BufReader},
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::http::{Handler, Request, Response};
+use crate::http2;
+
+/// How long a client has to send enough of its request to tell whether it
+/// speaks HTTP/2.
+const PREFACE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serve one client connection with `handler`.
+///
+/// Clients that open with the HTTP/2 preface get HTTP/2 (h2c with prior
+/// knowledge); everything else is treated as a single HTTP/1.1 request.
+pub fn handle_connection(stream: TcpStream, handler: &dyn Handler) {
+    let result = match is_http2(&stream) {
+        Ok(true) => serve_http2(stream, handler),
+        Ok(false) => serve_http1(stream, handler),
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = result {
+        eprintln!("Connection error: {}", e);
+    }
+}
+
+fn serve_http1(mut stream: TcpStream, handler: &dyn Handler) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let response = match Request::parse(&mut reader) {
+        Ok(mut request) => {
+            request.remote_addr = stream.peer_addr().ok();
+            handler.handle(&request)
+        }
+        Err(e) if e.kind() == io::ErrorKind::FileTooLarge => Response::new(413),
+        Err(_) => Response::new(400),
+    };
+
+    response.write_to(&mut stream)?;
+    stream.flush()
+}
+
+fn serve_http2(stream: TcpStream, handler: &dyn Handler) -> io::Result<()> {
+    let remote_addr = stream.peer_addr().ok();
+    let reader = BufReader::new(stream.try_clone()?);
+
+    http2::serve(reader, stream, handler, remote_addr)
+}
+
+/// Peek at the start of the stream to see whether it is the HTTP/2 preface,
+/// giving up if it has not arrived by `PREFACE_TIMEOUT`.
+fn is_http2(stream: &TcpStream) -> io::Result<bool> {
+    let deadline = Instant::now() + PREFACE_TIMEOUT;
+    let result = peek_preface(stream, deadline);
+    stream.set_read_timeout(None)?;
+    result
+}
+
+fn peek_preface(stream: &TcpStream, deadline: Instant) -> io::Result<bool> {
+    let mut buffer = [0; 24];
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "timed out waiting for the request",
+            ));
+        }
+        stream.set_read_timeout(Some(remaining))?;
+
+        let n = stream.peek(&mut buffer)?;
+
+        if n == 0 || !http2::PREFACE.starts_with(&buffer[..n]) {
+            return Ok(false);
+        }
+        if n == http2::PREFACE.len() {
+            return Ok(true);
+        }
+
+        // Only part of the preface has arrived so far.
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn gives_up_on_a_partial_preface() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        client.write_all(&http2::PREFACE[..5]).unwrap();
+
+        let started = Instant::now();
+        let deadline = started + Duration::from_millis(200);
+        let error = peek_preface(&server, deadline).unwrap_err();
+
+        assert!(matches!(
+            error.kind(),
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+        ));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,479 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::cookie::{Cookie, SameSite};
+use crate::crypto;
+use crate::http::{Handler, Request, Response};
+
+pub type SessionData = BTreeMap<String, String>;
+
+#[derive(Default)]
+struct SessionState {
+    /// The cookie value the session was loaded from.
+    key: Option<String>,
+    data: SessionData,
+    modified: bool,
+    destroyed: bool,
+    renew: bool,
+}
+
+/// The session of the client that sent a request.
+///
+/// Every request carries one. It starts out empty and is filled in by the
+/// `Sessions` middleware; handlers read and change it through `&Request`.
+/// Changes are saved to the session store once the handler returns.
+#[derive(Default)]
+pub struct Session {
+    state: Mutex<SessionState>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.state.lock().unwrap().data.get(key).cloned()
+    }
+
+    pub fn insert(&self, key: &str, value: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.data.insert(key.to_string(), value.to_string());
+        state.modified = true;
+    }
+
+    pub fn remove(&self, key: &str) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        let value = state.data.remove(key);
+        state.modified |= value.is_some();
+        value
+    }
+
+    /// End the session: its data is dropped from the store and the client
+    /// is told to delete the cookie.
+    pub fn destroy(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.data.clear();
+        state.destroyed = true;
+    }
+
+    /// Keep the data but move it to a new session key.
+    ///
+    /// Call this when the user logs in so that a key planted before the
+    /// login cannot be used to hijack the session ("session fixation").
+    pub fn renew(&self) {
+        self.state.lock().unwrap().renew = true;
+    }
+
+    fn restore(&self, key: String, data: SessionData) {
+        let mut state = self.state.lock().unwrap();
+        state.key = Some(key);
+        state.data = data;
+    }
+
+    fn take(&self) -> SessionState {
+        std::mem::take(&mut *self.state.lock().unwrap())
+    }
+}
+
+/// Where session data lives between requests.
+///
+/// The key is the value of the session cookie. What it holds depends on
+/// the store: an opaque id for `MemoryStore`, the signed data itself for
+/// `CookieStore`.
+pub trait SessionStore: Send + Sync {
+    /// Look up the data a key refers to, if it is valid and not expired.
+    fn load(&self, key: &str) -> Option<SessionData>;
+
+    /// Save `data`, returning the key to send to the client. `key` is the
+    /// current key for sessions that already exist.
+    fn save(&self, key: Option<&str>, data: &SessionData) -> io::Result<String>;
+
+    fn remove(&self, key: &str);
+}
+
+/// Keeps sessions in memory, keyed by random ids.
+///
+/// Sessions that have not been used for longer than the time-to-live are
+/// discarded. All sessions are lost when the server restarts.
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, (SessionData, Instant)>>,
+    ttl: Duration,
+}
+
+impl MemoryStore {
+    pub fn new(ttl: Duration) -> MemoryStore {
+        MemoryStore {
+            sessions: Mutex::new(HashMap::new()),
+            ttl,
+        }
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, key: &str) -> Option<SessionData> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let (data, last_used) = sessions.get_mut(key)?;
+
+        if last_used.elapsed() > self.ttl {
+            sessions.remove(key);
+            return None;
+        }
+
+        *last_used = Instant::now();
+        Some(data.clone())
+    }
+
+    fn save(&self, key: Option<&str>, data: &SessionData) -> io::Result<String> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, (_, last_used)| last_used.elapsed() <= self.ttl);
+
+        let key = match key {
+            Some(key) if sessions.contains_key(key) => key.to_string(),
+            _ => {
+                let mut id = [0; 16];
+                crypto::random_bytes(&mut id)?;
+                crypto::hex(&id)
+            }
+        };
+
+        sessions.insert(key.clone(), (data.clone(), Instant::now()));
+        Ok(key)
+    }
+
+    fn remove(&self, key: &str) {
+        self.sessions.lock().unwrap().remove(key);
+    }
+}
+
+/// Keeps the whole session in the cookie, signed with HMAC-SHA256.
+///
+/// Nothing is stored on the server, so sessions survive restarts and work
+/// across several servers sharing the secret. The client can read the data
+/// but cannot change it, and browsers limit cookies to about 4 KB. Since
+/// the server keeps no record, `destroy` cannot revoke copies of the cookie
+/// the client kept; they stay valid until they expire.
+pub struct CookieStore {
+    secret: Vec<u8>,
+    max_age: Duration,
+}
+
+impl CookieStore {
+    pub fn new(secret: &[u8], max_age: Duration) -> CookieStore {
+        CookieStore {
+            secret: secret.to_vec(),
+            max_age,
+        }
+    }
+
+    fn sign(&self, payload: &str) -> String {
+        crypto::base64_url_encode(&crypto::hmac_sha256(&self.secret, payload.as_bytes()))
+    }
+}
+
+impl SessionStore for CookieStore {
+    fn load(&self, key: &str) -> Option<SessionData> {
+        let (payload, signature) = key.split_once('.')?;
+
+        if !crypto::constant_time_eq(self.sign(payload).as_bytes(), signature.as_bytes()) {
+            return None;
+        }
+
+        let payload = String::from_utf8(crypto::base64_url_decode(payload)?).ok()?;
+        let (expires, data) = payload.split_once('|')?;
+
+        if expires.parse::<u64>().ok()? < unix_time() {
+            return None;
+        }
+
+        decode_data(data)
+    }
+
+    fn save(&self, _key: Option<&str>, data: &SessionData) -> io::Result<String> {
+        let expires = unix_time() + self.max_age.as_secs();
+        let payload =
+            crypto::base64_url_encode(format!("{}|{}", expires, encode_data(data)).as_bytes());
+        let signature = self.sign(&payload);
+
+        Ok(format!("{}.{}", payload, signature))
+    }
+
+    fn remove(&self, _key: &str) {}
+}
+
+/// Middleware that loads the session before calling the inner handler and
+/// saves it afterwards, setting the session cookie when needed.
+///
+/// The cookie is `HttpOnly` and `SameSite=Lax` with `Path=/` by default.
+pub struct Sessions<H> {
+    inner: H,
+    store: Box<dyn SessionStore>,
+    cookie: Cookie,
+}
+
+impl<H: Handler> Sessions<H> {
+    pub fn new(inner: H, store: impl SessionStore + 'static) -> Sessions<H> {
+        Sessions {
+            inner,
+            store: Box::new(store),
+            cookie: Cookie::new("session", "")
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Lax),
+        }
+    }
+
+    pub fn cookie_name(mut self, name: &str) -> Sessions<H> {
+        self.cookie.name = name.to_string();
+        self
+    }
+
+    /// Only send the cookie over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Sessions<H> {
+        self.cookie.secure = secure;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Sessions<H> {
+        self.cookie.same_site = Some(same_site);
+        self
+    }
+
+    /// Give the cookie a `Max-Age`; without one it lasts until the browser
+    /// is closed.
+    pub fn max_age(mut self, max_age: Duration) -> Sessions<H> {
+        self.cookie.max_age = Some(max_age);
+        self
+    }
+}
+
+impl<H: Handler> Handler for Sessions<H> {
+    fn handle(&self, request: &Request) -> Response {
+        let incoming = request.cookie(&self.cookie.name);
+
+        if let Some(key) = &incoming {
+            if let Some(data) = self.store.load(key) {
+                request.session.restore(key.clone(), data);
+            }
+        }
+
+        let response = self.inner.handle(request);
+        let state = request.session.take();
+
+        if state.destroyed {
+            if let Some(key) = &state.key {
+                self.store.remove(key);
+            }
+
+            if incoming.is_some() {
+                let mut removal = self.cookie.clone();
+                removal.value = String::new();
+                removal.max_age = Some(Duration::ZERO);
+                return response.with_cookie(&removal);
+            }
+        } else if state.modified || state.renew {
+            let key = if state.renew {
+                if let Some(old) = &state.key {
+                    self.store.remove(old);
+                }
+                None
+            } else {
+                state.key.as_deref()
+            };
+
+            let mut cookie = self.cookie.clone();
+            cookie.value = match self.store.save(key, &state.data) {
+                Ok(key) => key,
+                Err(e) => {
+                    eprintln!("Sessions: failed to save the session: {}", e);
+                    return Response::new(500);
+                }
+            };
+            return response.with_cookie(&cookie);
+        }
+
+        response
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn encode_data(data: &SessionData) -> String {
+    data.iter()
+        .map(|(key, value)| format!("{}={}", percent_encode(key), percent_encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn decode_data(text: &str) -> Option<SessionData> {
+    text.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=')?;
+            Some((percent_decode(key)?, percent_decode(value)?))
+        })
+        .collect()
+}
+
+fn percent_encode(text: &str) -> String {
+    let mut out = String::new();
+    for b in text.bytes() {
+        if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+fn percent_decode(text: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut input = text.bytes();
+
+    while let Some(b) = input.next() {
+        if b == b'%' {
+            let hex = [input.next()?, input.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counter(request: &Request) -> Response {
+        if request.path == "/logout" {
+            request.session.destroy();
+            return Response::new(200);
+        }
+
+        let visits: u32 = request
+            .session
+            .get("visits")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0)
+            + 1;
+        request.session.insert("visits", &visits.to_string());
+
+        Response::new(200).with_body(visits.to_string())
+    }
+
+    fn request(path: &str, cookie: Option<&str>) -> Request {
+        let mut request = Request::new("GET", path);
+        if let Some(cookie) = cookie {
+            request
+                .headers
+                .push(("Cookie".to_string(), cookie.to_string()));
+        }
+        request
+    }
+
+    /// Send a request and return the body and the `name=value` part of any
+    /// session cookie that was set.
+    fn send(handler: &dyn Handler, path: &str, cookie: Option<&str>) -> (String, Option<String>) {
+        let response = handler.handle(&request(path, cookie));
+        let set_cookie = response
+            .header("Set-Cookie")
+            .map(|value| value.split(';').next().unwrap().to_string());
+
+        (String::from_utf8(response.body).unwrap(), set_cookie)
+    }
+
+    #[test]
+    fn memory_sessions_persist_across_requests() {
+        let handler = Sessions::new(counter, MemoryStore::new(Duration::from_secs(60)));
+
+        let (body, cookie) = send(&handler, "/", None);
+        assert_eq!("1", body);
+        let cookie = cookie.unwrap();
+
+        let (body, _) = send(&handler, "/", Some(&cookie));
+        assert_eq!("2", body);
+
+        let (_, removal) = send(&handler, "/logout", Some(&cookie));
+        assert_eq!(Some("session=".to_string()), removal);
+
+        let (body, _) = send(&handler, "/", Some(&cookie));
+        assert_eq!("1", body);
+    }
+
+    #[test]
+    fn cookie_sessions_reject_tampering() {
+        let store = CookieStore::new(b"secret", Duration::from_secs(60));
+        let mut data = SessionData::new();
+        data.insert("user".to_string(), "ferris & co=1".to_string());
+
+        let key = store.save(None, &data).unwrap();
+        assert_eq!(Some(data), store.load(&key));
+
+        let (payload, signature) = key.split_once('.').unwrap();
+        let forged = crypto::base64_url_encode(b"99999999999|user=admin");
+        assert_eq!(None, store.load(&format!("{}.{}", forged, signature)));
+        assert_eq!(None, store.load(payload));
+
+        let other = CookieStore::new(b"other secret", Duration::from_secs(60));
+        assert_eq!(None, other.load(&key));
+    }
+
+    #[test]
+    fn cookie_sessions_expire() {
+        let store = CookieStore::new(b"secret", Duration::ZERO);
+        let payload = crypto::base64_url_encode(b"1|user=ferris");
+        let key = format!("{}.{}", payload, store.sign(&payload));
+
+        assert_eq!(None, store.load(&key));
+    }
+
+    #[test]
+    fn renew_issues_a_new_key() {
+        let handler = Sessions::new(
+            |request: &Request| {
+                request.session.insert("user", "ferris");
+                request.session.renew();
+                Response::new(200)
+            },
+            MemoryStore::new(Duration::from_secs(60)),
+        );
+
+        let (_, first) = send(&handler, "/", None);
+        let (_, second) = send(&handler, "/", first.as_deref());
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn failing_to_save_is_an_error() {
+        struct Unavailable;
+
+        impl SessionStore for Unavailable {
+            fn load(&self, _key: &str) -> Option<SessionData> {
+                None
+            }
+
+            fn save(&self, _key: Option<&str>, _data: &SessionData) -> io::Result<String> {
+                Err(io::Error::new(io::ErrorKind::NotFound, "no /dev/urandom"))
+            }
+
+            fn remove(&self, _key: &str) {}
+        }
+
+        let handler = Sessions::new(counter, Unavailable);
+        let response = handler.handle(&request("/", None));
+        assert_eq!(500, response.status);
+        assert_eq!(None, response.header("Set-Cookie"));
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A file in the temp directory that is deleted again when dropped.
+pub struct TempFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl TempFile {
+    pub fn new() -> io::Result<TempFile> {
+        TempFile::new_in(env::temp_dir())
+    }
+
+    pub fn new_in(dir: impl AsRef<Path>) -> io::Result<TempFile> {
+        loop {
+            let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let path = dir
+                .as_ref()
+                .join(format!("webapp-{}-{}.tmp", process::id(), id));
+
+            match OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => return Ok(TempFile { path, file }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn file(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    /// Open a fresh handle for reading the file from the start.
+    pub fn reopen(&self) -> io::Result<File> {
+        File::open(&self.path)
+    }
+
+    /// Move the contents to `dest`, which must already exist.
+    ///
+    /// Renaming is tried first; if `dest` is on another file system the
+    /// contents are copied instead.
+    pub fn persist(self, dest: &Path) -> io::Result<()> {
+        if fs::rename(&self.path, dest).is_ok() {
+            return Ok(());
+        }
+
+        fs::copy(&self.path, dest)?;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::http::{Handler, Request, Response};
+use crate::multipart::{self, Limits, Multipart, MultipartError, Part, PartData};
+
+/// Stores the files of a `multipart/form-data` POST under a directory.
+///
+/// Client-supplied filenames are reduced to a safe basename and never
+/// overwrite existing files; a numeric suffix is added instead. If any part
+/// of the upload fails, the files stored so far are removed again.
+pub struct UploadHandler {
+    dir: PathBuf,
+    limits: Limits,
+}
+
+impl UploadHandler {
+    pub fn new(dir: impl Into<PathBuf>) -> UploadHandler {
+        UploadHandler {
+            dir: dir.into(),
+            limits: Limits::default(),
+        }
+    }
+
+    pub fn limits(mut self, limits: Limits) -> UploadHandler {
+        self.limits = limits;
+        self
+    }
+
+    fn receive(&self, request: &Request, stored: &mut Vec<PathBuf>) -> Result<(), MultipartError> {
+        let boundary = request
+            .header("Content-Type")
+            .and_then(multipart::boundary)
+            .ok_or(MultipartError::Malformed(
+                "not a multipart/form-data request",
+            ))?;
+
+        let mut parts = Multipart::new(request.body.reader()?, &boundary, self.limits.clone());
+
+        while let Some(part) = parts.next_part()? {
+            match &part.filename {
+                Some(filename) if !filename.is_empty() => {
+                    let name = safe_filename(filename);
+                    stored.push(self.store(&name, part)?);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn store(&self, name: &str, part: Part) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        let (path, mut file) = create_unique(&self.dir, name)?;
+
+        let result = match part.data {
+            PartData::Memory(bytes) => io::Write::write_all(&mut file, &bytes),
+            PartData::File(temp) => {
+                drop(file);
+                temp.persist(&path)
+            }
+        };
+
+        match result {
+            Ok(()) => Ok(path),
+            Err(e) => {
+                let _ = fs::remove_file(&path);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Handler for UploadHandler {
+    fn handle(&self, request: &Request) -> Response {
+        if request.method != "POST" {
+            return Response::new(405).with_header("Allow", "POST");
+        }
+
+        let mut stored = Vec::new();
+
+        match self.receive(request, &mut stored) {
+            Ok(()) => {
+                let mut body = String::new();
+                for path in &stored {
+                    let name = path.file_name().unwrap().to_string_lossy();
+                    body.push_str(&format!("stored {}\n", name));
+                }
+
+                Response::new(201)
+                    .with_header("Content-Type", "text/plain; charset=utf-8")
+                    .with_body(body)
+            }
+            Err(e) => {
+                for path in &stored {
+                    let _ = fs::remove_file(path);
+                }
+
+                let status = match e {
+                    MultipartError::Io(_) => 500,
+                    MultipartError::Malformed(_) => 400,
+                    MultipartError::PartTooLarge
+                    | MultipartError::BodyTooLarge
+                    | MultipartError::TooManyParts => 413,
+                };
+
+                Response::new(status)
+                    .with_header("Content-Type", "text/plain; charset=utf-8")
+                    .with_body(format!("upload failed: {}\n", e))
+            }
+        }
+    }
+}
+
+/// Reduce a client-supplied filename to something safe to create.
+///
+/// Directory components are stripped, anything but ASCII letters, digits,
+/// `.`, `-` and `_` is replaced with `_`, and leading dots are removed so
+/// the result can be neither hidden nor `..`.
+pub fn safe_filename(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or("");
+
+    let cleaned: String = base
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let cleaned: String = cleaned.trim_start_matches('.').chars().take(200).collect();
+
+    if cleaned.is_empty() {
+        "upload".to_string()
+    } else {
+        cleaned
+    }
+}
+
+/// Create a new file named `name` in `dir`, adding `-1`, `-2`, ... before
+/// the extension until the name is free.
+fn create_unique(dir: &Path, name: &str) -> io::Result<(PathBuf, File)> {
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+        _ => (name, String::new()),
+    };
+
+    for n in 0.. {
+        let candidate = if n == 0 {
+            name.to_string()
+        } else {
+            format!("{}-{}{}", stem, n, extension)
+        };
+        let path = dir.join(candidate);
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Body;
+    use crate::temp::TempFile;
+
+    #[test]
+    fn sanitizes_filenames() {
+        assert_eq!("passwd", safe_filename("../../etc/passwd"));
+        assert_eq!("evil.exe", safe_filename("C:\\temp\\evil.exe"));
+        assert_eq!("my_report__1_.pdf", safe_filename("my report (1).pdf"));
+        assert_eq!("bashrc", safe_filename(".bashrc"));
+        assert_eq!("upload", safe_filename(".."));
+    }
+
+    #[test]
+    fn stores_uploaded_files_without_overwriting() {
+        let dir = TempFile::new().unwrap().path().with_extension("d");
+        let handler = UploadHandler::new(&dir);
+
+        let body = "--b\r\n\
+Content-Disposition: form-data; name=\"a\"; filename=\"../hi.txt\"\r\n\
+\r\n\
+one\r\n\
+--b\r\n\
+Content-Disposition: form-data; name=\"b\"; filename=\"hi.txt\"\r\n\
+\r\n\
+two\r\n\
+--b--\r\n";
+        let mut request = Request::new("POST", "/upload");
+        request.headers = vec![(
+            "Content-Type".to_string(),
+            "multipart/form-data; boundary=b".to_string(),
+        )];
+        request.body = Body::from(body.as_bytes().to_vec());
+
+        let response = handler.handle(&request);
+
+        assert_eq!(201, response.status);
+        assert_eq!("one", fs::read_to_string(dir.join("hi.txt")).unwrap());
+        assert_eq!("two", fs::read_to_string(dir.join("hi-1.txt")).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}